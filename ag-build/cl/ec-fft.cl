@@ -1,11 +1,11 @@
 /*
  * FFT algorithm for G1 is inspired from: http://www.bealto.com/gpu-fft_group-1.html
  */
-KERNEL void POINT_radix_fft(GLOBAL POINT_jacobian* x, // Source buffer
-                      GLOBAL POINT_jacobian* y, // Destination buffer
-                      GLOBAL SCALAR* pq, // Precalculated twiddle factors
-                      GLOBAL SCALAR* omegas, // [omega, omega^2, omega^4, ...]
-                      LOCAL POINT_jacobian* u_arg, // Local buffer to store intermediary values
+KERNEL void ${POINT}_radix_fft(GLOBAL ${POINT}_jacobian* x, // Source buffer
+                      GLOBAL ${POINT}_jacobian* y, // Destination buffer
+                      GLOBAL ${SCALAR}* pq, // Precalculated twiddle factors
+                      GLOBAL ${SCALAR}* omegas, // [omega, omega^2, omega^4, ...]
+                      LOCAL ${POINT}_jacobian* u_arg, // Local buffer to store intermediary values
                       uint n, // Number of elements
                       uint lgp, // Log2 of `p` (Read more in the link above)
                       uint deg, // 1=>radix2, 2=>radix4, 3=>radix8, ...
@@ -16,9 +16,9 @@ KERNEL void POINT_radix_fft(GLOBAL POINT_jacobian* x, // Source buffer
 // ignore that argument and use the globally defined extern memory instead.
 #ifdef CUDA
   // There can only be a single dynamic shared memory item, hence cast it to the type we need.
-  POINT_jacobian* u = (POINT_jacobian*)cuda_shared;
+  ${POINT}_jacobian* u = (${POINT}_jacobian*)cuda_shared;
 #else
-  LOCAL POINT_jacobian* u = u_arg;
+  LOCAL ${POINT}_jacobian* u = u_arg;
 #endif
 
   uint lid = GET_LOCAL_ID();
@@ -44,11 +44,11 @@ KERNEL void POINT_radix_fft(GLOBAL POINT_jacobian* x, // Source buffer
   uint counte = counts + count / lsize;
 
   // Compute powers of twiddle
-  const SCALAR twiddle = SCALAR_pow_lookup(omegas, (n >> lgp >> deg) * k);
-  SCALAR tmp = SCALAR_pow(twiddle, counts);
+  const ${SCALAR} twiddle = ${SCALAR}_pow_lookup(omegas, (n >> lgp >> deg) * k);
+  ${SCALAR} tmp = ${SCALAR}_pow(twiddle, counts);
   for(uint i = counts; i < counte; i++) {
-    u[i] = POINT_mul(x[i*t], tmp);
-    tmp = SCALAR_mul(tmp, twiddle);
+    u[i] = ${POINT}_mul(x[i*t], tmp);
+    tmp = ${SCALAR}_mul(tmp, twiddle);
   }
   BARRIER_LOCAL();
 
@@ -59,11 +59,11 @@ KERNEL void POINT_radix_fft(GLOBAL POINT_jacobian* x, // Source buffer
       const uint di = i & (bit - 1);
       const uint i0 = (i << 1) - di;
       const uint i1 = i0 + bit;
-      POINT_jacobian tmp_point = u[i0];
-      u[i0] = POINT_add(u[i0], u[i1]);
-      u[i1] = POINT_sub(tmp_point, u[i1]);
-      SCALAR pq_pow = SCALAR_pow(pq[0], di << rnd << pqshift);
-      u[i1] = POINT_mul(u[i1], pq_pow);
+      ${POINT}_jacobian tmp_point = u[i0];
+      u[i0] = ${POINT}_add(u[i0], u[i1]);
+      u[i1] = ${POINT}_sub(tmp_point, u[i1]);
+      ${SCALAR} pq_pow = ${SCALAR}_pow(pq[0], di << rnd << pqshift);
+      u[i1] = ${POINT}_mul(u[i1], pq_pow);
     }
 
     BARRIER_LOCAL();
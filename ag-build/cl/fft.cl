@@ -1,11 +1,11 @@
 /*
  * FFT algorithm is inspired from: http://www.bealto.com/gpu-fft_group-1.html
  */
-KERNEL void FIELD_radix_fft(GLOBAL FIELD* x, // Source buffer
-                      GLOBAL FIELD* y, // Destination buffer
-                      GLOBAL FIELD* pq, // Precalculated twiddle factors
-                      GLOBAL FIELD* omegas, // [omega, omega^2, omega^4, ...]
-                      LOCAL FIELD* u_arg, // Local buffer to store intermediary values
+KERNEL void ${FIELD}_radix_fft(GLOBAL ${FIELD}* x, // Source buffer
+                      GLOBAL ${FIELD}* y, // Destination buffer
+                      GLOBAL ${FIELD}* pq, // Precalculated twiddle factors
+                      GLOBAL ${FIELD}* omegas, // [omega, omega^2, omega^4, ...]
+                      LOCAL ${FIELD}* u_arg, // Local buffer to store intermediary values
                       uint n, // Number of elements
                       uint lgp, // Log2 of `p` (Read more in the link above)
                       uint deg, // 1=>radix2, 2=>radix4, 3=>radix8, ...
@@ -15,9 +15,9 @@ KERNEL void FIELD_radix_fft(GLOBAL FIELD* x, // Source buffer
 // ignore that argument and use the globally defined extern memory instead.
 #ifdef CUDA
   // There can only be a single dynamic shared memory item, hence cast it to the type we need.
-  FIELD* u = (FIELD*)cuda_shared;
+  ${FIELD}* u = (${FIELD}*)cuda_shared;
 #else
-  LOCAL FIELD* u = u_arg;
+  LOCAL ${FIELD}* u = u_arg;
 #endif
 
   uint lid = GET_LOCAL_ID();
@@ -37,11 +37,11 @@ KERNEL void FIELD_radix_fft(GLOBAL FIELD* x, // Source buffer
   uint counte = counts + count / lsize;
 
   // Compute powers of twiddle
-  const FIELD twiddle = FIELD_pow_lookup(omegas, (n >> lgp >> deg) * k);
-  FIELD tmp = FIELD_pow(twiddle, counts);
+  const ${FIELD} twiddle = ${FIELD}_pow_lookup(omegas, (n >> lgp >> deg) * k);
+  ${FIELD} tmp = ${FIELD}_pow(twiddle, counts);
   for(uint i = counts; i < counte; i++) {
-    u[i] = FIELD_mul(tmp, x[i*t]);
-    tmp = FIELD_mul(tmp, twiddle);
+    u[i] = ${FIELD}_mul(tmp, x[i*t]);
+    tmp = ${FIELD}_mul(tmp, twiddle);
   }
   BARRIER_LOCAL();
 
@@ -53,9 +53,9 @@ KERNEL void FIELD_radix_fft(GLOBAL FIELD* x, // Source buffer
       const uint i0 = (i << 1) - di;
       const uint i1 = i0 + bit;
       tmp = u[i0];
-      u[i0] = FIELD_add(u[i0], u[i1]);
-      u[i1] = FIELD_sub(tmp, u[i1]);
-      if(di != 0) u[i1] = FIELD_mul(pq[di << rnd << pqshift], u[i1]);
+      u[i0] = ${FIELD}_add(u[i0], u[i1]);
+      u[i1] = ${FIELD}_sub(tmp, u[i1]);
+      if(di != 0) u[i1] = ${FIELD}_mul(pq[di << rnd << pqshift], u[i1]);
     }
 
     BARRIER_LOCAL();
@@ -68,9 +68,9 @@ KERNEL void FIELD_radix_fft(GLOBAL FIELD* x, // Source buffer
 }
 
 /// Multiplies all of the elements by `field`
-KERNEL void FIELD_mul_by_field(GLOBAL FIELD* elements,
+KERNEL void ${FIELD}_mul_by_field(GLOBAL ${FIELD}* elements,
                         uint n,
-                        FIELD field) {
+                        ${FIELD} field) {
   const uint gid = GET_GLOBAL_ID();
-  elements[gid] = FIELD_mul(elements[gid], field);
+  elements[gid] = ${FIELD}_mul(elements[gid], field);
 }
@@ -1,18 +1,18 @@
 // FinalityLabs - 2019
 // Arbitrary size prime-field arithmetic library (add, sub, mul, pow)
 
-#define FIELD_BITS (FIELD_LIMBS * FIELD_LIMB_BITS)
-#if FIELD_LIMB_BITS == 32
-  #define FIELD_mac_with_carry mac_with_carry_32
-  #define FIELD_add_with_carry add_with_carry_32
-#elif FIELD_LIMB_BITS == 64
-  #define FIELD_mac_with_carry mac_with_carry_64
-  #define FIELD_add_with_carry add_with_carry_64
+#define ${FIELD}_BITS (${FIELD}_LIMBS * ${FIELD}_LIMB_BITS)
+#if ${FIELD}_LIMB_BITS == 32
+  #define ${FIELD}_mac_with_carry mac_with_carry_32
+  #define ${FIELD}_add_with_carry add_with_carry_32
+#elif ${FIELD}_LIMB_BITS == 64
+  #define ${FIELD}_mac_with_carry mac_with_carry_64
+  #define ${FIELD}_add_with_carry add_with_carry_64
 #endif
 
 // Greater than or equal
-DEVICE bool FIELD_gte(FIELD a, FIELD b) {
-  for(char i = FIELD_LIMBS - 1; i >= 0; i--){
+DEVICE bool ${FIELD}_gte(${FIELD} a, ${FIELD} b) {
+  for(char i = ${FIELD}_LIMBS - 1; i >= 0; i--){
     if(a.val[i] > b.val[i])
       return true;
     if(a.val[i] < b.val[i])
@@ -22,8 +22,8 @@ DEVICE bool FIELD_gte(FIELD a, FIELD b) {
 }
 
 // Equals
-DEVICE bool FIELD_eq(FIELD a, FIELD b) {
-  for(uchar i = 0; i < FIELD_LIMBS; i++)
+DEVICE bool ${FIELD}_eq(${FIELD} a, ${FIELD} b) {
+  for(uchar i = 0; i < ${FIELD}_LIMBS; i++)
     if(a.val[i] != b.val[i])
       return false;
   return true;
@@ -31,22 +31,22 @@ DEVICE bool FIELD_eq(FIELD a, FIELD b) {
 
 // Normal addition
 #if defined(OPENCL_NVIDIA) || defined(CUDA)
-  #define FIELD_add_ FIELD_add_nvidia
-  #define FIELD_sub_ FIELD_sub_nvidia
+  #define ${FIELD}_add_ ${FIELD}_add_nvidia
+  #define ${FIELD}_sub_ ${FIELD}_sub_nvidia
 #else
-  DEVICE FIELD FIELD_add_(FIELD a, FIELD b) {
+  DEVICE ${FIELD} ${FIELD}_add_(${FIELD} a, ${FIELD} b) {
     bool carry = 0;
-    for(uchar i = 0; i < FIELD_LIMBS; i++) {
-      FIELD_limb old = a.val[i];
+    for(uchar i = 0; i < ${FIELD}_LIMBS; i++) {
+      ${FIELD}_limb old = a.val[i];
       a.val[i] += b.val[i] + carry;
       carry = carry ? old >= a.val[i] : old > a.val[i];
     }
     return a;
   }
-  FIELD FIELD_sub_(FIELD a, FIELD b) {
+  ${FIELD} ${FIELD}_sub_(${FIELD} a, ${FIELD} b) {
     bool borrow = 0;
-    for(uchar i = 0; i < FIELD_LIMBS; i++) {
-      FIELD_limb old = a.val[i];
+    for(uchar i = 0; i < ${FIELD}_LIMBS; i++) {
+      ${FIELD}_limb old = a.val[i];
       a.val[i] -= b.val[i] + borrow;
       borrow = borrow ? old <= a.val[i] : old < a.val[i];
     }
@@ -55,16 +55,16 @@ DEVICE bool FIELD_eq(FIELD a, FIELD b) {
 #endif
 
 // Modular subtraction
-DEVICE FIELD FIELD_sub(FIELD a, FIELD b) {
-  FIELD res = FIELD_sub_(a, b);
-  if(!FIELD_gte(a, b)) res = FIELD_add_(res, FIELD_P);
+DEVICE ${FIELD} ${FIELD}_sub(${FIELD} a, ${FIELD} b) {
+  ${FIELD} res = ${FIELD}_sub_(a, b);
+  if(!${FIELD}_gte(a, b)) res = ${FIELD}_add_(res, ${FIELD}_P);
   return res;
 }
 
 // Modular addition
-DEVICE FIELD FIELD_add(FIELD a, FIELD b) {
-  FIELD res = FIELD_add_(a, b);
-  if(FIELD_gte(res, FIELD_P)) res = FIELD_sub_(res, FIELD_P);
+DEVICE ${FIELD} ${FIELD}_add(${FIELD} a, ${FIELD} b) {
+  ${FIELD} res = ${FIELD}_add_(a, b);
+  if(${FIELD}_gte(res, ${FIELD}_P)) res = ${FIELD}_sub_(res, ${FIELD}_P);
   return res;
 }
 
@@ -82,11 +82,11 @@ DEVICE FIELD FIELD_add(FIELD a, FIELD b) {
 //     arithmetic on the GPU
 //     https://ieeexplore.ieee.org/document/8464792
 
-DEVICE void FIELD_reduce(uint32_t accLow[FIELD_LIMBS], uint32_t np0, uint32_t fq[FIELD_LIMBS]) {
+DEVICE void ${FIELD}_reduce(uint32_t accLow[${FIELD}_LIMBS], uint32_t np0, uint32_t fq[${FIELD}_LIMBS]) {
   // accLow is an IN and OUT vector
   // count must be even
-  const uint32_t count = FIELD_LIMBS;
-  uint32_t accHigh[FIELD_LIMBS];
+  const uint32_t count = ${FIELD}_LIMBS;
+  uint32_t accHigh[${FIELD}_LIMBS];
   uint32_t bucket=0, lowCarry=0, highCarry=0, q;
   int32_t  i, j;
 
@@ -166,11 +166,11 @@ DEVICE void FIELD_reduce(uint32_t accLow[FIELD_LIMBS], uint32_t np0, uint32_t fq
 
 // Requirement: yLimbs >= xLimbs
 DEVICE inline
-void FIELD_mult_v1(uint32_t *x, uint32_t *y, uint32_t *xy) {
-  const uint32_t xLimbs  = FIELD_LIMBS;
-  const uint32_t yLimbs  = FIELD_LIMBS;
-  const uint32_t xyLimbs = FIELD_LIMBS * 2;
-  uint32_t temp[FIELD_LIMBS * 2];
+void ${FIELD}_mult_v1(uint32_t *x, uint32_t *y, uint32_t *xy) {
+  const uint32_t xLimbs  = ${FIELD}_LIMBS;
+  const uint32_t yLimbs  = ${FIELD}_LIMBS;
+  const uint32_t xyLimbs = ${FIELD}_LIMBS * 2;
+  uint32_t temp[${FIELD}_LIMBS * 2];
   uint32_t carry = 0;
 
   #pragma unroll
@@ -228,35 +228,35 @@ void FIELD_mult_v1(uint32_t *x, uint32_t *y, uint32_t *xy) {
   }
 }
 
-DEVICE FIELD FIELD_mul_nvidia(FIELD a, FIELD b) {
+DEVICE ${FIELD} ${FIELD}_mul_nvidia(${FIELD} a, ${FIELD} b) {
   // Perform full multiply
-  limb ab[2 * FIELD_LIMBS];
-  FIELD_mult_v1(a.val, b.val, ab);
+  limb ab[2 * ${FIELD}_LIMBS];
+  ${FIELD}_mult_v1(a.val, b.val, ab);
 
-  uint32_t io[FIELD_LIMBS];
+  uint32_t io[${FIELD}_LIMBS];
   #pragma unroll
-  for(int i=0;i<FIELD_LIMBS;i++) {
+  for(int i=0;i<${FIELD}_LIMBS;i++) {
     io[i]=ab[i];
   }
-  FIELD_reduce(io, FIELD_INV, FIELD_P.val);
+  ${FIELD}_reduce(io, ${FIELD}_INV, ${FIELD}_P.val);
 
   // Add io to the upper words of ab
-  ab[FIELD_LIMBS] = add_cc(ab[FIELD_LIMBS], io[0]);
+  ab[${FIELD}_LIMBS] = add_cc(ab[${FIELD}_LIMBS], io[0]);
   int j;
   #pragma unroll
-  for (j = 1; j < FIELD_LIMBS - 1; j++) {
-    ab[j + FIELD_LIMBS] = addc_cc(ab[j + FIELD_LIMBS], io[j]);
+  for (j = 1; j < ${FIELD}_LIMBS - 1; j++) {
+    ab[j + ${FIELD}_LIMBS] = addc_cc(ab[j + ${FIELD}_LIMBS], io[j]);
   }
-  ab[2 * FIELD_LIMBS - 1] = addc(ab[2 * FIELD_LIMBS - 1], io[FIELD_LIMBS - 1]);
+  ab[2 * ${FIELD}_LIMBS - 1] = addc(ab[2 * ${FIELD}_LIMBS - 1], io[${FIELD}_LIMBS - 1]);
 
-  FIELD r;
+  ${FIELD} r;
   #pragma unroll
-  for (int i = 0; i < FIELD_LIMBS; i++) {
-    r.val[i] = ab[i + FIELD_LIMBS];
+  for (int i = 0; i < ${FIELD}_LIMBS; i++) {
+    r.val[i] = ab[i + ${FIELD}_LIMBS];
   }
 
-  if (FIELD_gte(r, FIELD_P)) {
-    r = FIELD_sub_(r, FIELD_P);
+  if (${FIELD}_gte(r, ${FIELD}_P)) {
+    r = ${FIELD}_sub_(r, ${FIELD}_P);
   }
 
   return r;
@@ -265,86 +265,86 @@ DEVICE FIELD FIELD_mul_nvidia(FIELD a, FIELD b) {
 #endif
 
 // Modular multiplication
-DEVICE FIELD FIELD_mul_default(FIELD a, FIELD b) {
+DEVICE ${FIELD} ${FIELD}_mul_default(${FIELD} a, ${FIELD} b) {
   /* CIOS Montgomery multiplication, inspired from Tolga Acar's thesis:
    * https://www.microsoft.com/en-us/research/wp-content/uploads/1998/06/97Acar.pdf
    * Learn more:
    * https://en.wikipedia.org/wiki/Montgomery_modular_multiplication
    * https://alicebob.cryptoland.net/understanding-the-montgomery-reduction-algorithm/
    */
-  FIELD_limb t[FIELD_LIMBS + 2] = {0};
-  for(uchar i = 0; i < FIELD_LIMBS; i++) {
-    FIELD_limb carry = 0;
-    for(uchar j = 0; j < FIELD_LIMBS; j++)
-      t[j] = FIELD_mac_with_carry(a.val[j], b.val[i], t[j], &carry);
-    t[FIELD_LIMBS] = FIELD_add_with_carry(t[FIELD_LIMBS], &carry);
-    t[FIELD_LIMBS + 1] = carry;
+  ${FIELD}_limb t[${FIELD}_LIMBS + 2] = {0};
+  for(uchar i = 0; i < ${FIELD}_LIMBS; i++) {
+    ${FIELD}_limb carry = 0;
+    for(uchar j = 0; j < ${FIELD}_LIMBS; j++)
+      t[j] = ${FIELD}_mac_with_carry(a.val[j], b.val[i], t[j], &carry);
+    t[${FIELD}_LIMBS] = ${FIELD}_add_with_carry(t[${FIELD}_LIMBS], &carry);
+    t[${FIELD}_LIMBS + 1] = carry;
 
     carry = 0;
-    FIELD_limb m = FIELD_INV * t[0];
-    FIELD_mac_with_carry(m, FIELD_P.val[0], t[0], &carry);
-    for(uchar j = 1; j < FIELD_LIMBS; j++)
-      t[j - 1] = FIELD_mac_with_carry(m, FIELD_P.val[j], t[j], &carry);
+    ${FIELD}_limb m = ${FIELD}_INV * t[0];
+    ${FIELD}_mac_with_carry(m, ${FIELD}_P.val[0], t[0], &carry);
+    for(uchar j = 1; j < ${FIELD}_LIMBS; j++)
+      t[j - 1] = ${FIELD}_mac_with_carry(m, ${FIELD}_P.val[j], t[j], &carry);
 
-    t[FIELD_LIMBS - 1] = FIELD_add_with_carry(t[FIELD_LIMBS], &carry);
-    t[FIELD_LIMBS] = t[FIELD_LIMBS + 1] + carry;
+    t[${FIELD}_LIMBS - 1] = ${FIELD}_add_with_carry(t[${FIELD}_LIMBS], &carry);
+    t[${FIELD}_LIMBS] = t[${FIELD}_LIMBS + 1] + carry;
   }
 
-  FIELD result;
-  for(uchar i = 0; i < FIELD_LIMBS; i++) result.val[i] = t[i];
+  ${FIELD} result;
+  for(uchar i = 0; i < ${FIELD}_LIMBS; i++) result.val[i] = t[i];
 
-  if(FIELD_gte(result, FIELD_P)) result = FIELD_sub_(result, FIELD_P);
+  if(${FIELD}_gte(result, ${FIELD}_P)) result = ${FIELD}_sub_(result, ${FIELD}_P);
 
   return result;
 }
 
 #ifdef CUDA
-DEVICE FIELD FIELD_mul(FIELD a, FIELD b) {
-  return FIELD_mul_nvidia(a, b);
+DEVICE ${FIELD} ${FIELD}_mul(${FIELD} a, ${FIELD} b) {
+  return ${FIELD}_mul_nvidia(a, b);
 }
 #else
-DEVICE FIELD FIELD_mul(FIELD a, FIELD b) {
-  return FIELD_mul_default(a, b);
+DEVICE ${FIELD} ${FIELD}_mul(${FIELD} a, ${FIELD} b) {
+  return ${FIELD}_mul_default(a, b);
 }
 #endif
 
 // Squaring is a special case of multiplication which can be done ~1.5x faster.
 // https://stackoverflow.com/a/16388571/1348497
-DEVICE FIELD FIELD_sqr(FIELD a) {
-  return FIELD_mul(a, a);
+DEVICE ${FIELD} ${FIELD}_sqr(${FIELD} a) {
+  return ${FIELD}_mul(a, a);
 }
 
 // Left-shift the limbs by one bit and subtract by modulus in case of overflow.
-// Faster version of FIELD_add(a, a)
-DEVICE FIELD FIELD_double(FIELD a) {
-  for(uchar i = FIELD_LIMBS - 1; i >= 1; i--)
-    a.val[i] = (a.val[i] << 1) | (a.val[i - 1] >> (FIELD_LIMB_BITS - 1));
+// Faster version of ${FIELD}_add(a, a)
+DEVICE ${FIELD} ${FIELD}_double(${FIELD} a) {
+  for(uchar i = ${FIELD}_LIMBS - 1; i >= 1; i--)
+    a.val[i] = (a.val[i] << 1) | (a.val[i - 1] >> (${FIELD}_LIMB_BITS - 1));
   a.val[0] <<= 1;
-  if(FIELD_gte(a, FIELD_P)) a = FIELD_sub_(a, FIELD_P);
+  if(${FIELD}_gte(a, ${FIELD}_P)) a = ${FIELD}_sub_(a, ${FIELD}_P);
   return a;
 }
 
 // Modular exponentiation (Exponentiation by Squaring)
 // https://en.wikipedia.org/wiki/Exponentiation_by_squaring
-DEVICE FIELD FIELD_pow(FIELD base, uint exponent) {
-  FIELD res = FIELD_ONE;
+DEVICE ${FIELD} ${FIELD}_pow(${FIELD} base, uint exponent) {
+  ${FIELD} res = ${FIELD}_ONE;
   while(exponent > 0) {
     if (exponent & 1)
-      res = FIELD_mul(res, base);
+      res = ${FIELD}_mul(res, base);
     exponent = exponent >> 1;
-    base = FIELD_sqr(base);
+    base = ${FIELD}_sqr(base);
   }
   return res;
 }
 
 
 // Store squares of the base in a lookup table for faster evaluation.
-DEVICE FIELD FIELD_pow_lookup(GLOBAL FIELD *bases, uint exponent) {
-  FIELD res = FIELD_ONE;
+DEVICE ${FIELD} ${FIELD}_pow_lookup(GLOBAL ${FIELD} *bases, uint exponent) {
+  ${FIELD} res = ${FIELD}_ONE;
   uint i = 0;
   while(exponent > 0) {
     if (exponent & 1)
-      res = FIELD_mul(res, bases[i]);
+      res = ${FIELD}_mul(res, bases[i]);
     exponent = exponent >> 1;
     i++;
   }
@@ -352,41 +352,41 @@ DEVICE FIELD FIELD_pow_lookup(GLOBAL FIELD *bases, uint exponent) {
 }
 
 
-DEVICE FIELD FIELD_mont(FIELD_repr a) {
+DEVICE ${FIELD} ${FIELD}_mont(${FIELD}_repr a) {
   #ifdef CUDA
-    FIELD input = reinterpret_cast<FIELD&>(a);  
+    ${FIELD} input = reinterpret_cast<${FIELD}&>(a);  
   #else
-    FIELD input = * (FIELD *) &a;
+    ${FIELD} input = * (${FIELD} *) &a;
   #endif
 
-  return FIELD_mul(input, FIELD_R2);
+  return ${FIELD}_mul(input, ${FIELD}_R2);
 }
 
-DEVICE FIELD_repr FIELD_unmont(FIELD a) {
-  FIELD one = FIELD_ZERO;
+DEVICE ${FIELD}_repr ${FIELD}_unmont(${FIELD} a) {
+  ${FIELD} one = ${FIELD}_ZERO;
   one.val[0] = 1;
-  FIELD unmont = FIELD_mul(a, one);
+  ${FIELD} unmont = ${FIELD}_mul(a, one);
 
   
   #ifdef CUDA
-    FIELD_repr answer = reinterpret_cast<FIELD_repr&>(unmont);  
+    ${FIELD}_repr answer = reinterpret_cast<${FIELD}_repr&>(unmont);  
   #else
-    FIELD_repr answer = * (FIELD_repr *) &unmont;
+    ${FIELD}_repr answer = * (${FIELD}_repr *) &unmont;
   #endif
   return answer;
 }
 
 // Get `i`th bit (From most significant digit) of the field.
-DEVICE bool FIELD_get_bit(FIELD_repr l, uint i) {
-  return (l.val[FIELD_LIMBS - 1 - i / FIELD_LIMB_BITS] >> (FIELD_LIMB_BITS - 1 - (i % FIELD_LIMB_BITS))) & 1;
+DEVICE bool ${FIELD}_get_bit(${FIELD}_repr l, uint i) {
+  return (l.val[${FIELD}_LIMBS - 1 - i / ${FIELD}_LIMB_BITS] >> (${FIELD}_LIMB_BITS - 1 - (i % ${FIELD}_LIMB_BITS))) & 1;
 }
 
 // Get `window` consecutive bits, (Starting from `skip`th bit) from the field.
-DEVICE uint FIELD_get_bits(FIELD_repr l, uint skip, uint window) {
+DEVICE uint ${FIELD}_get_bits(${FIELD}_repr l, uint skip, uint window) {
   uint ret = 0;
   for(uint i = 0; i < window; i++) {
     ret <<= 1;
-    ret |= FIELD_get_bit(l, skip + i);
+    ret |= ${FIELD}_get_bit(l, skip + i);
   }
   return ret;
 }
@@ -57,12 +57,12 @@
  * The function accumulates the elliptic curve points in the respective buckets based on the corresponding scalar values.
  * After processing all the points, it performs a single scalar multiplication for each bucket to obtain the final results.
  * 
- * TODO: for WNAF optimization, the current code assumes the actual large integer bits is SCALAR_BITS - 1.
+ * TODO: for WNAF optimization, the current code assumes the actual large integer bits is ${SCALAR}_BITS - 1.
  */
-DEVICE void POINT_multiexp_chunk(
-  GLOBAL POINT_affine *bases,
-  GLOBAL SCALAR_repr *exps,
-  GLOBAL POINT_jacobian *buckets,
+DEVICE void ${POINT}_multiexp_chunk(
+  GLOBAL ${POINT}_affine *bases,
+  GLOBAL ${SCALAR}_repr *exps,
+  GLOBAL ${POINT}_jacobian *buckets,
   uint tid,
   uint chunk_len,
   uint n_chunk_threads,
@@ -73,19 +73,19 @@ DEVICE void POINT_multiexp_chunk(
 {
   // When the large integer bits number is not divisible by window_bits, some threads may have an actual 
   // window size smaller than window_bits. Here, we calculate the effective window size for those threads.
-  const ushort w = min((ushort)window_bits, (ushort)(SCALAR_BITS - tid * window_bits));
+  const ushort w = min((ushort)window_bits, (ushort)(${SCALAR}_BITS - tid * window_bits));
 
   // The WNAF optimization needs to check if the next less significant window generates a carry. 
   // Here, we calculate the size of the next window.
   ushort w_next = 0;
-  if (SCALAR_BITS >= (tid + 1) * window_bits) {
-    w_next = min((ushort)window_bits, (ushort)(SCALAR_BITS - (tid + 1) * window_bits));
+  if (${SCALAR}_BITS >= (tid + 1) * window_bits) {
+    w_next = min((ushort)window_bits, (ushort)(${SCALAR}_BITS - (tid + 1) * window_bits));
   }
 
   // Init buckets belongs to the current thread
-  POINT_jacobian* t_buckets = &buckets[tid * n_thread_buckets];
+  ${POINT}_jacobian* t_buckets = &buckets[tid * n_thread_buckets];
   for(uint i = 0; i < n_thread_buckets; i++) {
-    t_buckets[i] = POINT_ZERO;
+    t_buckets[i] = ${POINT}_ZERO;
   }
 
   uint half_bucket = 1 << (window_bits - 1);
@@ -94,14 +94,14 @@ DEVICE void POINT_multiexp_chunk(
   // Process each input element  
   for(uint i = 0; i < chunk_len; i++) {
     // Scalar for the thread's window
-    uint ind = SCALAR_get_bits(exps[i], tid * window_bits, w);
+    uint ind = ${SCALAR}_get_bits(exps[i], tid * window_bits, w);
 
     // Check if the current window generates a carry for the next more significant window.
     bool carry = (ind >= half_bucket);
 
     // Check if the next less significant window generets a carry for the current window.
     if (signed_window && w_next == window_bits) {
-      uint ind_next = SCALAR_get_bits(exps[i], tid * window_bits + window_bits, w_next);
+      uint ind_next = ${SCALAR}_get_bits(exps[i], tid * window_bits + window_bits, w_next);
       if (ind_next >= half_bucket) {
         ind += 1;
       }
@@ -110,11 +110,11 @@ DEVICE void POINT_multiexp_chunk(
     bool compute_neg = carry && signed_window;
     
     if (ind > 0 && !compute_neg) {
-      POINT_jacobian* bucket = &t_buckets[ind - 1];
-      *bucket = POINT_add_mixed(*bucket, bases[i]);
+      ${POINT}_jacobian* bucket = &t_buckets[ind - 1];
+      *bucket = ${POINT}_add_mixed(*bucket, bases[i]);
     } else if (full_bucket > ind && compute_neg) {
-      POINT_jacobian* bucket = &t_buckets[full_bucket - ind - 1];
-      *bucket = POINT_add_mixed(*bucket, POINT_affine_neg(bases[i]));
+      ${POINT}_jacobian* bucket = &t_buckets[full_bucket - ind - 1];
+      *bucket = ${POINT}_add_mixed(*bucket, ${POINT}_affine_neg(bases[i]));
     }
   }
 
@@ -122,24 +122,24 @@ DEVICE void POINT_multiexp_chunk(
   // Optimization. 3a + 2b + 1c = a +
   //                             (a) + b +
   //                             ((a) + b) + c
-  POINT_jacobian acc = t_buckets[n_thread_buckets - 1];
-  POINT_jacobian res = acc;
+  ${POINT}_jacobian acc = t_buckets[n_thread_buckets - 1];
+  ${POINT}_jacobian res = acc;
   for(int j = n_thread_buckets - 1; j >= 1; j--) {
-    acc = POINT_add(acc, t_buckets[j - 1]);
-    res = POINT_add(res, acc);
+    acc = ${POINT}_add(acc, t_buckets[j - 1]);
+    res = ${POINT}_add(res, acc);
   }
   t_buckets[0] = res;
   
   BARRIER_LOCAL();
 }
 
-// A utility function for `POINT_aggregate_chunk`
-DEVICE uint POINT_bucket_scalar_exp(uint index, uint window_bits, uint height) {
+// A utility function for `${POINT}_aggregate_chunk`
+DEVICE uint ${POINT}_bucket_scalar_exp(uint index, uint window_bits, uint height) {
   uint x = (index + (1 << height)) * window_bits;
-  if (x >= SCALAR_BITS) {
+  if (x >= ${SCALAR}_BITS) {
     return 0;
   } else {
-    return SCALAR_BITS - x;
+    return ${SCALAR}_BITS - x;
   }
 }
 
@@ -156,8 +156,8 @@ DEVICE uint POINT_bucket_scalar_exp(uint index, uint window_bits, uint height) {
  * And scalar_exp in the code represents the exponent of this power.
  */
 
-DEVICE void POINT_aggregate_chunk(
-  GLOBAL POINT_jacobian *buckets,
+DEVICE void ${POINT}_aggregate_chunk(
+  GLOBAL ${POINT}_jacobian *buckets,
   uint tid,
   uint n_chunk_threads,
   uint n_thread_buckets,
@@ -180,15 +180,15 @@ DEVICE void POINT_aggregate_chunk(
     }
     
     // The bucket_scalar_exp keeps changes in each height.
-    uint my_scalar_exp = POINT_bucket_scalar_exp(tid, window_bits, h);
-    uint sib_scalar_exp = POINT_bucket_scalar_exp(sib_id, window_bits, h);
+    uint my_scalar_exp = ${POINT}_bucket_scalar_exp(tid, window_bits, h);
+    uint sib_scalar_exp = ${POINT}_bucket_scalar_exp(sib_id, window_bits, h);
 
 
-    POINT_jacobian res = buckets[tid * n_thread_buckets];
+    ${POINT}_jacobian res = buckets[tid * n_thread_buckets];
     for(uint i = 0; i < my_scalar_exp - sib_scalar_exp; i++) {
-      res = POINT_double(res);
+      res = ${POINT}_double(res);
     }
-    buckets[tid * n_thread_buckets] = POINT_add(res, buckets[sib_id * n_thread_buckets]); // 8
+    buckets[tid * n_thread_buckets] = ${POINT}_add(res, buckets[sib_id * n_thread_buckets]); // 8
 
     h += 1;
   
@@ -208,29 +208,29 @@ DEVICE void POINT_aggregate_chunk(
  * @param n_chunks The number of chunks each line is divided into for parallel computation.
  * @param n_chunk_threads The number of threads assigned to each chunk, representing the number of windows.
  * @param window_bits The number of bits in each bucket window.
- * @param neg_is_cheap Indicates whether the affine negation operation is relatively cheap, controlling the WNAF optimization.
+ * @param neg_is_cheap Non-zero if the affine negation operation is relatively cheap, controlling the WNAF optimization.
  *
  * This function receives a row of large integer scalars and multiple rows of elliptic curve points. The length of each line of elliptic curve points
  * equals to the length of the large integer row and must be a power of two. The code divides each line into several chunks based on the input parameters
  * and computes the MSM for each chunk separately.
  */
-KERNEL void POINT_multiexp(
-    GLOBAL POINT_affine *bases,
-    GLOBAL POINT_jacobian *results,
-    GLOBAL SCALAR_repr *exps,
-    GLOBAL POINT_jacobian *buckets,
+KERNEL void ${POINT}_multiexp(
+    GLOBAL ${POINT}_affine *bases,
+    GLOBAL ${POINT}_jacobian *results,
+    GLOBAL ${SCALAR}_repr *exps,
+    GLOBAL ${POINT}_jacobian *buckets,
     uint line_len,
     uint n_lines,
     uint n_chunks,
     uint n_chunk_threads,
     uint window_bits,
-    bool neg_is_cheap
+    uint neg_is_cheap
 ) 
 {
   const uint gid = GET_GLOBAL_ID();
   if(gid >= n_lines * n_chunks * n_chunk_threads) return;
 
-  // POINT_jacobian* buckets = (POINT_jacobian*)cuda_shared;
+  // ${POINT}_jacobian* buckets = (${POINT}_jacobian*)cuda_shared;
 
   const uint chunk_len = line_len / n_chunks;
   
@@ -249,14 +249,14 @@ KERNEL void POINT_multiexp(
     n_thread_buckets = (1 << window_bits) - 1;
   }
   
-  POINT_affine *bases_line = &bases[line_id * line_len];
-  POINT_affine *bases_chunk = &bases_line[chunk_id * chunk_len];
-  SCALAR_repr *exps_chunk = &exps[chunk_id * chunk_len];
-  POINT_jacobian *buckets_chunk = &buckets[task_id * n_chunk_threads * n_thread_buckets];
+  ${POINT}_affine *bases_line = &bases[line_id * line_len];
+  ${POINT}_affine *bases_chunk = &bases_line[chunk_id * chunk_len];
+  ${SCALAR}_repr *exps_chunk = &exps[chunk_id * chunk_len];
+  ${POINT}_jacobian *buckets_chunk = &buckets[task_id * n_chunk_threads * n_thread_buckets];
 
-  POINT_multiexp_chunk(bases_chunk, exps_chunk, buckets_chunk, local_thread_id, chunk_len, n_chunk_threads, n_thread_buckets, window_bits, signed_window);
+  ${POINT}_multiexp_chunk(bases_chunk, exps_chunk, buckets_chunk, local_thread_id, chunk_len, n_chunk_threads, n_thread_buckets, window_bits, signed_window);
 
-  POINT_aggregate_chunk(buckets_chunk, local_thread_id, n_chunk_threads, n_thread_buckets, window_bits);
+  ${POINT}_aggregate_chunk(buckets_chunk, local_thread_id, n_chunk_threads, n_thread_buckets, window_bits);
 
   if (local_thread_id == 0) {
     results[line_id * n_chunks + chunk_id] = buckets_chunk[0];
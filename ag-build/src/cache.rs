@@ -0,0 +1,383 @@
+//! A cache of compiled kernels shared by all the builds of a user.
+//!
+//! Kernels are named by the digest of their source and compiler options, so
+//! identical kernels are compiled once per machine, whichever crate, profile
+//! or target directory they are built for. The cache is in
+//! `AG_BUILD_CACHE_DIR`, by default `ag-build` in the user's cache directory
+//! (`$XDG_CACHE_HOME`, `%LOCALAPPDATA%` or `$HOME/.cache`). Setting
+//! `AG_BUILD_CACHE_DIR` to an empty string disables the cache, kernels are
+//! then compiled in the working directory.
+//!
+//! An entry is compiled while holding an exclusive lock on its lock file, so
+//! concurrent builds wait for each other instead of compiling the same kernel
+//! twice, and is moved into place once complete. Builds using an entry hold a
+//! shared lock while copying it. When the cache grows beyond
+//! `AG_BUILD_CACHE_SIZE` MiB, 512 by default, the least recently used
+//! entries that are not in use are removed with their lock files.
+//!
+//! Builds hold a shared lock on `cache.lock` while they use the cache. The
+//! eviction holds it exclusively, so no build is about to lock a lock file
+//! that is removed, and is skipped while the cache is in use.
+
+// Only the atomic writes are used without the `cuda` feature.
+#![cfg_attr(not(feature = "cuda"), allow(dead_code))]
+
+use crate::{BuildError, BuildResult};
+
+use fs2::FileExt;
+use std::{
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
+};
+
+/// The variables configuring the cache, including those of the default
+/// directory.
+pub(crate) const ENV_VARS: [&str; 5] = [
+    "AG_BUILD_CACHE_DIR",
+    "AG_BUILD_CACHE_SIZE",
+    "XDG_CACHE_HOME",
+    "LOCALAPPDATA",
+    "HOME",
+];
+
+const DEFAULT_SIZE_MIB: u64 = 512;
+
+/// The lock file of the whole cache.
+const CACHE_LOCK: &str = "cache.lock";
+
+pub(crate) struct KernelCache {
+    dir: PathBuf,
+    /// The size the entries are evicted down to, `None` if unbounded.
+    max_size: Option<u64>,
+}
+
+impl KernelCache {
+    /// The cache configured by the environment, or an unbounded one in
+    /// `working_dir` if it is disabled.
+    pub(crate) fn from_env(working_dir: &Path) -> BuildResult<Self> {
+        let dir = match env::var_os("AG_BUILD_CACHE_DIR") {
+            Some(dir) if dir.is_empty() => None,
+            Some(dir) => Some(PathBuf::from(dir)),
+            None => default_dir(),
+        };
+        let Some(dir) = dir else {
+            return Ok(Self::new(working_dir.to_owned(), None));
+        };
+
+        let max_size = match env::var("AG_BUILD_CACHE_SIZE") {
+            Ok(size) => {
+                size.parse::<u64>().map_err(|_| BuildError::InvalidEnv {
+                    name: "AG_BUILD_CACHE_SIZE".to_owned(),
+                    value: size,
+                })?
+            }
+            Err(_) => DEFAULT_SIZE_MIB,
+        };
+        fs::create_dir_all(&dir).map_err(BuildError::io(&dir))?;
+        Ok(Self::new(dir, Some(max_size << 20)))
+    }
+
+    pub(crate) fn new(dir: PathBuf, max_size: Option<u64>) -> Self {
+        Self { dir, max_size }
+    }
+
+    /// Copies the entry `name` to `target`, calling `compile` with the path
+    /// to write it to if it is not cached yet. Returns the path of the entry.
+    pub(crate) fn get_or_insert(
+        &self, name: &str, target: &Path,
+        compile: impl FnOnce(&Path) -> BuildResult<()>,
+    ) -> BuildResult<PathBuf> {
+        let cache_lock_path = self.dir.join(CACHE_LOCK);
+        let cache_lock = open_lock(&cache_lock_path)
+            .and_then(|lock| lock.lock_shared().map(|_| lock))
+            .map_err(BuildError::io(&cache_lock_path))?;
+
+        let path = self.dir.join(name);
+        let lock_path = self.dir.join(format!("{}.lock", name));
+        let lock = open_lock(&lock_path).map_err(BuildError::io(&lock_path))?;
+        lock.lock_shared().map_err(BuildError::io(&lock_path))?;
+        if !path.exists() {
+            // Upgrade the lock, another build may compile the entry meanwhile.
+            lock.unlock()
+                .and_then(|_| lock.lock_exclusive())
+                .map_err(BuildError::io(&lock_path))?;
+            if !path.exists() {
+                write_atomic(&path, |tmp| compile(tmp))?;
+            }
+        }
+
+        // Mark the entry as used for the eviction.
+        let touched = fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        if let Err(e) = touched {
+            log::warn!("cannot touch {}: {}", path.display(), e);
+        }
+        if path != target {
+            copy_atomic(&path, target)?;
+        }
+        drop(lock);
+        drop(cache_lock);
+
+        self.evict(&path);
+        Ok(path)
+    }
+
+    /// Removes the least recently used entries, other than `keep`, until the
+    /// cache fits its size, and the lock files left by failed compilations.
+    /// Entries that are in use are skipped, as is the whole eviction while
+    /// other builds use the cache.
+    fn evict(&self, keep: &Path) {
+        let Some(max_size) = self.max_size else {
+            return;
+        };
+        let cache_lock_path = self.dir.join(CACHE_LOCK);
+        let cache_lock = open_lock(&cache_lock_path)
+            .and_then(|lock| lock.try_lock_exclusive().map(|_| lock));
+        let (Ok(_cache_lock), Ok(dir)) = (cache_lock, fs::read_dir(&self.dir))
+        else {
+            return;
+        };
+
+        let mut entries = Vec::new();
+        let mut lock_paths = Vec::new();
+        for entry in dir.filter_map(Result::ok) {
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_file() || path == cache_lock_path {
+                continue;
+            }
+            match path.extension() {
+                Some(ext) if ext == "lock" => lock_paths.push(path),
+                Some(ext) if ext != "tmp" => entries.push((
+                    metadata.modified().ok(),
+                    metadata.len(),
+                    path,
+                )),
+                _ => {}
+            }
+        }
+        let mut size: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        entries.sort();
+
+        for (_, len, path) in entries {
+            if size <= max_size {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+            let mut lock_path = path.clone().into_os_string();
+            lock_path.push(".lock");
+            if remove_unlocked(&path, Path::new(&lock_path)) {
+                log::info!("evicted {} from the kernel cache", path.display());
+                size -= len;
+            }
+        }
+        for lock_path in lock_paths {
+            let path = lock_path.with_extension("");
+            if !path.exists() {
+                remove_unlocked(&path, &lock_path);
+            }
+        }
+    }
+}
+
+/// Removes the entry `path` and its lock file, unless a build holds the lock.
+/// Returns whether the entry is removed.
+fn remove_unlocked(path: &Path, lock_path: &Path) -> bool {
+    let locked = open_lock(lock_path)
+        .and_then(|lock| lock.try_lock_exclusive().map(|_| lock));
+    let Ok(_lock) = locked else {
+        return false;
+    };
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return false,
+        _ => {}
+    }
+    // Still holding the lock, which no build waits for, see the module
+    // documentation.
+    if let Err(e) = fs::remove_file(lock_path) {
+        log::warn!("cannot remove {}: {}", lock_path.display(), e);
+    }
+    true
+}
+
+/// Opens the lock file of an entry, creating it if needed.
+fn open_lock(path: &Path) -> std::io::Result<fs::File> {
+    fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+}
+
+fn default_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| {
+            env::var_os("HOME").map(|home| Path::new(&home).join(".cache"))
+        })
+        .map(|dir| dir.join("ag-build"))
+}
+
+/// Writes `path` by calling `write` with a temporary path next to it, which
+/// is then renamed to `path`. Readers see either no file or a complete one,
+/// also if builds sharing the directory write it at the same time.
+pub(crate) fn write_atomic(
+    path: &Path, write: impl FnOnce(&Path) -> BuildResult<()>,
+) -> BuildResult<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let mut tmp = path.to_owned().into_os_string();
+    tmp.push(format!(
+        ".{}-{}.tmp",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp = PathBuf::from(tmp);
+
+    let result = write(&tmp)
+        .and_then(|_| fs::rename(&tmp, path).map_err(BuildError::io(path)));
+    if result.is_err() {
+        match fs::remove_file(&tmp) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                log::warn!("cannot remove {}: {}", tmp.display(), e)
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Copies `from` to `to` with [`write_atomic`].
+pub(crate) fn copy_atomic(from: &Path, to: &Path) -> BuildResult<()> {
+    write_atomic(to, |tmp| {
+        fs::copy(from, tmp).map(|_| ()).map_err(BuildError::io(tmp))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{cell::Cell, time::Duration};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "ag-build-cache-{}-{}",
+            name,
+            process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_compiles_once() {
+        let dir = test_dir("once");
+        let cache = KernelCache::new(dir.clone(), None);
+        let compiled = Cell::new(0);
+        let compile = |path: &Path| {
+            compiled.set(compiled.get() + 1);
+            fs::write(path, b"fatbin").map_err(BuildError::io(path))
+        };
+
+        let target = dir.join("a.copy");
+        let path = cache.get_or_insert("a.fatbin", &target, compile).unwrap();
+        assert_eq!(
+            cache.get_or_insert("a.fatbin", &target, compile).unwrap(),
+            path
+        );
+        assert_eq!(compiled.get(), 1);
+        assert_eq!(fs::read(&path).unwrap(), b"fatbin");
+        assert_eq!(fs::read(&target).unwrap(), b"fatbin");
+        fs::remove_file(target).unwrap();
+
+        // A failed compilation leaves no entry and no temporary file.
+        let target = dir.join("b.fatbin");
+        let failed = cache.get_or_insert("b.fatbin", &target, |path| {
+            fs::write(path, b"partial").unwrap();
+            Err(BuildError::io(path)(ErrorKind::Other.into()))
+        });
+        assert!(failed.is_err());
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["a.fatbin", "a.fatbin.lock", "b.fatbin.lock", "cache.lock"]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let dir = test_dir("evict");
+        let now = SystemTime::now();
+        for (i, name) in ["old", "used", "new"].iter().enumerate() {
+            let path = dir.join(format!("{}.fatbin", name));
+            fs::write(&path, [0; 100]).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(100 - i as u64))
+                .unwrap();
+        }
+
+        // The lock file of a failed compilation.
+        fs::write(dir.join("failed.fatbin.lock"), []).unwrap();
+
+        // Nothing is removed while another build uses the cache.
+        let cache = KernelCache::new(dir.clone(), Some(250));
+        let used = dir.join("used.fatbin");
+        let cache_lock = open_lock(&dir.join(CACHE_LOCK)).unwrap();
+        cache_lock.lock_shared().unwrap();
+        cache
+            .get_or_insert("used.fatbin", &used, |_| unreachable!())
+            .unwrap();
+        assert!(dir.join("old.fatbin").exists());
+        drop(cache_lock);
+
+        // Using an entry keeps it, the oldest of the others is removed with
+        // its lock file.
+        cache
+            .get_or_insert("used.fatbin", &used, |_| unreachable!())
+            .unwrap();
+        assert!(!dir.join("old.fatbin").exists());
+        assert!(!dir.join("old.fatbin.lock").exists());
+        assert!(!dir.join("failed.fatbin.lock").exists());
+        assert!(used.exists());
+        assert!(dir.join("new.fatbin").exists());
+
+        // An entry another build reads is skipped.
+        let lock = open_lock(&dir.join("new.fatbin.lock")).unwrap();
+        lock.lock_shared().unwrap();
+        let cache = KernelCache::new(dir.clone(), Some(100));
+        cache
+            .get_or_insert("used.fatbin", &used, |_| unreachable!())
+            .unwrap();
+        assert!(dir.join("new.fatbin").exists());
+        drop(lock);
+        cache
+            .get_or_insert("used.fatbin", &used, |_| unreachable!())
+            .unwrap();
+        assert!(!dir.join("new.fatbin").exists());
+        assert!(used.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -12,23 +12,28 @@
 /// automatically be used by the `ec-gpu-gen` functionality that needs a
 /// kernel. OpenCL compiles the source at run time).
 pub use super::source::SourceBuilder;
+use crate::{
+    cache::{write_atomic, KernelCache},
+    source::Limb32Or64,
+    BuildError, BuildPolicy, BuildResult, SourceMap, Target,
+};
 
 pub use std::{env, fs, path::PathBuf};
+use std::{path::Path, process::Command};
 
 fn in_build_script() -> bool { std::env::var("OUT_DIR").is_ok() }
 
-fn working_dir() -> String {
+fn working_dir() -> BuildResult<PathBuf> {
     if let Ok(dir) = env::var("ARK_GPU_BUILD_DIR") {
-        dir
+        Ok(dir.into())
     } else if let Ok(dir) = env::var("OUT_DIR") {
-        dir
+        Ok(dir.into())
     } else {
-        tempfile::tempdir()
-            .unwrap()
-            .path()
-            .to_str()
-            .unwrap()
-            .to_owned()
+        // Outside of build scripts, e.g. in tests, share a directory so that
+        // the kernels are only compiled once.
+        let dir = env::temp_dir().join("ag-build");
+        fs::create_dir_all(&dir).map_err(BuildError::io(&dir))?;
+        Ok(dir)
     }
 }
 
@@ -40,39 +45,129 @@ macro_rules! bprintln {
     };
 }
 
+/// Sets a compile-time environment variable for the crate being built, or
+/// for the current process outside of build scripts.
+fn set_env(key: &str, path: &Path) {
+    bprintln!("cargo:rustc-env={}={}", key, path.display());
+    if !in_build_script() {
+        env::set_var(key, path);
+    }
+}
+
+/// Writes a generated file, atomically as builds may share the working
+/// directory.
+fn write(path: &Path, content: &[u8]) -> BuildResult<()> {
+    write_atomic(path, |tmp| {
+        fs::write(tmp, content).map_err(BuildError::io(tmp))
+    })
+}
+
+/// Writes the map of the generated source next to it, as `<source>.map.json`.
+fn write_source_map(source_path: &Path, map: &SourceMap) -> BuildResult<()> {
+    let mut path = source_path.to_owned().into_os_string();
+    path.push(".map.json");
+    write(Path::new(&path), map.to_json().as_bytes())
+}
+
+/// Reruns the build script if a variable affecting the generated files
+/// changes.
+fn rerun_if_env_changed() {
+    let vars = crate::options::ENV_VARS
+        .iter()
+        .chain(&crate::cache::ENV_VARS);
+    for var in vars.chain(&["ARK_GPU_BUILD_DIR"]) {
+        bprintln!("cargo:rerun-if-env-changed={}", var);
+    }
+}
+
+/// Runs the kernel compiler on `source_path`, the output file must already be
+/// set on `command`.
+fn run_compiler(command: &mut Command, source_path: &Path) -> BuildResult<()> {
+    let output = command.arg(source_path).output().map_err(|source| {
+        BuildError::ToolchainMissing {
+            tool: command.get_program().to_string_lossy().into_owned(),
+            source,
+        }
+    })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(BuildError::Compiler {
+            source_path: source_path.to_owned(),
+            status: output.status,
+            diagnostics: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+/// Writes the JSON description of the kernel entry points and the typed
+/// launchers generated from it. The path to the launchers is stored in the
+/// `_EC_GPU_CUDA_KERNEL_LAUNCHERS` environment variable.
 #[cfg(feature = "cuda")]
-pub fn generate_cuda(source_builder: &SourceBuilder) -> PathBuf {
+fn generate_launchers(
+    source_builder: &SourceBuilder, out_dir: &Path,
+) -> BuildResult<PathBuf> {
     use sha2::{Digest, Sha256};
 
+    let kernels_json = source_builder.build_kernels_json();
+    let launchers = source_builder.build_launchers();
+
+    // The launchers only depend on the signatures, name them by their digest
+    // so that builds sharing a working directory do not overwrite each other.
+    let digest = hex::encode(Sha256::digest(kernels_json.as_bytes()));
+    let json_path = out_dir.join(format!("{}.kernels.json", &digest));
+    let launchers_path = out_dir.join(format!("{}.launchers.rs", &digest));
+
+    write(&json_path, kernels_json.as_bytes())?;
+    write(&launchers_path, launchers.as_bytes())?;
+    set_env("_EC_GPU_CUDA_KERNEL_LAUNCHERS", &launchers_path);
+
+    Ok(launchers_path)
+}
+
+/// Writes the manifest of the CUDA source. The path to it is
+/// stored in the `_EC_GPU_CUDA_KERNEL_MANIFEST` environment variable, meant to
+/// be passed to `CudaWorkspace::from_bytes_with_manifest` with
+/// `include_str!(env!("_EC_GPU_CUDA_KERNEL_MANIFEST"))`.
+#[cfg(feature = "cuda")]
+fn generate_manifest(
+    source_builder: &SourceBuilder, out_dir: &Path,
+) -> BuildResult<PathBuf> {
+    let manifest = source_builder
+        .manifest(source_builder.limb_size_or(Limb32Or64::Limb32));
+    let path = out_dir.join(format!("{}.manifest.json", &manifest.digest));
+    write(&path, manifest.to_json().as_bytes())?;
+    set_env("_EC_GPU_CUDA_KERNEL_MANIFEST", &path);
+
+    Ok(path)
+}
+
+#[cfg(feature = "cuda")]
+pub fn generate_cuda(source_builder: &SourceBuilder) -> BuildResult<PathBuf> {
+    use sha2::{Digest, Sha256};
+
+    rerun_if_env_changed();
+    source_builder.check(source_builder.limb_size_or(Limb32Or64::Limb32))?;
+    let out_dir = working_dir()?;
+
+    // The launchers are Rust source and the manifest is embedded, they are
+    // needed even if the kernel itself is not compiled.
+    generate_launchers(source_builder, &out_dir)?;
+    generate_manifest(source_builder, &out_dir)?;
+
     // This is a hack when no properly compiled kernel is needed. That's the
     // case when the documentation is built on docs.rs and when Clippy is
     // run. We can use arbitrary bytes as input then.
     if std::env::var("DOCS_RS").is_ok() || cfg!(feature = "cargo-clippy") {
         bprintln!("cargo:rustc-env=_EC_GPU_CUDA_KERNEL_FATBIN=../build.rs");
-        return PathBuf::from("../build.rs");
+        return Ok(PathBuf::from("../build.rs"));
     }
 
-    let kernel_source = source_builder.build_32_bit_limbs();
-    let out_dir = working_dir();
-
-    // Make it possible to override the default options. Though the source and
-    // output file is always set automatically.
-    let mut nvcc = match env::var("EC_GPU_CUDA_NVCC_ARGS") {
-        Ok(args) => execute::command(format!("nvcc {}", args)),
-        Err(_) => {
-            let mut command = std::process::Command::new("nvcc");
-            command
-                .arg("--optimize=6")
-                // Compile with as many threads as CPUs are available.
-                .arg("--threads=0")
-                .arg("--fatbin")
-                .arg("--gpu-architecture=sm_86")
-                .arg("--generate-code=arch=compute_86,code=sm_86")
-                .arg("--generate-code=arch=compute_80,code=sm_80")
-                .arg("--generate-code=arch=compute_75,code=sm_75");
-            command
-        }
-    };
+    let (kernel_source, source_map) =
+        source_builder.build(source_builder.limb_size_or(Limb32Or64::Limb32));
+
+    // The source and output file are always set automatically.
+    let mut nvcc = source_builder.get_cuda_options().nvcc_command()?;
 
     // Hash the source and the compile flags. Use that as the filename, so that
     // the kernel is only rebuilt if any of them change.
@@ -81,81 +176,193 @@ pub fn generate_cuda(source_builder: &SourceBuilder) -> PathBuf {
     hasher.update(&format!("{:?}", &nvcc));
     let kernel_digest = hex::encode(hasher.finalize());
 
-    let source_path: PathBuf = [&out_dir, &format!("{}.cu", &kernel_digest)]
-        .iter()
-        .collect();
-    let fatbin_path: PathBuf =
-        [&out_dir, &format!("{}.fatbin", &kernel_digest)]
-            .iter()
-            .collect();
-
-    fs::write(&source_path, &kernel_source).unwrap_or_else(|_| {
-        panic!(
-            "Cannot write kernel source at {}.",
-            source_path.to_str().unwrap()
-        )
-    });
-
-    // Only compile if the output doesn't exist yet.
-    if !fatbin_path.as_path().exists() {
-        let status = nvcc
-            .arg("--output-file")
-            .arg(&fatbin_path)
-            .arg(&source_path)
-            .status()
-            .expect("Cannot run nvcc. Install the NVIDIA toolkit or disable the `cuda` feature.");
-
-        if !status.success() {
-            panic!(
-                "nvcc failed. See the kernel source at {}",
-                source_path.to_str().unwrap()
-            );
-        }
-    }
+    let source_path = out_dir.join(format!("{}.cu", &kernel_digest));
+    let fatbin_name = format!("{}.fatbin", &kernel_digest);
+    let fatbin_path = out_dir.join(&fatbin_name);
+
+    write(&source_path, kernel_source.as_bytes())?;
+    write_source_map(&source_path, &source_map)?;
+
+    // Only compile if the kernel isn't cached yet. The cached file may be
+    // evicted, the crate embeds a copy in the working directory.
+    let cache = KernelCache::from_env(&out_dir)?;
+    cache.get_or_insert(&fatbin_name, &fatbin_path, |output| {
+        nvcc.arg("--output-file").arg(output);
+        run_compiler(&mut nvcc, &source_path)
+    })?;
 
     // The idea to put the path to the farbin into a compile-time env variable
     // is from https://github.com/LutzCle/fast-interconnects-demo/blob/b80ea8e04825167f486ab8ac1b5d67cf7dd51d2c/rust-demo/build.rs
+    set_env("_EC_GPU_CUDA_KERNEL_FATBIN", &fatbin_path);
+
+    Ok(fatbin_path)
+}
+
+/// Embeds a stub in place of the CUDA kernel, which fails to load with
+/// `error`, and reports the error as a cargo warning. The launchers and the
+/// manifest are already generated by [`generate_cuda`] before it compiles.
+#[cfg(feature = "cuda")]
+fn generate_cuda_stub(error: &BuildError) -> BuildResult<PathBuf> {
+    let out_dir = working_dir()?;
+    let message = error.to_string();
+    // A warning ends at the line break, report the lines separately.
+    for line in message.lines() {
+        bprintln!("cargo:warning={}", line);
+    }
     bprintln!(
-        "cargo:rustc-env=_EC_GPU_CUDA_KERNEL_FATBIN={}",
-        fatbin_path.to_str().unwrap()
+        "cargo:warning=The CUDA kernel is not compiled, loading it fails at \
+         run time."
     );
-    if !in_build_script() {
-        env::set_var(
-            "_EC_GPU_CUDA_KERNEL_FATBIN",
-            fatbin_path.to_str().unwrap(),
-        );
+
+    let stub_path = out_dir.join("stub.fatbin");
+    write(
+        &stub_path,
+        &[ag_types::STUB_KERNEL_MAGIC, message.as_bytes()].concat(),
+    )?;
+    set_env("_EC_GPU_CUDA_KERNEL_FATBIN", &stub_path);
+
+    Ok(stub_path)
+}
+
+/// Handles a failed [`try_generate`](crate::try_generate) according to
+/// `policy`. Only a missing toolchain is recovered from, other errors are
+/// bugs of the kernels or of the build environment and are returned.
+pub(crate) fn recover(
+    source_builder: &SourceBuilder, policy: BuildPolicy, error: BuildError,
+) -> BuildResult<()> {
+    if policy == BuildPolicy::Fail
+        || !matches!(error, BuildError::ToolchainMissing { .. })
+    {
+        return Err(error);
+    }
+    #[cfg(feature = "cuda")]
+    generate_cuda_stub(&error)?;
+    if policy == BuildPolicy::OpenClFallback {
+        generate_opencl(source_builder)?;
     }
+    Ok(())
+}
 
-    fatbin_path
+/// Generates each of `targets` with `generate`, handling its errors with
+/// [`recover`]. A target replaced by a stub does not stop the remaining ones.
+pub(crate) fn generate_each(
+    source_builder: &SourceBuilder, targets: &[Target], policy: BuildPolicy,
+    mut generate: impl FnMut(Target) -> BuildResult<()>,
+) -> BuildResult<()> {
+    for &target in targets {
+        generate(target)
+            .or_else(|error| recover(source_builder, policy, error))?;
+    }
+    Ok(())
 }
 
-#[cfg(feature = "opencl")]
-pub fn generate_opencl(source_builder: &SourceBuilder) -> PathBuf {
-    let kernel_source = source_builder.build_64_bit_limbs();
-    let out_dir = working_dir();
+pub fn generate_opencl(source_builder: &SourceBuilder) -> BuildResult<PathBuf> {
+    source_builder.check(source_builder.limb_size_or(Limb32Or64::Limb64))?;
+    let (kernel_source, source_map) =
+        source_builder.build(source_builder.limb_size_or(Limb32Or64::Limb64));
+    let out_dir = working_dir()?;
 
     // Generating the kernel source is cheap, hence use a fixed name and
     // override it on every build.
-    let source_path: PathBuf = [&out_dir, "kernel.cl"].iter().collect();
-
-    fs::write(&source_path, &kernel_source).unwrap_or_else(|_| {
-        panic!(
-            "Cannot write kernel source at {}.",
-            source_path.to_str().unwrap()
-        )
-    });
+    let source_path = out_dir.join("kernel.cl");
+    write(&source_path, kernel_source.as_bytes())?;
+    write_source_map(&source_path, &source_map)?;
 
     // For OpenCL we only need the kernel source, it is compiled at runtime.
-    bprintln!(
-        "cargo:rustc-env=_EC_GPU_OPENCL_KERNEL_SOURCE={}",
-        source_path.to_str().unwrap()
-    );
-    if !in_build_script() {
-        env::set_var(
-            "_EC_GPU_OPENCL_KERNEL_SOURCE",
-            source_path.to_str().unwrap(),
-        );
+    set_env("_EC_GPU_OPENCL_KERNEL_SOURCE", &source_path);
+
+    Ok(source_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_compiler() {
+        let mut command = Command::new("ag-build-no-such-compiler");
+        let error = run_compiler(&mut command, Path::new("kernel.cu"));
+        match error {
+            Err(BuildError::ToolchainMissing { tool, source }) => {
+                assert_eq!(tool, "ag-build-no-such-compiler");
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    fn missing() -> BuildError {
+        BuildError::ToolchainMissing {
+            tool: "nvcc".to_owned(),
+            source: std::io::ErrorKind::NotFound.into(),
+        }
     }
 
-    source_path
+    #[test]
+    fn test_recover() {
+        let source_builder = SourceBuilder::new();
+        let invalid = || BuildError::InvalidEnv {
+            name: "EC_GPU_CUDA_NVCC_ARGS".to_owned(),
+            value: String::new(),
+        };
+
+        let error = recover(&source_builder, BuildPolicy::Fail, missing());
+        assert!(matches!(error, Err(BuildError::ToolchainMissing { .. })));
+        for policy in [BuildPolicy::Stub, BuildPolicy::OpenClFallback] {
+            let error = recover(&source_builder, policy, invalid());
+            assert!(matches!(error, Err(BuildError::InvalidEnv { .. })));
+        }
+
+        recover(&source_builder, BuildPolicy::Stub, missing()).unwrap();
+        #[cfg(feature = "cuda")]
+        {
+            let stub = fs::read(working_dir().unwrap().join("stub.fatbin"));
+            let message = missing().to_string();
+            assert_eq!(
+                stub.unwrap(),
+                [ag_types::STUB_KERNEL_MAGIC, message.as_bytes()].concat()
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_each() {
+        let source_builder = SourceBuilder::new();
+        let targets = [Target::Cuda, Target::Opencl];
+        for (policy, expected) in [
+            (BuildPolicy::Fail, &targets[..1]),
+            (BuildPolicy::Stub, &targets[..]),
+        ] {
+            let mut generated = Vec::new();
+            let result =
+                generate_each(&source_builder, &targets, policy, |target| {
+                    generated.push(target);
+                    match target {
+                        Target::Cuda => Err(missing()),
+                        Target::Opencl => Ok(()),
+                    }
+                });
+            assert_eq!(result.is_ok(), policy == BuildPolicy::Stub);
+            assert_eq!(generated, expected);
+        }
+    }
+
+    #[test]
+    fn test_compiler_diagnostics() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo \"$0: error: bad\" >&2; exit 2");
+        let error = run_compiler(&mut command, Path::new("kernel.cu"));
+        match error {
+            Err(BuildError::Compiler {
+                source_path,
+                status,
+                diagnostics,
+            }) => {
+                assert_eq!(source_path, Path::new("kernel.cu"));
+                assert_eq!(status.code(), Some(2));
+                assert_eq!(diagnostics, "kernel.cu: error: bad\n");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
 }
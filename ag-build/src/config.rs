@@ -0,0 +1,421 @@
+//! Declarative configuration of the generated kernels.
+//!
+//! Instead of calling the [`SourceBuilder`] in `build.rs`, a crate lists its
+//! kernels in the `[package.metadata.ag-build]` table of its `Cargo.toml`,
+//! or in a TOML file of the same format, and calls
+//! [`generate_from_metadata`](crate::generate_from_metadata):
+//!
+//! ```toml
+//! [package.metadata.ag-build]
+//! targets = ["cuda"]
+//! limb-bits = 32
+//!
+//! [[package.metadata.ag-build.curves]]
+//! name = "bn254-g1"
+//! kernels = ["ec-fft", "multiexp"]
+//! # Only generated if the `bn254` feature of the crate is enabled.
+//! feature = "bn254"
+//!
+//! [[package.metadata.ag-build.fields]]
+//! name = "bn254-fr"
+//! kernels = ["fft"]
+//! ```
+//!
+//! The names are resolved with a [`TypeRegistry`]. The built-in one knows
+//! the curves and fields of the enabled `bls12-381` and `bn254` features of
+//! `ag-build`, other types are registered by the build script.
+
+use crate::{BuildError, BuildResult, SourceBuilder};
+
+use ag_types::{GpuCurveAffine, GpuField};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// The kernels generated for a curve.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CurveKernel {
+    /// The point arithmetic, which the other kernels include.
+    Ec,
+    EcFft,
+    Multiexp,
+}
+
+/// The kernels generated for a field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FieldKernel {
+    Fft,
+}
+
+/// What the kernels are generated for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Target {
+    /// A fatbin compiled with nvcc, requires the `cuda` feature.
+    Cuda,
+    /// The OpenCL source.
+    Opencl,
+}
+
+impl Target {
+    /// The targets of the enabled features of `ag-build`.
+    pub fn enabled() -> Vec<Target> {
+        let mut targets = Vec::new();
+        if cfg!(feature = "cuda") {
+            targets.push(Target::Cuda);
+        }
+        if cfg!(feature = "opencl") {
+            targets.push(Target::Opencl);
+        }
+        targets
+    }
+}
+
+/// A curve and the kernels generated for it.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CurveConfig {
+    /// The name of the curve in the [`TypeRegistry`], e.g. `bn254-g1`.
+    pub name: String,
+    /// The kernels, the point arithmetic and the fields are always generated.
+    #[serde(default)]
+    pub kernels: Vec<CurveKernel>,
+    /// The feature of the crate being built the curve is generated for, it
+    /// is always generated if `None`.
+    pub feature: Option<String>,
+}
+
+/// A field and the kernels generated for it.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldConfig {
+    /// The name of the field in the [`TypeRegistry`], e.g. `bn254-fr`.
+    pub name: String,
+    /// The kernels, the field arithmetic is always generated.
+    #[serde(default)]
+    pub kernels: Vec<FieldKernel>,
+    /// The feature of the crate being built the field is generated for, it
+    /// is always generated if `None`.
+    pub feature: Option<String>,
+}
+
+/// The configuration of the generated kernels, see the [module
+/// documentation](self).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct KernelConfig {
+    #[serde(default)]
+    pub curves: Vec<CurveConfig>,
+    #[serde(default)]
+    pub fields: Vec<FieldConfig>,
+    /// The size of the limbs, 32 or 64. By default 32 for CUDA and 64 for
+    /// OpenCL.
+    pub limb_bits: Option<u32>,
+    /// The targets, by default those of the enabled features of `ag-build`.
+    pub targets: Option<Vec<Target>>,
+    /// The file the configuration was read from, for error messages.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+impl KernelConfig {
+    /// Parses a configuration in TOML.
+    pub fn parse(config: &str) -> BuildResult<Self> {
+        toml::from_str(config).map_err(|e| invalid(None, e.message()))
+    }
+
+    /// Reads the configuration from a TOML file.
+    pub fn from_file(path: &Path) -> BuildResult<Self> {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let config = fs::read_to_string(path).map_err(BuildError::io(path))?;
+        let mut config =
+            Self::parse(&config).map_err(|e| e.with_config_path(path))?;
+        config.path = Some(path.to_owned());
+        Ok(config)
+    }
+
+    /// Reads the configuration from the `[package.metadata.ag-build]` table
+    /// of the `Cargo.toml` of the crate being built.
+    pub fn from_cargo_metadata() -> BuildResult<Self> {
+        let dir = env::var_os("CARGO_MANIFEST_DIR").ok_or_else(|| {
+            invalid(
+                None,
+                "CARGO_MANIFEST_DIR is not set, not in a build script",
+            )
+        })?;
+        let path = Path::new(&dir).join("Cargo.toml");
+        println!("cargo:rerun-if-changed={}", path.display());
+        let manifest =
+            fs::read_to_string(&path).map_err(BuildError::io(&path))?;
+        let mut config = Self::from_manifest(&manifest)
+            .map_err(|e| e.with_config_path(&path))?;
+        config.path = Some(path);
+        Ok(config)
+    }
+
+    /// Parses the `[package.metadata.ag-build]` table of a `Cargo.toml`.
+    fn from_manifest(manifest: &str) -> BuildResult<Self> {
+        let manifest = toml::Value::Table(
+            toml::from_str(manifest).map_err(|e| invalid(None, e.message()))?,
+        );
+        let config = ["package", "metadata", "ag-build"]
+            .iter()
+            .try_fold(&manifest, |value, key| value.get(key))
+            .ok_or_else(|| {
+                invalid(None, "there is no [package.metadata.ag-build] table")
+            })?;
+        config
+            .clone()
+            .try_into()
+            .map_err(|e: toml::de::Error| invalid(None, e.message()))
+    }
+
+    /// The targets to generate.
+    pub fn targets(&self) -> Vec<Target> {
+        self.targets.clone().unwrap_or_else(Target::enabled)
+    }
+
+    /// Adds the configured items of the enabled features of the crate being
+    /// built to a new builder, resolving their names with `registry`.
+    pub fn source_builder(
+        &self, registry: &TypeRegistry,
+    ) -> BuildResult<SourceBuilder> {
+        self.source_builder_with_features(registry, |feature| {
+            let var = feature.to_uppercase().replace('-', "_");
+            env::var_os(format!("CARGO_FEATURE_{}", var)).is_some()
+        })
+    }
+
+    fn source_builder_with_features(
+        &self, registry: &TypeRegistry, enabled: impl Fn(&str) -> bool,
+    ) -> BuildResult<SourceBuilder> {
+        let path = self.path.as_deref();
+        let mut builder = SourceBuilder::new();
+        if let Some(bits) = self.limb_bits {
+            if bits != 32 && bits != 64 {
+                let message =
+                    format!("limb-bits must be 32 or 64, not {}", bits);
+                return Err(invalid(path, message));
+            }
+            builder = builder.limb_bits(bits);
+        }
+
+        let is_enabled = |feature: &Option<String>| match feature {
+            Some(feature) => enabled(feature),
+            None => true,
+        };
+        for curve in self.curves.iter().filter(|c| is_enabled(&c.feature)) {
+            let add = registry.curves.get(&curve.name).ok_or_else(|| {
+                unknown(path, "curve", &curve.name, registry.curves.keys())
+            })?;
+            builder = add(builder, &curve.kernels);
+        }
+        for field in self.fields.iter().filter(|f| is_enabled(&f.feature)) {
+            let add = registry.fields.get(&field.name).ok_or_else(|| {
+                unknown(path, "field", &field.name, registry.fields.keys())
+            })?;
+            builder = add(builder, &field.kernels);
+        }
+        Ok(builder)
+    }
+}
+
+type AddCurve = fn(SourceBuilder, &[CurveKernel]) -> SourceBuilder;
+type AddField = fn(SourceBuilder, &[FieldKernel]) -> SourceBuilder;
+
+/// The curves and fields a [`KernelConfig`] can name.
+///
+/// ```ignore
+/// let registry = TypeRegistry::builtin().curve::<MyG1Affine>("my-g1");
+/// ```
+#[derive(Clone, Default)]
+pub struct TypeRegistry {
+    curves: BTreeMap<String, AddCurve>,
+    fields: BTreeMap<String, AddField>,
+}
+
+impl TypeRegistry {
+    /// An empty registry.
+    pub fn new() -> Self { Self::default() }
+
+    /// The curves and fields of the enabled features: `bls12-381-g1`,
+    /// `bls12-381-fr` and `bls12-381-fq` with `bls12-381`, `bn254-g1`,
+    /// `bn254-fr` and `bn254-fq` with `bn254`.
+    pub fn builtin() -> Self {
+        #[allow(unused_mut)]
+        let mut registry = Self::new();
+        #[cfg(feature = "bls12-381")]
+        {
+            registry = registry
+                .curve::<ark_bls12_381::G1Affine>("bls12-381-g1")
+                .field::<ark_bls12_381::Fr>("bls12-381-fr")
+                .field::<ark_bls12_381::Fq>("bls12-381-fq");
+        }
+        #[cfg(feature = "bn254")]
+        {
+            registry = registry
+                .curve::<ark_bn254::G1Affine>("bn254-g1")
+                .field::<ark_bn254::Fr>("bn254-fr")
+                .field::<ark_bn254::Fq>("bn254-fq");
+        }
+        registry
+    }
+
+    /// Registers the curve of the points `C` as `name`.
+    pub fn curve<C>(mut self, name: &str) -> Self
+    where C: GpuCurveAffine + 'static {
+        self.curves.insert(name.to_owned(), add_curve::<C>);
+        self
+    }
+
+    /// Registers the field `F` as `name`.
+    pub fn field<F>(mut self, name: &str) -> Self
+    where F: GpuField + 'static {
+        self.fields.insert(name.to_owned(), add_field::<F>);
+        self
+    }
+}
+
+fn add_curve<C>(
+    builder: SourceBuilder, kernels: &[CurveKernel],
+) -> SourceBuilder
+where C: GpuCurveAffine + 'static {
+    kernels
+        .iter()
+        .fold(builder.add_ec::<C>(), |builder, kernel| match kernel {
+            CurveKernel::Ec => builder,
+            CurveKernel::EcFft => builder.add_ec_fft::<C>(),
+            CurveKernel::Multiexp => builder.add_multiexp::<C>(),
+        })
+}
+
+fn add_field<F>(
+    builder: SourceBuilder, kernels: &[FieldKernel],
+) -> SourceBuilder
+where F: GpuField + 'static {
+    kernels.iter().fold(
+        builder.add_field::<F>(),
+        |builder, kernel| match kernel {
+            FieldKernel::Fft => builder.add_fft::<F>(),
+        },
+    )
+}
+
+fn invalid(path: Option<&Path>, message: impl Into<String>) -> BuildError {
+    BuildError::InvalidConfig {
+        path: path.map(Path::to_owned),
+        message: message.into(),
+    }
+}
+
+fn unknown<'a>(
+    path: Option<&Path>, kind: &str, name: &str,
+    known: impl Iterator<Item = &'a String>,
+) -> BuildError {
+    let known: Vec<_> = known.map(String::as_str).collect();
+    invalid(
+        path,
+        format!(
+            "unknown {} `{}`, the known ones are: {}",
+            kind,
+            name,
+            known.join(", ")
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chosen_ark_suite::{Fr, G1Affine};
+
+    const MANIFEST: &str = r#"
+        [package]
+        name = "kernels"
+
+        [package.metadata.ag-build]
+        targets = ["cuda"]
+        limb-bits = 32
+
+        [[package.metadata.ag-build.curves]]
+        name = "bls12-381-g1"
+        kernels = ["multiexp"]
+        feature = "bls12-381"
+
+        [[package.metadata.ag-build.fields]]
+        name = "bls12-381-fr"
+        kernels = ["fft"]
+    "#;
+
+    fn registry() -> TypeRegistry {
+        TypeRegistry::new()
+            .curve::<G1Affine>("bls12-381-g1")
+            .field::<Fr>("bls12-381-fr")
+    }
+
+    #[test]
+    fn test_from_manifest() {
+        let config = KernelConfig::from_manifest(MANIFEST).unwrap();
+        assert_eq!(config.targets(), [Target::Cuda]);
+        assert_eq!(config.limb_bits, Some(32));
+        assert_eq!(
+            config.curves,
+            [CurveConfig {
+                name: "bls12-381-g1".to_owned(),
+                kernels: vec![CurveKernel::Multiexp],
+                feature: Some("bls12-381".to_owned()),
+            }]
+        );
+        assert_eq!(config.fields[0].kernels, [FieldKernel::Fft]);
+    }
+
+    #[test]
+    fn test_features() {
+        let config = KernelConfig::from_manifest(MANIFEST).unwrap();
+        let kernels = |enabled: bool| {
+            let builder = config
+                .source_builder_with_features(&registry(), |feature| {
+                    feature == "bls12-381" && enabled
+                })
+                .unwrap();
+            let kernels = builder.kernels();
+            kernels.into_iter().map(|k| k.template).collect::<Vec<_>>()
+        };
+
+        let all = kernels(true);
+        assert!(all.contains(&"POINT_multiexp".to_owned()));
+        assert!(all.contains(&"FIELD_radix_fft".to_owned()));
+        let fields_only = kernels(false);
+        assert!(!fields_only.contains(&"POINT_multiexp".to_owned()));
+        assert!(fields_only.contains(&"FIELD_radix_fft".to_owned()));
+    }
+
+    #[test]
+    fn test_errors() {
+        let config = KernelConfig::parse(
+            "[[curves]]\nname = \"bn254-g1\"\nkernels = [\"multiexp\"]",
+        )
+        .unwrap();
+        let error = config.source_builder(&registry()).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "invalid kernel configuration: unknown curve `bn254-g1`, the \
+             known ones are: bls12-381-g1"
+        );
+
+        assert!(
+            KernelConfig::parse("[[curves]]\nname = \"x\"\nsize = 1").is_err()
+        );
+        assert!(KernelConfig::parse("limb-bits = 16")
+            .unwrap()
+            .source_builder(&registry())
+            .is_err());
+        assert!(KernelConfig::from_manifest("[package]").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+/// An error of generating or compiling the kernels.
+#[derive(Debug)]
+pub enum BuildError {
+    /// The compiler cannot be run, usually because the toolkit is not
+    /// installed.
+    ToolchainMissing { tool: String, source: io::Error },
+    /// The compiler rejected the generated source.
+    Compiler {
+        source_path: PathBuf,
+        status: ExitStatus,
+        /// What the compiler printed to stderr.
+        diagnostics: String,
+    },
+    /// A generated file cannot be written.
+    Io { path: PathBuf, source: io::Error },
+    /// An environment variable overriding a build option has an invalid
+    /// value.
+    InvalidEnv { name: String, value: String },
+    /// The kernel configuration is invalid, see
+    /// [`KernelConfig`](crate::KernelConfig).
+    InvalidConfig {
+        /// The file the configuration was read from.
+        path: Option<PathBuf>,
+        message: String,
+    },
+    /// The constants of a field are inconsistent, so that the generated
+    /// arithmetic would compute wrong results.
+    InvalidField {
+        /// The name of the field in the generated source.
+        name: String,
+        message: String,
+    },
+}
+
+pub type BuildResult<T> = Result<T, BuildError>;
+
+impl BuildError {
+    /// Maps an IO error on `path`, for use with `map_err`.
+    pub(crate) fn io(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| BuildError::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    /// Sets the path of an [`BuildError::InvalidConfig`] without one.
+    pub(crate) fn with_config_path(mut self, config_path: &Path) -> Self {
+        if let BuildError::InvalidConfig {
+            path: path @ None, ..
+        } = &mut self
+        {
+            *path = Some(config_path.to_owned());
+        }
+        self
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::ToolchainMissing { tool, source } => write!(
+                f,
+                "cannot run {}: {}. Install the NVIDIA toolkit or disable \
+                 the `cuda` feature",
+                tool, source
+            ),
+            BuildError::Compiler {
+                source_path,
+                status,
+                diagnostics,
+            } => write!(
+                f,
+                "the kernel compiler failed with {}. See the kernel source \
+                 at {} and its source map next to it\n{}",
+                status,
+                source_path.display(),
+                diagnostics.trim_end()
+            ),
+            BuildError::Io { path, source } => {
+                write!(f, "cannot write {}: {}", path.display(), source)
+            }
+            BuildError::InvalidEnv { name, value } => {
+                write!(f, "invalid value `{}` of {}", value, name)
+            }
+            BuildError::InvalidConfig {
+                path: Some(path),
+                message,
+            } => write!(
+                f,
+                "invalid kernel configuration in {}: {}",
+                path.display(),
+                message
+            ),
+            BuildError::InvalidConfig {
+                path: None,
+                message,
+            } => write!(f, "invalid kernel configuration: {}", message),
+            BuildError::InvalidField { name, message } => {
+                write!(f, "invalid constants of field `{}`: {}", name, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::ToolchainMissing { source, .. }
+            | BuildError::Io { source, .. } => Some(source),
+            BuildError::Compiler { .. }
+            | BuildError::InvalidEnv { .. }
+            | BuildError::InvalidConfig { .. }
+            | BuildError::InvalidField { .. } => None,
+        }
+    }
+}
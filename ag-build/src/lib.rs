@@ -19,11 +19,46 @@
 //!
 //! The `ag_build::generate()` takes care of the actual code
 //! generation/compilation. It will automatically create a CUDA and/or OpenCL
-//! kernel. It will define two environment variables, which are meant for
+//! kernel. It will define environment variables, which are meant for
 //! internal use. `_EC_GPU_CUDA_KERNEL_FATBIN` that points to the compiled CUDA
 //! kernel, and `_EC_GPU_OPENCL_KERNEL_SOURCE` that points to the generated
 //! OpenCL source.
 //!
+//! [`try_generate`] returns a [`BuildError`] instead, and
+//! [`generate_with_policy`] lets build scripts continue without the CUDA
+//! toolkit: with [`BuildPolicy::Stub`] a stub is embedded in place of the
+//! kernel, loading it fails at run time with the build error, see
+//! `ag_cuda_proxy::LoadError::Stub`. Before the
+//! kernels are generated, the constants of every field are checked, an
+//! inconsistent one fails with [`BuildError::InvalidField`], see
+//! [`SourceBuilder::check_32_bit_limbs`].
+//!
+//! Instead of calling the [`SourceBuilder`] in `build.rs`, the kernels can
+//! be listed in the `Cargo.toml` of the crate and generated with
+//! [`generate_from_metadata`], see [`config`].
+//!
+//! The nvcc options are set with [`SourceBuilder::cuda_options`] and can be
+//! overridden with environment variables, see [`CudaBuildOptions`]. Compiled
+//! kernels are cached per user, in `AG_BUILD_CACHE_DIR` or the user's cache
+//! directory, so identical kernels are compiled once per machine.
+//!
+//! With CUDA it also writes a JSON description of every kernel entry point
+//! (see [`SourceBuilder::kernels`]) and typed Rust launchers generated from
+//! it. `_EC_GPU_CUDA_KERNEL_LAUNCHERS` points to the launchers, which are
+//! meant to be included with
+//! `include!(env!("_EC_GPU_CUDA_KERNEL_LAUNCHERS"))`.
+//! `_EC_GPU_CUDA_KERNEL_MANIFEST` points to the [`KernelManifest`] of the
+//! fatbin, which `ag_cuda_proxy::CudaWorkspace::from_bytes_with_manifest`
+//! checks when loading it, so that a fatbin lacking a kernel fails to load
+//! instead of failing at the first launch.
+//!
+//! Kernels applying an operation to every element of field vectors need not
+//! be written by hand, they are generated from an [`Expr`], see
+//! [`ElementwiseKernel`].
+//!
+//! Fields and curves without an arkworks type are defined by their modulus
+//! and coefficients, see [`FieldSpec`] and [`CurveSpec`].
+//!
 //!
 //! Feature flags
 //! -------------
@@ -34,20 +69,150 @@
 //! [fatbin]: https://en.wikipedia.org/wiki/Fat_binary#Heterogeneous_computing
 //! [feature flags]: https://doc.rust-lang.org/cargo/reference/manifest.html#the-features-section
 
-pub use source::SourceBuilder;
+#[cfg(test)]
+extern crate ark_bls12_381 as chosen_ark_suite;
+
+pub use config::{
+    CurveConfig, CurveKernel, FieldConfig, FieldKernel, KernelConfig, Target,
+    TypeRegistry,
+};
+pub use error::{BuildError, BuildResult};
+pub use options::{CudaArch, CudaBuildOptions};
+pub use source::{
+    AddressSpace, Bindings, CurveSpec, ElementwiseKernel, Expr, FieldConstants,
+    FieldSpec, KernelManifest, KernelParam, KernelSignature, ManifestItem,
+    SourceBuilder, SourceMap, SourceRange, SpecError, TemplateError,
+};
 
+pub mod config;
+mod error;
+mod options;
 mod source;
 
+#[cfg(any(feature = "cuda", feature = "opencl"))]
+mod cache;
 #[cfg(any(feature = "cuda", feature = "opencl"))]
 mod compile;
 
 #[cfg(all(test, any(feature = "cuda", feature = "opencl")))]
 mod tests;
 
-#[allow(unused_variables)]
+/// What a build script does when the kernels cannot be generated, e.g. on a
+/// machine without the CUDA toolkit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BuildPolicy {
+    /// Fails the build with the error.
+    #[default]
+    Fail,
+    /// If the CUDA toolkit is missing, reports the error as a cargo warning
+    /// and embeds a stub in place of the CUDA kernel. Loading the stub fails
+    /// at run time with the error. Other errors fail the build.
+    Stub,
+    /// Like [`BuildPolicy::Stub`], and generates the OpenCL source, even if
+    /// the `opencl` feature is disabled.
+    OpenClFallback,
+}
+
+impl BuildPolicy {
+    /// The policy set with the `AG_BUILD_POLICY` environment variable, one of
+    /// `fail`, `stub` and `opencl`. Panics on other values.
+    pub fn from_env() -> Option<Self> {
+        println!("cargo:rerun-if-env-changed=AG_BUILD_POLICY");
+        let policy = std::env::var("AG_BUILD_POLICY").ok()?;
+        Some(match policy.as_str() {
+            "fail" => BuildPolicy::Fail,
+            "stub" => BuildPolicy::Stub,
+            "opencl" => BuildPolicy::OpenClFallback,
+            _ => panic!(
+                "AG_BUILD_POLICY must be `fail`, `stub` or `opencl`, not `{}`",
+                policy
+            ),
+        })
+    }
+}
+
+/// Generates the kernels, panicking on errors. See [`try_generate`].
 pub fn generate(source_builder: &SourceBuilder) {
-    #[cfg(feature = "cuda")]
-    compile::generate_cuda(source_builder);
-    #[cfg(feature = "opencl")]
-    compile::generate_opencl(source_builder);
+    generate_with_policy(source_builder, BuildPolicy::Fail)
+}
+
+/// Generates the kernels, handling errors according to `policy`. Panics if
+/// the error cannot be handled.
+pub fn generate_with_policy(
+    source_builder: &SourceBuilder, policy: BuildPolicy,
+) {
+    generate_targets(source_builder, &Target::enabled(), policy)
+}
+
+/// Generates the kernels configured in the `[package.metadata.ag-build]`
+/// table of the crate being built, with the curves and fields of
+/// [`TypeRegistry::builtin`], see [`config`]. Panics if the configuration is
+/// invalid or the error cannot be handled according to `policy`.
+pub fn generate_from_metadata(policy: BuildPolicy) {
+    let config =
+        KernelConfig::from_cargo_metadata().unwrap_or_else(|e| panic!("{}", e));
+    generate_config(&config, &TypeRegistry::builtin(), policy)
+}
+
+/// Generates the kernels of `config` for its targets, see
+/// [`generate_from_metadata`].
+pub fn generate_config(
+    config: &KernelConfig, registry: &TypeRegistry, policy: BuildPolicy,
+) {
+    let source_builder = config
+        .source_builder(registry)
+        .unwrap_or_else(|e| panic!("{}", e));
+    generate_targets(&source_builder, &config.targets(), policy)
+}
+
+#[allow(unused_variables)]
+fn generate_targets(
+    source_builder: &SourceBuilder, targets: &[Target], policy: BuildPolicy,
+) {
+    #[cfg(any(feature = "cuda", feature = "opencl"))]
+    if let Err(error) =
+        compile::generate_each(source_builder, targets, policy, |target| {
+            try_generate_targets(source_builder, &[target])
+        })
+    {
+        panic!("{}", error);
+    }
+}
+
+/// Generates the kernels of the enabled features and sets the environment
+/// variables pointing to them.
+pub fn try_generate(source_builder: &SourceBuilder) -> BuildResult<()> {
+    try_generate_targets(source_builder, &Target::enabled())
+}
+
+/// Generates the kernels for `targets` and sets the environment variables
+/// pointing to them. The CUDA target requires the `cuda` feature.
+#[allow(unused_variables)]
+pub fn try_generate_targets(
+    source_builder: &SourceBuilder, targets: &[Target],
+) -> BuildResult<()> {
+    for target in targets {
+        match target {
+            #[cfg(feature = "cuda")]
+            Target::Cuda => {
+                compile::generate_cuda(source_builder)?;
+            }
+            #[cfg(not(feature = "cuda"))]
+            Target::Cuda => {
+                return Err(BuildError::InvalidConfig {
+                    path: None,
+                    message: "the `cuda` target requires the `cuda` feature \
+                              of ag-build"
+                        .to_owned(),
+                })
+            }
+            #[cfg(any(feature = "cuda", feature = "opencl"))]
+            Target::Opencl => {
+                compile::generate_opencl(source_builder)?;
+            }
+            #[cfg(not(any(feature = "cuda", feature = "opencl")))]
+            Target::Opencl => {}
+        }
+    }
+    Ok(())
 }
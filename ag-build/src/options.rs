@@ -0,0 +1,331 @@
+//! The options the CUDA kernel is compiled with.
+//!
+//! The options are resolved in this order, later steps take precedence:
+//!
+//! 1. The defaults of [`CudaBuildOptions::new`].
+//! 2. The options set on the builder and passed with
+//!    [`SourceBuilder::cuda_options`](crate::SourceBuilder::cuda_options).
+//! 3. The `AG_BUILD_CUDA_ARCHS` (comma separated, e.g. `86,80` or
+//!    `sm_86,sm_80`), `AG_BUILD_CUDA_OPT_LEVEL` (`0` to `6`),
+//!    `AG_BUILD_CUDA_LINE_INFO` and `AG_BUILD_CUDA_EMBED_PTX` (`0` or `1`)
+//!    environment variables, each replacing a single option.
+//! 4. `EC_GPU_CUDA_NVCC_ARGS`, which replaces all the arguments except the
+//!    source and the output file.
+//!
+//! The resolved arguments are part of the digest the compiled kernel is named
+//! by, so changing any of them recompiles the kernel.
+
+// Only the builder is used without the `cuda` feature.
+#![cfg_attr(not(feature = "cuda"), allow(dead_code))]
+
+use crate::{BuildError, BuildResult};
+
+use std::{env, fmt, ops::RangeInclusive, process::Command, str::FromStr};
+
+/// The variables overriding the options.
+pub(crate) const ENV_VARS: [&str; 5] = [
+    "AG_BUILD_CUDA_ARCHS",
+    "AG_BUILD_CUDA_OPT_LEVEL",
+    "AG_BUILD_CUDA_LINE_INFO",
+    "AG_BUILD_CUDA_EMBED_PTX",
+    "EC_GPU_CUDA_NVCC_ARGS",
+];
+
+/// The `--optimize` levels, which nvcc passes on to the host compiler. The
+/// host compilers treat the levels above 3 like 3.
+const OPT_LEVELS: RangeInclusive<u32> = 0..=6;
+
+/// A GPU architecture by its compute capability, e.g. `CudaArch::new(8, 6)`
+/// for `sm_86`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CudaArch {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl CudaArch {
+    pub const fn new(major: u32, minor: u32) -> Self { Self { major, minor } }
+}
+
+impl fmt::Display for CudaArch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for CudaArch {
+    type Err = ();
+
+    /// Parses `86`, `sm_86` or `compute_86`, the last digit is the minor
+    /// version.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let digits = s
+            .trim()
+            .trim_start_matches("sm_")
+            .trim_start_matches("compute_");
+        if digits.len() < 2 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(());
+        }
+        let (major, minor) = digits.split_at(digits.len() - 1);
+        let parse = |digits: &str| digits.parse().map_err(|_| ());
+        Ok(Self::new(parse(major)?, parse(minor)?))
+    }
+}
+
+/// The options of compiling the CUDA kernel with nvcc.
+///
+/// ```
+/// use ag_build::{CudaArch, CudaBuildOptions};
+///
+/// let options = CudaBuildOptions::new()
+///     .archs([CudaArch::new(8, 9), CudaArch::new(8, 0)])
+///     .embed_ptx(true)
+///     .define("WINDOW_SIZE", Some("10"));
+/// assert!(options
+///     .nvcc_args()
+///     .contains(&"--generate-code=arch=compute_89,code=compute_89".into()));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CudaBuildOptions {
+    archs: Vec<CudaArch>,
+    embed_ptx: bool,
+    opt_level: u32,
+    line_info: bool,
+    defines: Vec<(String, Option<String>)>,
+    extra_args: Vec<String>,
+}
+
+impl Default for CudaBuildOptions {
+    fn default() -> Self { Self::new() }
+}
+
+impl CudaBuildOptions {
+    /// Compiles for `sm_86`, `sm_80` and `sm_75` without PTX, at optimization
+    /// level 6 and without line info.
+    pub fn new() -> Self {
+        Self {
+            archs: vec![
+                CudaArch::new(8, 6),
+                CudaArch::new(8, 0),
+                CudaArch::new(7, 5),
+            ],
+            embed_ptx: false,
+            opt_level: 6,
+            line_info: false,
+            defines: Vec::new(),
+            extra_args: Vec::new(),
+        }
+    }
+
+    /// Replaces the architectures to generate code for.
+    pub fn archs(mut self, archs: impl IntoIterator<Item = CudaArch>) -> Self {
+        self.archs = archs.into_iter().collect();
+        self
+    }
+
+    /// Embeds the PTX of the newest architecture, which the driver compiles
+    /// for devices newer than all the architectures.
+    pub fn embed_ptx(mut self, embed_ptx: bool) -> Self {
+        self.embed_ptx = embed_ptx;
+        self
+    }
+
+    /// The `--optimize` level, from 0 to 6. Panics on other levels.
+    pub fn opt_level(mut self, opt_level: u32) -> Self {
+        assert!(
+            OPT_LEVELS.contains(&opt_level),
+            "the optimization level must be from 0 to 6, not {}",
+            opt_level
+        );
+        self.opt_level = opt_level;
+        self
+    }
+
+    /// Generates line info for profilers, without affecting the optimization.
+    pub fn line_info(mut self, line_info: bool) -> Self {
+        self.line_info = line_info;
+        self
+    }
+
+    /// Defines a preprocessor macro, `-DNAME` or `-DNAME=VALUE`.
+    pub fn define(mut self, name: &str, value: Option<&str>) -> Self {
+        self.defines
+            .push((name.to_owned(), value.map(str::to_owned)));
+        self
+    }
+
+    /// Appends an argument to the generated ones.
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.extra_args.push(arg.to_owned());
+        self
+    }
+
+    /// Applies the `AG_BUILD_CUDA_*` environment variables.
+    pub(crate) fn with_env_overrides(mut self) -> BuildResult<Self> {
+        if let Some(archs) = env_var("AG_BUILD_CUDA_ARCHS") {
+            self.archs = archs
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid_env("AG_BUILD_CUDA_ARCHS", &archs))?;
+        }
+        if let Some(level) = env_var("AG_BUILD_CUDA_OPT_LEVEL") {
+            self.opt_level =
+                parse_opt_level("AG_BUILD_CUDA_OPT_LEVEL", &level)?;
+        }
+        if let Some(flag) = env_var("AG_BUILD_CUDA_LINE_INFO") {
+            self.line_info = parse_flag("AG_BUILD_CUDA_LINE_INFO", &flag)?;
+        }
+        if let Some(flag) = env_var("AG_BUILD_CUDA_EMBED_PTX") {
+            self.embed_ptx = parse_flag("AG_BUILD_CUDA_EMBED_PTX", &flag)?;
+        }
+        Ok(self)
+    }
+
+    /// The nvcc arguments, except for the source and the output file. The
+    /// first architecture is also the `--gpu-architecture`.
+    pub fn nvcc_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--fatbin".to_owned(),
+            format!("--optimize={}", self.opt_level),
+            // Compile with as many threads as CPUs are available.
+            "--threads=0".to_owned(),
+        ];
+        if let Some(first) = self.archs.first() {
+            args.push(format!("--gpu-architecture=sm_{}", first));
+        }
+        for arch in &self.archs {
+            args.push(format!(
+                "--generate-code=arch=compute_{},code=sm_{}",
+                arch, arch
+            ));
+        }
+        if let Some(newest) = self.archs.iter().max().filter(|_| self.embed_ptx)
+        {
+            args.push(format!(
+                "--generate-code=arch=compute_{},code=compute_{}",
+                newest, newest
+            ));
+        }
+        if self.line_info {
+            args.push("--generate-line-info".to_owned());
+        }
+        for (name, value) in &self.defines {
+            args.push(match value {
+                Some(value) => format!("-D{}={}", name, value),
+                None => format!("-D{}", name),
+            });
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+
+    /// The nvcc command with all the overrides applied.
+    pub(crate) fn nvcc_command(&self) -> BuildResult<Command> {
+        if let Some(args) = env_var("EC_GPU_CUDA_NVCC_ARGS") {
+            return Ok(execute::command(format!("nvcc {}", args)));
+        }
+        let mut command = Command::new("nvcc");
+        command.args(self.clone().with_env_overrides()?.nvcc_args());
+        Ok(command)
+    }
+}
+
+fn env_var(name: &str) -> Option<String> { env::var(name).ok() }
+
+fn invalid_env(name: &str, value: &str) -> BuildError {
+    BuildError::InvalidEnv {
+        name: name.to_owned(),
+        value: value.to_owned(),
+    }
+}
+
+fn parse_opt_level(name: &str, value: &str) -> BuildResult<u32> {
+    value
+        .parse()
+        .ok()
+        .filter(|level| OPT_LEVELS.contains(level))
+        .ok_or_else(|| invalid_env(name, value))
+}
+
+fn parse_flag(name: &str, value: &str) -> BuildResult<bool> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(invalid_env(name, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nvcc_args() {
+        assert_eq!(
+            CudaBuildOptions::new().nvcc_args(),
+            [
+                "--fatbin",
+                "--optimize=6",
+                "--threads=0",
+                "--gpu-architecture=sm_86",
+                "--generate-code=arch=compute_86,code=sm_86",
+                "--generate-code=arch=compute_80,code=sm_80",
+                "--generate-code=arch=compute_75,code=sm_75",
+            ]
+        );
+
+        let args = CudaBuildOptions::new()
+            .archs([CudaArch::new(7, 0), CudaArch::new(9, 0)])
+            .embed_ptx(true)
+            .opt_level(1)
+            .line_info(true)
+            .define("A", None)
+            .define("B", Some("2"))
+            .arg("--use_fast_math")
+            .nvcc_args();
+        assert_eq!(
+            args[1..],
+            [
+                "--optimize=1",
+                "--threads=0",
+                "--gpu-architecture=sm_70",
+                "--generate-code=arch=compute_70,code=sm_70",
+                "--generate-code=arch=compute_90,code=sm_90",
+                "--generate-code=arch=compute_90,code=compute_90",
+                "--generate-line-info",
+                "-DA",
+                "-DB=2",
+                "--use_fast_math",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_opt_level() {
+        assert_eq!(parse_opt_level("LEVEL", "0").unwrap(), 0);
+        assert_eq!(parse_opt_level("LEVEL", "6").unwrap(), 6);
+        for level in ["", "-1", "7", "3.0", "O3"] {
+            match parse_opt_level("LEVEL", level) {
+                Err(BuildError::InvalidEnv { name, value }) => {
+                    assert_eq!(
+                        (name.as_str(), value.as_str()),
+                        ("LEVEL", level)
+                    );
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_arch() {
+        for arch in ["86", "sm_86", "compute_86", " 86"] {
+            assert_eq!(arch.parse(), Ok(CudaArch::new(8, 6)));
+        }
+        assert_eq!("100".parse(), Ok(CudaArch::new(10, 0)));
+        for arch in ["", "8", "sm_8x", "8.6"] {
+            assert_eq!(arch.parse::<CudaArch>(), Err(()));
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! The few operations on unsigned integers needed to compute the Montgomery
+//! constants of a field, on little-endian 32-bit limbs.
+
+use std::cmp::Ordering;
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number, `_` may separate
+/// the digits. The result has no leading zero limbs.
+pub(crate) fn parse(number: &str) -> Option<Vec<u32>> {
+    let (digits, radix) = match number.strip_prefix("0x") {
+        Some(digits) => (digits, 16),
+        None => (number, 10),
+    };
+    let mut result = Vec::new();
+    let mut empty = true;
+    for c in digits.chars().filter(|&c| c != '_') {
+        let digit = c.to_digit(radix)?;
+        mul_add(&mut result, radix, digit);
+        empty = false;
+    }
+    if empty {
+        return None;
+    }
+    Some(normalized(result))
+}
+
+/// Sets `a` to `a * factor + summand`.
+fn mul_add(a: &mut Vec<u32>, factor: u32, summand: u32) {
+    let mut carry = summand as u64;
+    for limb in a.iter_mut() {
+        let product = *limb as u64 * factor as u64 + carry;
+        *limb = product as u32;
+        carry = product >> 32;
+    }
+    if carry != 0 {
+        a.push(carry as u32);
+    }
+}
+
+/// Removes the leading zero limbs.
+pub(crate) fn normalized(mut a: Vec<u32>) -> Vec<u32> {
+    while a.last() == Some(&0) {
+        a.pop();
+    }
+    a
+}
+
+/// The number of significant bits.
+pub(crate) fn bits(a: &[u32]) -> usize {
+    match a.iter().rposition(|&limb| limb != 0) {
+        Some(i) => (i + 1) * 32 - a[i].leading_zeros() as usize,
+        None => 0,
+    }
+}
+
+/// Compares numbers of any length.
+pub(crate) fn cmp(a: &[u32], b: &[u32]) -> Ordering {
+    let len = a.len().max(b.len());
+    let limb = |x: &[u32], i: usize| x.get(i).copied().unwrap_or(0);
+    (0..len)
+        .rev()
+        .map(|i| limb(a, i).cmp(&limb(b, i)))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Sets `a` to `a - b`, `a` must not be smaller than `b`.
+fn sub_assign(a: &mut [u32], b: &[u32]) {
+    let mut borrow = 0u64;
+    for (i, limb) in a.iter_mut().enumerate() {
+        let subtrahend = b.get(i).copied().unwrap_or(0) as u64 + borrow;
+        let (difference, overflow) = (*limb as u64).overflowing_sub(subtrahend);
+        *limb = difference as u32;
+        borrow = overflow as u64;
+    }
+    debug_assert_eq!(borrow, 0);
+}
+
+/// `2^exponent mod modulus` with as many limbs as the modulus.
+pub(crate) fn pow2_mod(exponent: usize, modulus: &[u32]) -> Vec<u32> {
+    // Doubling a number below the modulus needs one more limb.
+    let mut result = vec![0; modulus.len() + 1];
+    result[0] = 1;
+    if cmp(&result, modulus).is_ge() {
+        sub_assign(&mut result, modulus);
+    }
+    for _ in 0..exponent {
+        let mut carry = 0;
+        for limb in result.iter_mut() {
+            let shifted = (*limb << 1) | carry;
+            carry = *limb >> 31;
+            *limb = shifted;
+        }
+        if cmp(&result, modulus).is_ge() {
+            sub_assign(&mut result, modulus);
+        }
+    }
+    result.truncate(modulus.len());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("0"), Some(vec![]));
+        assert_eq!(parse("4294967297"), Some(vec![1, 1]));
+        assert_eq!(parse("0x1_0000_0001"), Some(vec![1, 1]));
+        assert_eq!(parse("0xffffffff"), Some(vec![u32::MAX]));
+        for invalid in ["", "0x", "12a", "-1", "0x1g"] {
+            assert_eq!(parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_pow2_mod() {
+        assert_eq!(bits(&[0, 1, 0]), 33);
+        assert_eq!(pow2_mod(0, &[7]), [1]);
+        assert_eq!(pow2_mod(5, &[7]), [4]);
+        // 2^64 mod (2^32 + 1) = 1
+        assert_eq!(pow2_mod(64, &[1, 1]), [1, 0]);
+        assert_eq!(pow2_mod(0, &[1]), [0]);
+    }
+}
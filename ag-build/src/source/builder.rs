@@ -1,12 +1,24 @@
 //! Builder to create the source code of a GPU kernel.
 
-use std::{collections::BTreeSet, fmt::Write};
+use std::collections::BTreeSet;
 
 use super::{
+    check::check_field,
+    elementwise::ElementwiseKernel,
+    engine::{Bindings, TemplateError},
+    launcher::launchers,
     limb::Limb32Or64,
-    synthesis::{Ec, EcFft, Fft, Field, Multiexp, NameAndSource},
+    manifest::{FieldConstants, KernelManifest, ManifestItem},
+    map::{Segment, SourceMap, SourceWriter},
+    signature::KernelSignature,
+    spec::{CurveSpec, FieldSpec},
+    synthesis::{
+        CurveNames, Ec, EcFft, Elementwise, Fft, Field, Multiexp,
+        NameAndSource, SpecField, UserTemplate,
+    },
     template::*,
 };
+use crate::{BuildResult, CudaBuildOptions};
 use ag_types::{GpuCurveAffine, GpuField};
 
 // In the `HashSet`s the concrete types cannot be used, as each item of the set
@@ -14,7 +26,6 @@ use ag_types::{GpuCurveAffine, GpuField};
 // We distinguish between extension fields and other fields as sub-fields need
 // to be defined first in the source code (due to being C, where the order of
 // declaration matters).
-#[derive(Default)]
 pub struct SourceBuilder {
     /// The [`Field`]s that are used in this kernel.
     fields: BTreeSet<Box<dyn NameAndSource>>,
@@ -28,8 +39,38 @@ pub struct SourceBuilder {
     /// The [`Multiexp`]s that are used in this kernel.
     multiexps: BTreeSet<Box<dyn NameAndSource>>,
     others: BTreeSet<Box<dyn NameAndSource>>,
+    /// The templates added with [`SourceBuilder::add_template`].
+    templates: BTreeSet<Box<dyn NameAndSource>>,
+    /// The kernels added with [`SourceBuilder::add_elementwise`].
+    elementwise: BTreeSet<Box<dyn NameAndSource>>,
     /// Additional source that is appended at the end of the generated source.
     extra_sources: Vec<String>,
+    /// The options the CUDA kernel is compiled with.
+    cuda_options: CudaBuildOptions,
+    /// Whether to emit `#line` directives pointing at the templates.
+    line_directives: bool,
+    /// The size of the limbs if it is not the default of the target.
+    limb_size: Option<Limb32Or64>,
+}
+
+impl Default for SourceBuilder {
+    fn default() -> Self {
+        Self {
+            fields: BTreeSet::new(),
+            extension_fields: BTreeSet::new(),
+            ffts: BTreeSet::new(),
+            ec: BTreeSet::new(),
+            ec_ffts: BTreeSet::new(),
+            multiexps: BTreeSet::new(),
+            others: BTreeSet::new(),
+            templates: BTreeSet::new(),
+            elementwise: BTreeSet::new(),
+            extra_sources: Vec::new(),
+            cuda_options: CudaBuildOptions::default(),
+            line_directives: true,
+            limb_size: None,
+        }
+    }
 }
 
 impl SourceBuilder {
@@ -58,7 +99,7 @@ impl SourceBuilder {
     pub fn add_fft<F>(self) -> Self
     where F: GpuField + 'static {
         let mut config = self.add_field::<F>();
-        let fft = Fft::<F>::new();
+        let fft = Fft::new(F::name());
         config.ffts.insert(Box::new(fft));
         config
     }
@@ -66,7 +107,7 @@ impl SourceBuilder {
     pub fn add_ec<C>(self) -> Self
     where C: GpuCurveAffine + 'static {
         let mut config = self.add_field::<C::Base>().add_field::<C::Scalar>();
-        let ec = Ec::<C>::new();
+        let ec = Ec::new(CurveNames::of::<C>());
         config.ec.insert(Box::new(ec));
         config
     }
@@ -78,7 +119,7 @@ impl SourceBuilder {
     pub fn add_ec_fft<C>(self) -> Self
     where C: GpuCurveAffine + 'static {
         let mut config = self.add_ec::<C>();
-        let ec_fft = EcFft::<C>::new();
+        let ec_fft = EcFft::new(CurveNames::of::<C>());
         config.ec_ffts.insert(Box::new(ec_fft));
         config
     }
@@ -93,7 +134,54 @@ impl SourceBuilder {
             panic!("The source code has not been tested on opencl");
         }
         let mut config = self.add_ec::<C>();
-        let multiexp = Multiexp::<C>::new();
+        let multiexp = Multiexp::new(CurveNames::of::<C>());
+        config.multiexps.insert(Box::new(multiexp));
+        config
+    }
+
+    /// Add a field defined by its modulus instead of an arkworks type, see
+    /// [`FieldSpec`].
+    pub fn add_field_spec(mut self, spec: &FieldSpec) -> Self {
+        self.fields.insert(Box::new(SpecField::new(spec.clone())));
+        self
+    }
+
+    /// Like [`SourceBuilder::add_fft`] for a field defined by its modulus.
+    pub fn add_fft_spec(self, spec: &FieldSpec) -> Self {
+        let mut config = self.add_field_spec(spec);
+        let fft = Fft::new(spec.name().to_owned());
+        config.ffts.insert(Box::new(fft));
+        config
+    }
+
+    /// Like [`SourceBuilder::add_ec`] for a curve defined by its
+    /// coefficients, see [`CurveSpec`].
+    pub fn add_ec_spec(self, spec: &CurveSpec) -> Self {
+        let mut config = self
+            .add_field_spec(spec.base())
+            .add_field_spec(spec.scalar());
+        let ec = Ec::new(CurveNames::of_spec(spec));
+        config.ec.insert(Box::new(ec));
+        config
+    }
+
+    /// Like [`SourceBuilder::add_ec_fft`] for a curve defined by its
+    /// coefficients.
+    pub fn add_ec_fft_spec(self, spec: &CurveSpec) -> Self {
+        let mut config = self.add_ec_spec(spec);
+        let ec_fft = EcFft::new(CurveNames::of_spec(spec));
+        config.ec_ffts.insert(Box::new(ec_fft));
+        config
+    }
+
+    /// Like [`SourceBuilder::add_multiexp`] for a curve defined by its
+    /// coefficients.
+    pub fn add_multiexp_spec(self, spec: &CurveSpec) -> Self {
+        if cfg!(feature = "opencl") {
+            panic!("The source code has not been tested on opencl");
+        }
+        let mut config = self.add_ec_spec(spec);
+        let multiexp = Multiexp::new(CurveNames::of_spec(spec));
         config.multiexps.insert(Box::new(multiexp));
         config
     }
@@ -109,6 +197,66 @@ impl SourceBuilder {
         config
     }
 
+    /// Adds a template instantiated with `bindings`, see [`Bindings`] for the
+    /// placeholder syntax. The same template can be added for several fields
+    /// or curves, it is included once for each set of bindings. The templates
+    /// follow the built-in ones, so they can use their functions.
+    ///
+    /// `name` is used as the file name in `#line` directives, so that
+    /// compiler diagnostics point at the template, e.g. pass the path of the
+    /// file the template is read from.
+    ///
+    /// Panics if a placeholder is not bound or a binding is not used, see
+    /// [`SourceBuilder::try_add_template`].
+    ///
+    /// ```
+    /// use ag_build::{Bindings, SourceBuilder};
+    ///
+    /// // Usually bound with `Bindings::name::<F>("FIELD")` to a field `F`
+    /// // that was added to the builder.
+    /// let source = SourceBuilder::new().add_template(
+    ///     "square.cl",
+    ///     "KERNEL void ${FIELD}_square(GLOBAL ${FIELD} *x) {\n\
+    ///        x[GET_GLOBAL_ID()] = ${FIELD}_sqr(x[GET_GLOBAL_ID()]);\n\
+    ///      }\n",
+    ///     Bindings::new().bind("FIELD", "Fr"),
+    /// );
+    /// assert_eq!(source.kernels()[0].name, "Fr_square");
+    /// assert_eq!(source.kernels()[0].template, "FIELD_square");
+    /// ```
+    pub fn add_template(
+        self, name: &str, source: &str, bindings: Bindings,
+    ) -> Self {
+        self.try_add_template(name, source, bindings)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [`SourceBuilder::add_template`], returning an error if the
+    /// template does not match the bindings.
+    pub fn try_add_template(
+        mut self, name: &str, source: &str, bindings: Bindings,
+    ) -> Result<Self, TemplateError> {
+        let template = UserTemplate::new(name, source, bindings)?;
+        self.templates.insert(Box::new(template));
+        Ok(self)
+    }
+
+    /// Adds an elementwise kernel for the field `F`, and the field itself,
+    /// see [`ElementwiseKernel`]. The same kernel can be added for several
+    /// fields.
+    ///
+    /// Panics if an operand is named like the type of `F` or starts with it
+    /// followed by `_`, like the functions and constants of the field.
+    pub fn add_elementwise<F>(self, kernel: ElementwiseKernel) -> Self
+    where F: GpuField + 'static {
+        kernel.assert_field_names_unused(&F::name());
+        let mut config = self.add_field::<F>();
+        config
+            .elementwise
+            .insert(Box::new(Elementwise::<F>::new(kernel)));
+        config
+    }
+
     /// Appends some given source at the end of the generated source.
     ///
     /// This is useful for cases where you use this library as building block,
@@ -120,12 +268,50 @@ impl SourceBuilder {
         self
     }
 
+    /// Sets the options the CUDA kernel is compiled with. They can be
+    /// overridden with environment variables, see [`CudaBuildOptions`].
+    pub fn cuda_options(mut self, options: CudaBuildOptions) -> Self {
+        self.cuda_options = options;
+        self
+    }
+
+    /// Whether to emit `#line` directives, so that compiler diagnostics point
+    /// at the template files. Enabled by default. Without them diagnostics
+    /// point at the generated source, which [`SourceMap`] relates to the
+    /// templates.
+    pub fn line_directives(mut self, enabled: bool) -> Self {
+        self.line_directives = enabled;
+        self
+    }
+
+    /// Generates the kernels with limbs of `bits` bits, 32 or 64, instead of
+    /// the default of the target, 32 for CUDA and 64 for OpenCL. Panics on
+    /// other sizes.
+    pub fn limb_bits(mut self, bits: u32) -> Self {
+        self.limb_size = Some(match bits {
+            32 => Limb32Or64::Limb32,
+            64 => Limb32Or64::Limb64,
+            _ => panic!("limbs have 32 or 64 bits, not {}", bits),
+        });
+        self
+    }
+
+    /// The size of the limbs set with [`SourceBuilder::limb_bits`], or
+    /// `default`.
+    pub(crate) fn limb_size_or(&self, default: Limb32Or64) -> Limb32Or64 {
+        self.limb_size.unwrap_or(default)
+    }
+
+    pub(crate) fn get_cuda_options(&self) -> &CudaBuildOptions {
+        &self.cuda_options
+    }
+
     /// Generate the GPU kernel source code based on the current configuration
     /// with 32-bit limbs.
     ///
     /// On CUDA 32-bit limbs are recommended.
     pub fn build_32_bit_limbs(&self) -> String {
-        self.build(Limb32Or64::Limb32)
+        self.build(Limb32Or64::Limb32).0
     }
 
     /// Generate the GPU kernel source code based on the current configuration
@@ -133,30 +319,162 @@ impl SourceBuilder {
     ///
     /// On OpenCL 32-bit limbs are recommended.
     pub fn build_64_bit_limbs(&self) -> String {
+        self.build(Limb32Or64::Limb64).0
+    }
+
+    /// Like [`SourceBuilder::build_32_bit_limbs`], with the map from the lines
+    /// of the source to the templates and items they come from.
+    pub fn build_32_bit_limbs_with_map(&self) -> (String, SourceMap) {
+        self.build(Limb32Or64::Limb32)
+    }
+
+    /// Like [`SourceBuilder::build_64_bit_limbs`], with the map from the lines
+    /// of the source to the templates and items they come from.
+    pub fn build_64_bit_limbs_with_map(&self) -> (String, SourceMap) {
         self.build(Limb32Or64::Limb64)
     }
 
+    /// Returns the signatures of all kernel entry points in the generated
+    /// source, including those of the appended sources.
+    pub fn kernels(&self) -> Vec<KernelSignature> {
+        let mut kernels: Vec<_> = self
+            .items()
+            .into_iter()
+            .flat_map(|(_, items)| items)
+            .flat_map(|item| {
+                KernelSignature::parse_all(
+                    &item.source(Limb32Or64::Limb32),
+                    &item.bindings(),
+                )
+            })
+            .collect();
+        for source in &self.extra_sources {
+            kernels
+                .extend(KernelSignature::parse_all(source, &Bindings::new()));
+        }
+        kernels
+    }
+
+    /// Generate the description of the source generated with 32-bit limbs,
+    /// see [`KernelManifest`].
+    pub fn manifest_32_bit_limbs(&self) -> KernelManifest {
+        self.manifest(Limb32Or64::Limb32)
+    }
+
+    /// Generate the description of the source generated with 64-bit limbs,
+    /// see [`KernelManifest`].
+    pub fn manifest_64_bit_limbs(&self) -> KernelManifest {
+        self.manifest(Limb32Or64::Limb64)
+    }
+
+    /// Checks that the constants of the fields generated with 32-bit limbs are
+    /// consistent, e.g. that `FIELD_ONE` is `R mod P`. The kernels are
+    /// checked before they are generated with [`crate::generate`].
+    pub fn check_32_bit_limbs(&self) -> BuildResult<()> {
+        self.check(Limb32Or64::Limb32)
+    }
+
+    /// Like [`SourceBuilder::check_32_bit_limbs`] with 64-bit limbs.
+    pub fn check_64_bit_limbs(&self) -> BuildResult<()> {
+        self.check(Limb32Or64::Limb64)
+    }
+
+    /// Generate a JSON description of the kernel entry points, see
+    /// [`SourceBuilder::kernels`].
+    pub fn build_kernels_json(&self) -> String {
+        serde_json::to_string_pretty(&self.kernels())
+            .expect("kernel signatures are serializable")
+    }
+
+    /// Generate typed Rust launchers for the kernel entry points.
+    ///
+    /// The result is meant to be included into a crate that depends on
+    /// `ag-cuda-proxy` and `ag-types`, see [`crate::generate`].
+    pub fn build_launchers(&self) -> String { launchers(&self.kernels()) }
+
+    /// The items of the generated source by their kind, in the order they
+    /// are written.
+    fn items(&self) -> [(&'static str, &BTreeSet<Box<dyn NameAndSource>>); 9] {
+        [
+            ("field", &self.fields),
+            ("extension field", &self.extension_fields),
+            ("curve", &self.ec),
+            ("fft", &self.ffts),
+            ("ec fft", &self.ec_ffts),
+            ("multiexp", &self.multiexps),
+            ("kernel", &self.others),
+            ("template", &self.templates),
+            ("elementwise", &self.elementwise),
+        ]
+    }
+
     /// Generate the GPU kernel source code based on the current configuration.
-    fn build(&self, limb_size: Limb32Or64) -> String {
-        let mut answer = COMMON_SRC.into();
-        write_field(&mut answer, limb_size, &self.fields);
-        write_field(&mut answer, limb_size, &self.extension_fields);
-        write_field(&mut answer, limb_size, &self.ec);
-        write_field(&mut answer, limb_size, &self.ffts);
-        write_field(&mut answer, limb_size, &self.ec_ffts);
-        write_field(&mut answer, limb_size, &self.multiexps);
-        write_field(&mut answer, limb_size, &self.others);
-        write!(answer, "{}", self.extra_sources.join("\n")).unwrap();
-        answer
+    pub(crate) fn build(&self, limb_size: Limb32Or64) -> (String, SourceMap) {
+        let mut writer = SourceWriter::new(self.line_directives);
+        writer.write(None, &Segment::template("common.cl", COMMON_SRC.into()));
+        for (kind, items) in self.items() {
+            write_field(&mut writer, limb_size, kind, items);
+        }
+        for source in &self.extra_sources {
+            let segment = Segment::generated("appended source", source.clone());
+            writer.write(None, &segment);
+        }
+        writer.finish()
+    }
+
+    pub(crate) fn check(&self, limb_size: Limb32Or64) -> BuildResult<()> {
+        for (_, items) in self.items() {
+            for item in items {
+                if let Some(limbs) = item.field_limbs() {
+                    check_field(&item.name(), &limbs, limb_size)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn manifest(&self, limb_size: Limb32Or64) -> KernelManifest {
+        use sha2::{Digest, Sha256};
+
+        let (source, _) = self.build(limb_size);
+        let items = self.items().into_iter().flat_map(|(kind, items)| {
+            items.iter().map(move |item| (kind, item))
+        });
+        let mut manifest = KernelManifest {
+            digest: hex::encode(Sha256::digest(source.as_bytes())),
+            limb_bits: match limb_size {
+                Limb32Or64::Limb32 => 32,
+                Limb32Or64::Limb64 => 64,
+            },
+            items: Vec::new(),
+            fields: Vec::new(),
+            kernels: self.kernels(),
+        };
+        for (kind, item) in items {
+            manifest.items.push(ManifestItem {
+                kind: kind.to_owned(),
+                name: item.name(),
+            });
+            if let Some(limbs) = item.field_limbs() {
+                let constants =
+                    FieldConstants::new(&item.name(), &limbs, limb_size);
+                manifest.fields.push(constants);
+            }
+        }
+        manifest
     }
 }
 
 fn write_field(
-    result: &mut String, limb_size: Limb32Or64,
+    writer: &mut SourceWriter, limb_size: Limb32Or64, kind: &str,
     field: &BTreeSet<Box<dyn NameAndSource>>,
 ) {
     for item in field {
-        write!(result, "{}\n", item.source(limb_size)).unwrap();
+        let label = format!("{} {}", kind, item.name());
+        for segment in item.segments(limb_size) {
+            writer.write(Some(&label), &segment);
+        }
+        writer.push("\n");
     }
-    write!(result, "\n\n").unwrap();
+    writer.push("\n\n");
 }
@@ -0,0 +1,192 @@
+//! Checks of the field constants before they are generated.
+//!
+//! The field arithmetic in `field.cl` relies on `FIELD_ONE` being `R mod P`,
+//! `FIELD_R2` being `R^2 mod P` and `FIELD_INV` being `-P^-1 mod 2^LIMB_BITS`,
+//! with `R = 2^(LIMBS * LIMB_BITS)`, and on the most significant bit of the
+//! modulus being unset, so that the sum of two elements fits the limbs. The
+//! constants come from the `GpuField` implementations, a [`FieldSpec`] and
+//! [`Limb::calc_inv`]. If they are wrong, the kernels compile and compute
+//! wrong results, hence they are recomputed here.
+//!
+//! [`FieldSpec`]: super::spec::FieldSpec
+
+use super::{
+    bigint,
+    limb::{Limb, Limb32, Limb32Or64, Limb64},
+    manifest::to_hex,
+    template::FieldLimbs,
+};
+use crate::{BuildError, BuildResult};
+
+/// Checks the constants of the field named `name`, as generated with limbs
+/// of the size `limb`.
+pub(crate) fn check_field(
+    name: &str, field: &FieldLimbs, limb: Limb32Or64,
+) -> BuildResult<()> {
+    let invalid = |message: String| {
+        Err(BuildError::InvalidField {
+            name: name.to_owned(),
+            message,
+        })
+    };
+    let p = &field.modulus;
+    // A 64-bit limb is made of two 32-bit limbs.
+    let (limb_bits, split_limb) = match limb {
+        Limb32Or64::Limb32 => (32, false),
+        Limb32Or64::Limb64 => (64, p.len() % 2 == 1),
+    };
+
+    if p.is_empty() || split_limb {
+        return invalid(format!(
+            "the modulus has {} 32-bit limbs, which are not a whole number \
+             of {}-bit limbs",
+            p.len(),
+            limb_bits
+        ));
+    }
+    for (constant, limbs) in [("ONE", &field.one), ("R2", &field.r2)] {
+        if limbs.len() != p.len() {
+            return invalid(format!(
+                "{}_{} has {} 32-bit limbs, the modulus has {}",
+                name,
+                constant,
+                limbs.len(),
+                p.len()
+            ));
+        }
+    }
+    if p[p.len() - 1] >> 31 == 1 {
+        return invalid(format!(
+            "the most significant bit of the modulus {} is set, the field \
+             arithmetic requires it to be unset",
+            to_hex(p)
+        ));
+    }
+
+    let (p0, inv, valid) = match limb {
+        Limb32Or64::Limb32 => {
+            let inv = Limb32::calc_inv(Limb32::new(p[0])).value();
+            (p[0] as u64, inv as u64, p[0].wrapping_mul(inv) == u32::MAX)
+        }
+        Limb32Or64::Limb64 => {
+            let p0 = Limb64::from_u32_limbs(&p[..2])[0].value();
+            let inv = Limb64::calc_inv(Limb64::new(p0)).value();
+            (p0, inv, p0.wrapping_mul(inv) == u64::MAX)
+        }
+    };
+    if !valid {
+        return invalid(format!(
+            "{}_INV is {:#x}, but P * {:#x} is not -1 mod 2^{}, the lowest \
+             limb of P being {:#x}",
+            name, inv, inv, limb_bits, p0
+        ));
+    }
+
+    let r_bits = 32 * p.len();
+    let expected = [
+        ("ONE", &field.one, "R", r_bits),
+        ("R2", &field.r2, "R^2", 2 * r_bits),
+    ];
+    for (constant, actual, power, exponent) in expected {
+        let expected = bigint::pow2_mod(exponent, p);
+        if *actual != expected {
+            return invalid(format!(
+                "{}_{} is {}, but {} mod P is {} with R = 2^{}",
+                name,
+                constant,
+                to_hex(actual),
+                power,
+                to_hex(&expected),
+                r_bits
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FieldSpec, SourceBuilder};
+
+    /// The curves the workspace depends on.
+    fn workspace_curves() -> SourceBuilder {
+        SourceBuilder::new()
+            .add_multiexp::<ark_bls12_381::G1Affine>()
+            .add_field::<ark_bls12_381::Fq2>()
+            .add_multiexp::<ark_bn254::G1Affine>()
+            .add_field::<ark_bn254::Fq2>()
+    }
+
+    #[test]
+    fn test_workspace_curves() {
+        let source = workspace_curves();
+        assert_eq!(source.manifest_32_bit_limbs().fields.len(), 4);
+        source.check_32_bit_limbs().unwrap();
+        source.check_64_bit_limbs().unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |field: &FieldLimbs, limb| {
+            let error = check_field("Fq", field, limb).unwrap_err();
+            assert!(matches!(&error, BuildError::InvalidField { .. }));
+            error.to_string()
+        };
+        let fq = || FieldLimbs::of::<ark_bls12_381::Fq>();
+
+        let mut field = fq();
+        field.one[0] ^= 1;
+        let message = error(&field, Limb32Or64::Limb32);
+        assert!(
+            message.starts_with(
+                "invalid constants of field `Fq`: Fq_ONE is 0x15f65ec3fa80e4"
+            ) && message.ends_with(
+                ", but R mod P is 0x15f65ec3fa80e4935c071a97a256ec6d77ce5853\
+                 705257455f48985753c758baebf4000bc40c0002760900000002fffd \
+                 with R = 2^384"
+            ),
+            "{}",
+            message
+        );
+
+        let mut field = fq();
+        field.r2 = fq().one;
+        assert!(error(&field, Limb32Or64::Limb64)
+            .contains("Fq_R2 is 0x15f65ec3fa80e4935c071a97a256ec6d"));
+
+        let mut field = fq();
+        field.modulus[0] -= 1;
+        assert_eq!(
+            error(&field, Limb32Or64::Limb64),
+            "invalid constants of field `Fq`: Fq_INV is 0x0, but P * 0x0 is \
+             not -1 mod 2^64, the lowest limb of P being 0xb9feffffffffaaaa"
+        );
+
+        let mut field = fq();
+        field.modulus[11] |= 1 << 31;
+        assert!(error(&field, Limb32Or64::Limb32).contains(
+            "the most significant bit of the modulus 0x9a0111ea397fe69a"
+        ));
+
+        let mut field = fq();
+        field.r2.pop();
+        assert!(error(&field, Limb32Or64::Limb32)
+            .ends_with("Fq_R2 has 11 32-bit limbs, the modulus has 12"));
+
+        // The Goldilocks field needs its most significant bit.
+        let goldilocks =
+            FieldSpec::new("Goldilocks", "0xffffffff00000001", 64).unwrap();
+        let source = SourceBuilder::new().add_field_spec(&goldilocks);
+        assert!(source.check_64_bit_limbs().is_err());
+
+        let p = FieldSpec::new("P", "0x7fffffffffffffffffffffff", 32).unwrap();
+        let source = SourceBuilder::new().add_field_spec(&p);
+        source.check_32_bit_limbs().unwrap();
+        assert_eq!(
+            source.check_64_bit_limbs().unwrap_err().to_string(),
+            "invalid constants of field `P`: the modulus has 3 32-bit limbs, \
+             which are not a whole number of 64-bit limbs"
+        );
+    }
+}
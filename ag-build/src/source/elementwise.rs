@@ -0,0 +1,486 @@
+//! Elementwise kernels over field vectors, written as Rust expressions.
+//!
+//! An [`Expr`] combines vectors and scalars of a field with the usual
+//! operators. An [`ElementwiseKernel`] names an expression and is added to a
+//! builder for a field with [`SourceBuilder::add_elementwise`], which
+//! generates a kernel computing the expression for every index of the
+//! vectors. Its launcher is generated like the ones of all other kernels, see
+//! [`SourceBuilder::build_launchers`].
+//!
+//! [`SourceBuilder::add_elementwise`]: crate::SourceBuilder::add_elementwise
+//! [`SourceBuilder::build_launchers`]: crate::SourceBuilder::build_launchers
+
+use std::{
+    fmt::Write,
+    ops::{Add, Mul, Neg, Sub},
+};
+
+/// Names that cannot be used for operands: the parameters of the generated
+/// kernel, the macros of the templates and the types the kernel is declared
+/// with.
+const RESERVED: [&str; 17] = [
+    "result",
+    "n",
+    "i",
+    "KERNEL",
+    "DEVICE",
+    "GLOBAL",
+    "LOCAL",
+    "CONSTANT",
+    "CUDA",
+    "OPENCL_NVIDIA",
+    "AMD",
+    "GET_GLOBAL_ID",
+    "GET_GROUP_ID",
+    "GET_LOCAL_ID",
+    "GET_LOCAL_SIZE",
+    "BARRIER_LOCAL",
+    "uint",
+];
+
+/// An expression over field elements, evaluated for every index of the
+/// vectors.
+///
+/// ```
+/// use ag_build::Expr;
+///
+/// let (a, b, c, z) = (
+///     Expr::vector("a"),
+///     Expr::vector("b"),
+///     Expr::vector("c"),
+///     Expr::vector("z"),
+/// );
+/// let mul_sub = a * b - c * z;
+///
+/// let (x, beta, gamma) = (
+///     Expr::vector("x"),
+///     Expr::scalar("beta"),
+///     Expr::scalar("gamma"),
+/// );
+/// let shift_scale = (x + beta) * gamma;
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    /// The element of a device buffer at the current index.
+    Vector(String),
+    /// A value passed to the kernel, the same for all indices.
+    Scalar(String),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+    Square(Box<Expr>),
+    Double(Box<Expr>),
+}
+
+impl Expr {
+    /// A vector parameter of the kernel, named `name`.
+    pub fn vector(name: &str) -> Self { Expr::Vector(name.to_owned()) }
+
+    /// A scalar parameter of the kernel, named `name`.
+    pub fn scalar(name: &str) -> Self { Expr::Scalar(name.to_owned()) }
+
+    /// `self * self`, with the faster squaring of the field.
+    pub fn square(self) -> Self { Expr::Square(Box::new(self)) }
+
+    /// `self + self`, with the faster doubling of the field.
+    pub fn double(self) -> Self { Expr::Double(Box::new(self)) }
+
+    /// The operands in order of their first appearance.
+    fn operands<'e>(&'e self, operands: &mut Vec<&'e Expr>) {
+        match self {
+            Expr::Vector(_) | Expr::Scalar(_) => {
+                if !operands.contains(&self) {
+                    operands.push(self);
+                }
+            }
+            Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) => {
+                a.operands(operands);
+                b.operands(operands);
+            }
+            Expr::Neg(a) | Expr::Square(a) | Expr::Double(a) => {
+                a.operands(operands)
+            }
+        }
+    }
+
+    /// The source computing the expression at index `i`, with the field
+    /// named by the `FIELD` placeholder.
+    fn write_source(&self, out: &mut String) -> std::fmt::Result {
+        let mut call = |function: &str, args: &[&Expr]| {
+            write!(out, "${{FIELD}}_{}(", function)?;
+            for (index, arg) in args.iter().enumerate() {
+                if index > 0 {
+                    write!(out, ", ")?;
+                }
+                arg.write_source(out)?;
+            }
+            write!(out, ")")
+        };
+        match self {
+            Expr::Vector(name) => write!(out, "{}[i]", name),
+            Expr::Scalar(name) => write!(out, "{}", name),
+            Expr::Add(a, b) => call("add", &[a, b]),
+            Expr::Sub(a, b) => call("sub", &[a, b]),
+            Expr::Mul(a, b) => call("mul", &[a, b]),
+            // There is no negation, subtract from zero instead.
+            Expr::Neg(a) => {
+                write!(out, "${{FIELD}}_sub(${{FIELD}}_ZERO, ")?;
+                a.write_source(out)?;
+                write!(out, ")")
+            }
+            Expr::Square(a) => call("sqr", &[a]),
+            Expr::Double(a) => call("double", &[a]),
+        }
+    }
+
+    fn evaluate<F: ark_ff::Field>(
+        &self, index: usize, vectors: &[(&str, &[F])], scalars: &[(&str, F)],
+    ) -> F {
+        let eval = |expr: &Expr| expr.evaluate(index, vectors, scalars);
+        match self {
+            Expr::Vector(name) => {
+                let (_, vector) =
+                    vectors.iter().find(|(n, _)| n == name).unwrap();
+                vector[index]
+            }
+            Expr::Scalar(name) => {
+                let (_, scalar) =
+                    scalars.iter().find(|(n, _)| n == name).unwrap();
+                *scalar
+            }
+            Expr::Add(a, b) => eval(a) + eval(b),
+            Expr::Sub(a, b) => eval(a) - eval(b),
+            Expr::Mul(a, b) => eval(a) * eval(b),
+            Expr::Neg(a) => -eval(a),
+            Expr::Square(a) => eval(a).square(),
+            Expr::Double(a) => eval(a).double(),
+        }
+    }
+}
+
+impl Add for Expr {
+    type Output = Expr;
+
+    fn add(self, rhs: Expr) -> Expr { Expr::Add(Box::new(self), Box::new(rhs)) }
+}
+
+impl Sub for Expr {
+    type Output = Expr;
+
+    fn sub(self, rhs: Expr) -> Expr { Expr::Sub(Box::new(self), Box::new(rhs)) }
+}
+
+impl Mul for Expr {
+    type Output = Expr;
+
+    fn mul(self, rhs: Expr) -> Expr { Expr::Mul(Box::new(self), Box::new(rhs)) }
+}
+
+impl Neg for Expr {
+    type Output = Expr;
+
+    fn neg(self) -> Expr { Expr::Neg(Box::new(self)) }
+}
+
+/// A named kernel computing `result[i] = expr` for every index `i` below
+/// `n`.
+///
+/// The kernel of a field `F` is named `{F}_{name}` and takes the result
+/// buffer, the vectors and the scalars in order of their first appearance in
+/// the expression, and the number of elements `n`:
+///
+/// ```
+/// use ag_build::{ElementwiseKernel, Expr};
+///
+/// let kernel = ElementwiseKernel::new(
+///     "shift_scale",
+///     (Expr::vector("x") + Expr::scalar("beta")) * Expr::scalar("gamma"),
+/// );
+/// assert_eq!(kernel.vectors(), ["x"]);
+/// assert_eq!(kernel.scalars(), ["beta", "gamma"]);
+/// ```
+///
+/// Added for a field `Fr`, the generated launcher takes the buffers on the
+/// device, e.g. with `result` and `x` being `DeviceData` of `n` elements:
+///
+/// ```ignore
+/// workspace.field_shift_scale::<Fr>(config, FieldShiftScaleArgs {
+///     result: (&result).into(),
+///     x: (&x).into(),
+///     beta: ValueArg::new(beta),
+///     gamma: ValueArg::new(gamma),
+///     n: n as u32,
+/// })?
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ElementwiseKernel {
+    name: String,
+    expr: Expr,
+}
+
+impl ElementwiseKernel {
+    /// Panics if the name or an operand is not an identifier, if an operand
+    /// is named like a parameter of the generated kernel (`result`, `n` or
+    /// `i`), a macro of the templates, e.g. `GLOBAL`, or `uint`, or if a name
+    /// is used for both a vector and a scalar.
+    pub fn new(name: &str, expr: Expr) -> Self {
+        assert!(is_identifier(name), "invalid kernel name `{}`", name);
+        let kernel = Self {
+            name: name.to_owned(),
+            expr,
+        };
+        let names: Vec<_> = kernel
+            .operands()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        for (index, name) in names.iter().enumerate() {
+            assert!(
+                is_identifier(name) && !RESERVED.contains(name),
+                "invalid operand name `{}` in kernel `{}`",
+                name,
+                kernel.name
+            );
+            assert!(
+                !names[..index].contains(name),
+                "`{}` is both a vector and a scalar in kernel `{}`",
+                name,
+                kernel.name
+            );
+        }
+        kernel
+    }
+
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn expr(&self) -> &Expr { &self.expr }
+
+    /// The names of the vector parameters, in the order they are passed.
+    pub fn vectors(&self) -> Vec<&str> {
+        self.operands()
+            .into_iter()
+            .filter(|(_, vector)| *vector)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// The names of the scalar parameters, in the order they are passed.
+    pub fn scalars(&self) -> Vec<&str> {
+        self.operands()
+            .into_iter()
+            .filter(|(_, vector)| !vector)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Panics if an operand is named like the type of the field `field` or
+    /// like one of its functions and constants, e.g. `{field}_ZERO`.
+    pub(crate) fn assert_field_names_unused(&self, field: &str) {
+        for (name, _) in self.operands() {
+            let clashes = name
+                .strip_prefix(field)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('_'));
+            assert!(
+                !clashes,
+                "operand `{}` of kernel `{}` clashes with the identifiers of \
+                 the field `{}`",
+                name, self.name, field
+            );
+        }
+    }
+
+    /// The names of the operands and whether they are vectors.
+    fn operands(&self) -> Vec<(&str, bool)> {
+        let mut operands = Vec::new();
+        self.expr.operands(&mut operands);
+        operands
+            .into_iter()
+            .map(|operand| match operand {
+                Expr::Vector(name) => (name.as_str(), true),
+                Expr::Scalar(name) => (name.as_str(), false),
+                _ => unreachable!("operands are vectors or scalars"),
+            })
+            .collect()
+    }
+
+    /// The kernel source with the `FIELD` placeholder.
+    pub(crate) fn template(&self) -> String {
+        let mut params = vec!["GLOBAL ${FIELD} *result".to_owned()];
+        for vector in self.vectors() {
+            params.push(format!("GLOBAL ${{FIELD}} *{}", vector));
+        }
+        for scalar in self.scalars() {
+            params.push(format!("${{FIELD}} {}", scalar));
+        }
+        params.push("uint n".to_owned());
+
+        let mut expr = String::new();
+        self.expr.write_source(&mut expr).unwrap();
+        format!(
+            "KERNEL void ${{FIELD}}_{}({}) {{
+  const uint i = GET_GLOBAL_ID();
+  if (i >= n) return;
+  result[i] = {};
+}}
+",
+            self.name,
+            params.join(", "),
+            expr
+        )
+    }
+
+    /// Computes the `n` elements of the kernel on the CPU, with the vectors
+    /// and scalars in the order of [`ElementwiseKernel::vectors`] and
+    /// [`ElementwiseKernel::scalars`]. Like the kernel, an expression of
+    /// scalars only is computed `n` times.
+    ///
+    /// Panics if their numbers do not match the kernel or a vector does not
+    /// have `n` elements.
+    pub fn evaluate<F: ark_ff::Field>(
+        &self, n: usize, vectors: &[&[F]], scalars: &[F],
+    ) -> Vec<F> {
+        let vector_names = self.vectors();
+        let scalar_names = self.scalars();
+        assert_eq!(vectors.len(), vector_names.len(), "number of vectors");
+        assert_eq!(scalars.len(), scalar_names.len(), "number of scalars");
+        assert!(
+            vectors.iter().all(|vector| vector.len() == n),
+            "the vectors must have {} elements",
+            n
+        );
+
+        let vectors: Vec<_> = vector_names
+            .into_iter()
+            .zip(vectors.iter().copied())
+            .collect();
+        let scalars: Vec<_> = scalar_names
+            .into_iter()
+            .zip(scalars.iter().copied())
+            .collect();
+        (0..n)
+            .map(|index| self.expr.evaluate(index, &vectors, &scalars))
+            .collect()
+    }
+}
+
+pub(crate) fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::SourceBuilder;
+    use ag_types::GpuName;
+    use chosen_ark_suite::Fr;
+
+    #[test]
+    fn test_template() {
+        let kernel = ElementwiseKernel::new(
+            "mul_sub",
+            Expr::vector("a") * Expr::vector("b")
+                - Expr::vector("c") * Expr::scalar("z").double()
+                - -Expr::vector("a").square(),
+        );
+        assert_eq!(kernel.vectors(), ["a", "b", "c"]);
+        assert_eq!(kernel.scalars(), ["z"]);
+        assert_eq!(
+            kernel.template(),
+            "KERNEL void ${FIELD}_mul_sub(GLOBAL ${FIELD} *result, \
+             GLOBAL ${FIELD} *a, GLOBAL ${FIELD} *b, GLOBAL ${FIELD} *c, \
+             ${FIELD} z, uint n) {
+  const uint i = GET_GLOBAL_ID();
+  if (i >= n) return;
+  result[i] = ${FIELD}_sub(${FIELD}_sub(${FIELD}_mul(a[i], b[i]), \
+             ${FIELD}_mul(c[i], ${FIELD}_double(z))), \
+             ${FIELD}_sub(${FIELD}_ZERO, ${FIELD}_sqr(a[i])));
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let kernel = ElementwiseKernel::new(
+            "shift_scale",
+            (Expr::vector("x") + Expr::scalar("beta")) * Expr::scalar("gamma")
+                - Expr::vector("y"),
+        );
+        let x = [Fr::from(1u64), Fr::from(2u64)];
+        let y = [Fr::from(3u64), Fr::from(4u64)];
+        assert_eq!(
+            kernel.evaluate(2, &[&x, &y], &[Fr::from(10u64), Fr::from(2u64)]),
+            [Fr::from(19u64), Fr::from(20u64)]
+        );
+
+        // The kernel writes `n` elements also without vectors.
+        let square = ElementwiseKernel::new("fill", Expr::scalar("x").square());
+        let nine = Fr::from(9u64);
+        assert_eq!(square.evaluate(3, &[], &[Fr::from(3u64)]), [nine; 3]);
+    }
+
+    #[test]
+    fn test_add_elementwise() {
+        let kernel = ElementwiseKernel::new(
+            "axpy",
+            Expr::scalar("alpha") * Expr::vector("x") + Expr::vector("y"),
+        );
+        let source = SourceBuilder::new().add_elementwise::<Fr>(kernel);
+
+        let kernels = source.kernels();
+        let axpy = kernels.iter().find(|k| k.template == "FIELD_axpy").unwrap();
+        assert_eq!(axpy.name, format!("{}_axpy", Fr::name()));
+        let params: Vec<_> = axpy.params.iter().map(|p| &p.name).collect();
+        assert_eq!(params, ["result", "x", "y", "alpha", "n"]);
+        assert!(source
+            .build_launchers()
+            .contains("fn field_axpy<'b, Field: ::ag_types::GpuName + "));
+        assert!(source.build_32_bit_limbs().contains(&format!(
+            "{0}_add({0}_mul(alpha, x[i]), y[i])",
+            Fr::name()
+        )));
+    }
+
+    #[test]
+    #[should_panic(expected = "`x` is both a vector and a scalar")]
+    fn test_vector_and_scalar() {
+        ElementwiseKernel::new("f", Expr::vector("x") * Expr::scalar("x"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid operand name `n`")]
+    fn test_reserved_name() {
+        ElementwiseKernel::new("f", Expr::vector("x") * Expr::scalar("n"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid operand name `GLOBAL`")]
+    fn test_macro_name() {
+        ElementwiseKernel::new("f", Expr::vector("GLOBAL") * Expr::scalar("x"));
+    }
+
+    #[test]
+    fn test_field_names() {
+        let kernel = ElementwiseKernel::new(
+            "f",
+            Expr::vector("Fr_ZERO_x") * Expr::scalar("Fr2"),
+        );
+        kernel.assert_field_names_unused("Fq");
+        kernel.assert_field_names_unused("Fr2_");
+    }
+
+    #[test]
+    #[should_panic(expected = "clashes with the identifiers of the field")]
+    fn test_field_constant_name() {
+        let zero = format!("{}_ZERO", Fr::name());
+        let kernel = ElementwiseKernel::new(
+            "f",
+            Expr::vector("x") - Expr::scalar(&zero),
+        );
+        SourceBuilder::new().add_elementwise::<Fr>(kernel);
+    }
+}
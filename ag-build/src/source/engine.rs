@@ -0,0 +1,202 @@
+//! Instantiation of kernel templates.
+//!
+//! A placeholder is written `${NAME}`, where `NAME` consists of ASCII letters,
+//! digits and underscores, and is replaced by the value bound to it, e.g. a
+//! template `${FIELD}_add` with `FIELD` bound to `Fr` becomes `Fr_add`. As the
+//! placeholder is delimited, `${FIELD2}` and `${FIELD}` can be bound
+//! independently. `$$` stands for a literal `$`.
+//!
+//! A placeholder without binding, a binding that the template does not use
+//! and a `$` starting neither are errors.
+
+use ag_types::GpuName;
+use std::{collections::BTreeMap, fmt};
+
+/// The values of the placeholders of a template.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bindings(BTreeMap<String, String>);
+
+impl Bindings {
+    pub fn new() -> Self { Self::default() }
+
+    /// Binds `placeholder` to `value`, replacing a previous binding.
+    pub fn bind(mut self, placeholder: &str, value: impl Into<String>) -> Self {
+        self.0.insert(placeholder.to_owned(), value.into());
+        self
+    }
+
+    /// Binds `placeholder` to the name of `T`, e.g. of a field or a curve
+    /// point, like the built-in templates do.
+    pub fn name<T: GpuName>(self, placeholder: &str) -> Self {
+        self.bind(placeholder, T::name())
+    }
+
+    pub fn get(&self, placeholder: &str) -> Option<&str> {
+        self.0.get(placeholder).map(String::as_str)
+    }
+
+    /// The placeholders and their values, ordered by placeholder.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+}
+
+/// An error of instantiating a template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A placeholder in the template has no binding.
+    Unbound {
+        template: String,
+        placeholder: String,
+        line: usize,
+    },
+    /// A binding is not used by the template.
+    Unused {
+        template: String,
+        placeholder: String,
+    },
+    /// A `$` is not followed by `{NAME}` or `$`.
+    Malformed { template: String, line: usize },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Unbound {
+                template,
+                placeholder,
+                line,
+            } => write!(
+                f,
+                "{}:{}: placeholder `{}` is not bound",
+                template, line, placeholder
+            ),
+            TemplateError::Unused {
+                template,
+                placeholder,
+            } => write!(
+                f,
+                "{}: placeholder `{}` is bound but not used",
+                template, placeholder
+            ),
+            TemplateError::Malformed { template, line } => write!(
+                f,
+                "{}:{}: expected `${{NAME}}` or `$$` after `$`",
+                template, line
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// Instantiates the template `source`, named `template` in errors.
+pub fn render(
+    template: &str, source: &str, bindings: &Bindings,
+) -> Result<String, TemplateError> {
+    let mut result = String::with_capacity(source.len());
+    let mut used = vec![false; bindings.0.len()];
+    let mut rest = source;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let line = || {
+            source[..source.len() - rest.len() + start]
+                .matches('\n')
+                .count()
+                + 1
+        };
+        let malformed = || TemplateError::Malformed {
+            template: template.to_owned(),
+            line: line(),
+        };
+
+        let after = &rest[start + 1..];
+        if let Some(after) = after.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+            continue;
+        }
+        let inner = after.strip_prefix('{').ok_or_else(malformed)?;
+        let end = inner.find('}').ok_or_else(malformed)?;
+        let placeholder = &inner[..end];
+        let valid = !placeholder.is_empty()
+            && placeholder
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_');
+        if !valid {
+            return Err(malformed());
+        }
+
+        let index = bindings
+            .0
+            .keys()
+            .position(|key| key == placeholder)
+            .ok_or_else(|| TemplateError::Unbound {
+                template: template.to_owned(),
+                placeholder: placeholder.to_owned(),
+                line: line(),
+            })?;
+        used[index] = true;
+        result.push_str(&bindings.0[placeholder]);
+        rest = &inner[end + 1..];
+    }
+    result.push_str(rest);
+
+    if let Some((placeholder, _)) =
+        bindings.0.keys().zip(used).find(|(_, used)| !used)
+    {
+        return Err(TemplateError::Unused {
+            template: template.to_owned(),
+            placeholder: placeholder.clone(),
+        });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let bindings =
+            Bindings::new().bind("FIELD", "Fq").bind("FIELD2", "Fq2");
+        assert_eq!(
+            render("t", "${FIELD2}_mul(${FIELD} a) $$x", &bindings),
+            Ok("Fq2_mul(Fq a) $x".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let bindings = Bindings::new().bind("POINT", "G1");
+        assert_eq!(
+            render("t.cl", "${POINT}\n${SCALAR}_add", &bindings),
+            Err(TemplateError::Unbound {
+                template: "t.cl".to_owned(),
+                placeholder: "SCALAR".to_owned(),
+                line: 2,
+            })
+        );
+        assert_eq!(
+            render("t.cl", "POINT", &bindings),
+            Err(TemplateError::Unused {
+                template: "t.cl".to_owned(),
+                placeholder: "POINT".to_owned(),
+            })
+        );
+        for source in ["${POINT}$", "\n$POINT", "${POINT", "${}", "${PO INT}"] {
+            assert!(matches!(
+                render("t.cl", source, &bindings),
+                Err(TemplateError::Malformed { .. })
+            ));
+        }
+        assert_eq!(
+            render("t.cl", "\n\n${", &bindings).unwrap_err().to_string(),
+            "t.cl:3: expected `${NAME}` or `$$` after `$`"
+        );
+    }
+}
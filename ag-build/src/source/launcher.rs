@@ -0,0 +1,651 @@
+//! Generates typed Rust launchers for the kernel entry points.
+//!
+//! For every kernel template one argument struct with a field per parameter
+//! and one method of the `KernelLaunchers` trait is generated. The trait is
+//! implemented for `ag_cuda_proxy::Kernel` and
+//! `&ag_cuda_proxy::ActiveWorkspace`. Pointer and value arguments are typed
+//! with the Rust types the parameter types are generated from, e.g. a
+//! `GLOBAL POINT_jacobian *` parameter takes a `PointerArg` to the
+//! `GpuCurveAffine::Curve` of the point. A template whose signature changes
+//! therefore changes the generated Rust types and breaks the build of the
+//! callers.
+
+use super::signature::{
+    templatize, AddressSpace, KernelParam, KernelSignature,
+};
+use std::{collections::BTreeMap, fmt::Write};
+
+const HEADER: &str = "\
+// Typed kernel launchers generated by `ag-build` from the signatures of the
+// `KERNEL` functions. Do not edit, the file is overwritten on every build.
+";
+
+/// Generates the launcher source for the given kernels. Kernels that are
+/// instantiated from the same template share one launcher, which is generic
+/// over the placeholders that appear in the kernel name.
+pub(super) fn launchers(kernels: &[KernelSignature]) -> String {
+    let mut templates = BTreeMap::new();
+    for kernel in kernels {
+        templates.entry(kernel.template.clone()).or_insert(kernel);
+    }
+
+    let mut structs = String::new();
+    let mut declarations = String::new();
+    let mut kernel_impls = String::new();
+    let mut workspace_impls = String::new();
+    for kernel in templates.values() {
+        let launcher = Launcher::new(kernel);
+        launcher.write_struct(&mut structs).unwrap();
+        launcher.write_declaration(&mut declarations).unwrap();
+        launcher.write_kernel_impl(&mut kernel_impls).unwrap();
+        launcher.write_workspace_impl(&mut workspace_impls).unwrap();
+    }
+
+    format!(
+        "{HEADER}
+{structs}
+/// Typed launchers for the kernels of the generated source.
+pub trait KernelLaunchers<'a> {{
+{declarations}}}
+
+impl<'a> KernelLaunchers<'a> for ::ag_cuda_proxy::Kernel<'a> {{
+{kernel_impls}}}
+
+impl<'a> KernelLaunchers<'a> for &::ag_cuda_proxy::ActiveWorkspace<'a> {{
+{workspace_impls}}}
+"
+    )
+}
+
+struct Launcher<'k> {
+    kernel: &'k KernelSignature,
+    /// The snake case name of the launcher function.
+    function: String,
+    /// The camel case name of the argument struct.
+    args: String,
+    /// The placeholders in the kernel name and the generic parameters
+    /// representing them.
+    generics: Vec<(String, String)>,
+    /// The argument struct fields of the parameters passed from the host.
+    fields: Vec<Option<ArgType>>,
+}
+
+/// The Rust type of a parameter passed from the host.
+enum ArgType {
+    /// A primitive type passed by value.
+    Primitive(&'static str),
+    /// A `PointerArg` to elements of the type.
+    Pointer(Element),
+    /// A `ValueArg` of the type.
+    Value(Element),
+}
+
+/// The Rust type of the elements of a kernel parameter.
+struct Element {
+    ty: String,
+    /// The index of the generic parameter the type is derived from.
+    generic: Option<usize>,
+    /// The bound the generic parameter needs for the type to exist.
+    bound: Option<String>,
+}
+
+impl Element {
+    const UNTYPED: &'static str = "::ag_cuda_proxy::Untyped";
+
+    fn untyped() -> Self {
+        Self {
+            ty: Self::UNTYPED.to_owned(),
+            generic: None,
+            bound: None,
+        }
+    }
+}
+
+impl<'k> Launcher<'k> {
+    fn new(kernel: &'k KernelSignature) -> Self {
+        let mut generics: Vec<_> = kernel
+            .bindings
+            .keys()
+            .filter_map(|p| {
+                kernel.template.find(p.as_str()).map(|pos| (pos, p))
+            })
+            .collect();
+        generics.sort();
+        let generics: Vec<_> = generics
+            .into_iter()
+            .map(|(_, p)| (p.clone(), camel_case(p)))
+            .collect();
+        let fields = kernel
+            .params
+            .iter()
+            .map(|param| arg_type(param, kernel, &generics))
+            .collect();
+
+        Self {
+            kernel,
+            function: kernel.template.to_lowercase(),
+            args: format!("{}Args", camel_case(&kernel.template)),
+            generics,
+            fields,
+        }
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.fields
+            .iter()
+            .flatten()
+            .filter_map(|field| match field {
+                ArgType::Primitive(_) => None,
+                ArgType::Pointer(element) | ArgType::Value(element) => {
+                    Some(element)
+                }
+            })
+    }
+
+    /// Whether the argument struct borrows data.
+    fn borrows(&self) -> bool {
+        self.fields
+            .iter()
+            .any(|field| matches!(field, Some(ArgType::Pointer(_))))
+    }
+
+    /// The generic parameters of the argument struct, those its field types
+    /// are derived from.
+    fn struct_generics(&self) -> Vec<&str> {
+        self.generics
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| {
+                self.elements().any(|e| e.generic == Some(*index))
+            })
+            .map(|(_, (_, generic))| generic.as_str())
+            .collect()
+    }
+
+    fn args_type(&self) -> String {
+        let mut params = self.struct_generics();
+        if self.borrows() {
+            params.insert(0, "'b");
+        }
+        if params.is_empty() {
+            self.args.clone()
+        } else {
+            format!("{}<{}>", self.args, params.join(", "))
+        }
+    }
+
+    fn generic_bound(&self, placeholder: &str) -> &'static str {
+        if placeholder == "POINT" {
+            "::ag_types::GpuCurveAffine"
+        } else {
+            "::ag_types::GpuName"
+        }
+    }
+
+    /// The bounds the field types need in addition to those of the generic
+    /// parameters, `kernel_args` adds that the elements are kernel
+    /// arguments. The bounds of the generic parameters themselves are then
+    /// left to [`Launcher::generic_bounds`], as a bound in both places is
+    /// linted.
+    fn where_clause(&self, kernel_args: bool) -> String {
+        let mut predicates: Vec<String> = Vec::new();
+        for element in self.elements() {
+            let predicate = match (&element.bound, element.generic) {
+                // Bounds of the generic parameters of a launcher.
+                (Some(_), Some(_)) if kernel_args => continue,
+                (Some(bound), Some(generic)) => {
+                    format!("{}: {}", self.generics[generic].1, bound)
+                }
+                _ => continue,
+            };
+            if !predicates.contains(&predicate) {
+                predicates.push(predicate);
+            }
+        }
+        if kernel_args {
+            for element in self.elements() {
+                if element.ty == Element::UNTYPED
+                    || self.is_generic(&element.ty)
+                {
+                    continue;
+                }
+                let predicate =
+                    format!("{}: ::ag_types::KernelArg", element.ty);
+                if !predicates.contains(&predicate) {
+                    predicates.push(predicate);
+                }
+            }
+        }
+        if predicates.is_empty() {
+            String::new()
+        } else {
+            format!("\n    where\n        {},", predicates.join(",\n        "))
+        }
+    }
+
+    fn is_generic(&self, ty: &str) -> bool {
+        self.generics.iter().any(|(_, generic)| generic == ty)
+    }
+
+    /// The bounds of the generic parameter `index` of a launcher, including
+    /// those its elements need.
+    fn generic_bounds(&self, index: usize) -> String {
+        let (placeholder, generic) = &self.generics[index];
+        let mut bounds = vec![self.generic_bound(placeholder)];
+        for element in self.elements().filter(|e| e.generic == Some(index)) {
+            if let Some(bound) = &element.bound {
+                if !bounds.contains(&bound.as_str()) {
+                    bounds.push(bound);
+                }
+            }
+        }
+        if self.elements().any(|e| e.ty == *generic) {
+            bounds.push("::ag_types::KernelArg");
+        }
+        bounds.join(" + ")
+    }
+
+    fn signature(&self, receiver: &str) -> String {
+        let generics: String = (0..self.generics.len())
+            .map(|index| {
+                let generic = &self.generics[index].1;
+                format!(", {}: {}", generic, self.generic_bounds(index))
+            })
+            .collect();
+        format!(
+            "fn {}<'b{}>(
+        {}, config: ::ag_cuda_proxy::KernelConfig, args: {},
+    ) -> ::ag_cuda_proxy::LaunchResult<::ag_cuda_proxy::PendingTask<'a, 'b>>{}",
+            self.function,
+            generics,
+            receiver,
+            self.args_type(),
+            self.where_clause(true)
+        )
+    }
+
+    fn write_struct(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(
+            out,
+            "/// Arguments of the `{}` kernel.",
+            self.kernel.template
+        )?;
+        let mut params: Vec<_> = self
+            .generics
+            .iter()
+            .filter(|(_, generic)| {
+                self.struct_generics().contains(&generic.as_str())
+            })
+            .map(|(placeholder, generic)| {
+                format!("{}: {}", generic, self.generic_bound(placeholder))
+            })
+            .collect();
+        if self.borrows() {
+            params.insert(0, "'b".to_owned());
+        }
+        if params.is_empty() {
+            writeln!(out, "pub struct {} {{", self.args)?;
+        } else {
+            writeln!(
+                out,
+                "pub struct {}<{}>{} {{",
+                self.args,
+                params.join(", "),
+                self.where_clause(false)
+            )?;
+        }
+        for (param, field) in self.kernel.params.iter().zip(&self.fields) {
+            let ty = match field {
+                None => continue,
+                Some(ArgType::Primitive(ty)) => ty.to_string(),
+                Some(ArgType::Pointer(element)) => {
+                    format!("::ag_cuda_proxy::PointerArg<'b, {}>", element.ty)
+                }
+                Some(ArgType::Value(element)) => {
+                    format!("::ag_cuda_proxy::ValueArg<{}>", element.ty)
+                }
+            };
+            writeln!(out, "    /// `{}`", declaration(param))?;
+            writeln!(out, "    pub {}: {},", field_name(&param.name), ty)?;
+        }
+        writeln!(out, "}}\n")
+    }
+
+    fn write_declaration(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(
+            out,
+            "    /// Launches the `{}` kernel.",
+            self.kernel.template
+        )?;
+        writeln!(out, "    {};\n", self.signature("self"))
+    }
+
+    fn write_kernel_impl(&self, out: &mut String) -> std::fmt::Result {
+        let mut name = self.kernel.template.clone();
+        let mut name_args = String::new();
+        for (placeholder, generic) in &self.generics {
+            name = name.replacen(placeholder.as_str(), "{}", 1);
+            write!(
+                name_args,
+                ", <{} as ::ag_types::GpuName>::name()",
+                generic
+            )?;
+        }
+
+        writeln!(out, "    {} {{", self.signature("self"))?;
+        writeln!(
+            out,
+            "        self.func(&format!(\"{}\"{}))?",
+            name, name_args
+        )?;
+        writeln!(out, "            .validate(&config)?")?;
+        for (param, arg) in self.kernel.params.iter().zip(&self.fields) {
+            let field = field_name(&param.name);
+            match arg {
+                None => writeln!(out, "            .empty()?")?,
+                Some(ArgType::Pointer(_)) => {
+                    writeln!(out, "            .ptr(args.{})?", field)?
+                }
+                Some(ArgType::Value(_)) => {
+                    writeln!(out, "            .value(args.{})?", field)?
+                }
+                Some(ArgType::Primitive(_)) => {
+                    writeln!(out, "            .val(args.{})?", field)?
+                }
+            }
+        }
+        writeln!(out, "            .launch(config)")?;
+        writeln!(out, "    }}\n")
+    }
+
+    fn write_workspace_impl(&self, out: &mut String) -> std::fmt::Result {
+        let generics: Vec<_> =
+            self.generics.iter().map(|(_, g)| g.as_str()).collect();
+        writeln!(out, "    {} {{", self.signature("self"))?;
+        writeln!(
+            out,
+            "        self.create_kernel()?.{}::<{}>(config, args)",
+            self.function,
+            generics.join(", ")
+        )?;
+        writeln!(out, "    }}\n")
+    }
+}
+
+/// The Rust type of the argument struct field, `None` if the parameter is
+/// not passed from the host.
+fn arg_type(
+    param: &KernelParam, kernel: &KernelSignature,
+    generics: &[(String, String)],
+) -> Option<ArgType> {
+    if param.address_space == AddressSpace::Local {
+        return None;
+    }
+    let primitive = primitive_type(&param.ty);
+    if param.pointer {
+        let element = match primitive {
+            Some(ty) => Element {
+                ty: ty.to_owned(),
+                generic: None,
+                bound: None,
+            },
+            None => element_type(&param.ty, kernel, generics),
+        };
+        return Some(ArgType::Pointer(element));
+    }
+    Some(match primitive {
+        Some(ty) => ArgType::Primitive(ty),
+        None => ArgType::Value(element_type(&param.ty, kernel, generics)),
+    })
+}
+
+fn primitive_type(ty: &str) -> Option<&'static str> {
+    Some(match ty {
+        // A `bool` written by a kernel may hold any byte.
+        "bool" => "u8",
+        "char" => "i8",
+        "uchar" => "u8",
+        "short" => "i16",
+        "ushort" => "u16",
+        "int" => "i32",
+        "uint" => "u32",
+        "long" => "i64",
+        "ulong" => "u64",
+        "size_t" => "usize",
+        "float" => "f32",
+        "double" => "f64",
+        _ => return None,
+    })
+}
+
+/// The Rust type of the generated type `ty`, derived from the generic
+/// parameter of the placeholder it is named after:
+///
+/// * `FIELD` is the field and `FIELD_repr` its `PrimeFieldRepr::Repr`.
+/// * `POINT_affine` is the `GpuRepr::Repr` of the affine point and
+///   `POINT_jacobian` its `GpuCurveAffine::Curve`.
+/// * `SCALAR` and `BASE` are the fields of the `POINT` parameter.
+///
+/// Other types are [`Element::UNTYPED`].
+fn element_type(
+    ty: &str, kernel: &KernelSignature, generics: &[(String, String)],
+) -> Element {
+    let ty = templatize(ty, &kernel.bindings);
+    let generic =
+        |placeholder: &str| generics.iter().position(|(p, _)| p == placeholder);
+    // The longest placeholder the type starts with.
+    let placeholder = kernel
+        .bindings
+        .keys()
+        .filter(|p| {
+            ty == **p
+                || ty.starts_with(p.as_str()) && ty[p.len()..].starts_with('_')
+        })
+        .max_by_key(|p| p.len());
+    let (placeholder, suffix) = match placeholder {
+        Some(p) => (p.as_str(), &ty[p.len()..]),
+        None => return Element::untyped(),
+    };
+
+    let (base, index, derived) = match (generic(placeholder), generic("POINT"))
+    {
+        (Some(index), _) => (generics[index].1.clone(), index, false),
+        (None, Some(point)) if ["SCALAR", "BASE"].contains(&placeholder) => (
+            format!(
+                "<{} as ::ag_types::GpuCurveAffine>::{}",
+                generics[point].1,
+                camel_case(placeholder)
+            ),
+            point,
+            true,
+        ),
+        _ => return Element::untyped(),
+    };
+    let (ty, bound) = match (placeholder == "POINT", suffix) {
+        (true, "_affine") => {
+            (format!("<{} as ::ag_types::GpuRepr>::Repr", base), None)
+        }
+        (true, "_jacobian") => (
+            format!("<{} as ::ag_types::GpuCurveAffine>::Curve", base),
+            None,
+        ),
+        (false, "") => (base, None),
+        // The scalars of a `GpuCurveAffine` are a `PrimeFieldRepr` already.
+        (false, "_repr") if derived && placeholder == "SCALAR" => (
+            format!("<{} as ::ag_types::PrimeFieldRepr>::Repr", base),
+            None,
+        ),
+        (false, "_repr") if !derived => (
+            format!("<{} as ::ag_types::PrimeFieldRepr>::Repr", base),
+            Some("::ag_types::PrimeFieldRepr".to_owned()),
+        ),
+        _ => return Element::untyped(),
+    };
+    Element {
+        ty,
+        generic: Some(index),
+        bound,
+    }
+}
+
+fn declaration(param: &KernelParam) -> String {
+    let qualifier = match param.address_space {
+        AddressSpace::Private => "",
+        AddressSpace::Global => "GLOBAL ",
+        AddressSpace::Local => "LOCAL ",
+        AddressSpace::Constant => "CONSTANT ",
+    };
+    let pointer = if param.pointer { "*" } else { "" };
+    format!("{}{}{} {}", qualifier, param.ty, pointer, param.name)
+}
+
+fn field_name(name: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "as", "box", "break", "const", "continue", "crate", "do", "else",
+        "enum", "extern", "fn", "for", "if", "impl", "in", "let", "loop",
+        "match", "mod", "move", "mut", "priv", "pub", "ref", "return",
+        "static", "struct", "trait", "type", "unsafe", "use", "where", "while",
+    ];
+    if KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            first.to_string() + &chars.as_str().to_ascii_lowercase()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::engine::Bindings;
+
+    fn multiexp_launchers(bases: &str) -> String {
+        let source = format!(
+            "KERNEL void G1_multiexp(GLOBAL {} *bases,
+                                     LOCAL G1_jacobian *shared,
+                                     uint n,
+                                     bool neg_is_cheap,
+                                     FR scalar) {{}}",
+            bases
+        );
+        let kernels = KernelSignature::parse_all(
+            &source,
+            &Bindings::new().bind("POINT", "G1").bind("SCALAR", "FR"),
+        );
+        launchers(&kernels)
+    }
+
+    #[test]
+    fn test_launchers() {
+        let launchers = multiexp_launchers("G1_affine");
+
+        assert!(launchers.contains(
+            "pub struct PointMultiexpArgs<'b, Point: \
+             ::ag_types::GpuCurveAffine> {"
+        ));
+        assert!(launchers.contains(
+            "pub bases: ::ag_cuda_proxy::PointerArg<'b, <Point as \
+             ::ag_types::GpuRepr>::Repr>,"
+        ));
+        assert!(!launchers.contains("pub shared"));
+        assert!(launchers.contains("pub n: u32,"));
+        assert!(launchers.contains("pub neg_is_cheap: u8,"));
+        assert!(launchers.contains(
+            "pub scalar: ::ag_cuda_proxy::ValueArg<<Point as \
+             ::ag_types::GpuCurveAffine>::Scalar>,"
+        ));
+        assert!(launchers.contains(
+            "fn point_multiexp<'b, Point: ::ag_types::GpuCurveAffine>("
+        ));
+        assert!(launchers.contains(
+            "args: PointMultiexpArgs<'b, Point>,
+    ) -> ::ag_cuda_proxy::LaunchResult<::ag_cuda_proxy::PendingTask<'a, 'b>>
+    where
+        <Point as ::ag_types::GpuRepr>::Repr: ::ag_types::KernelArg,
+        <Point as ::ag_types::GpuCurveAffine>::Scalar: ::ag_types::KernelArg,"
+        ));
+        assert!(launchers.contains(
+            "self.func(&format!(\"{}_multiexp\", <Point as \
+             ::ag_types::GpuName>::name()))?"
+        ));
+        assert!(launchers
+            .contains(".validate(&config)?\n            .ptr(args.bases)?"));
+        assert!(launchers.contains(".ptr(args.bases)?\n            .empty()?"));
+    }
+
+    #[test]
+    fn test_type_changes_signature() {
+        let affine = multiexp_launchers("G1_affine");
+        let jacobian = multiexp_launchers("G1_jacobian");
+        assert_ne!(affine, jacobian);
+        assert!(jacobian.contains(
+            "pub bases: ::ag_cuda_proxy::PointerArg<'b, <Point as \
+             ::ag_types::GpuCurveAffine>::Curve>,"
+        ));
+        assert!(jacobian.contains(
+            "<Point as ::ag_types::GpuCurveAffine>::Curve: \
+             ::ag_types::KernelArg,"
+        ));
+
+        let uint = multiexp_launchers("uint");
+        assert!(
+            uint.contains("pub bases: ::ag_cuda_proxy::PointerArg<'b, u32>,")
+        );
+        let repr = multiexp_launchers("FR_repr");
+        assert!(repr.contains(
+            "pub bases: ::ag_cuda_proxy::PointerArg<'b, <<Point as \
+             ::ag_types::GpuCurveAffine>::Scalar as \
+             ::ag_types::PrimeFieldRepr>::Repr>,"
+        ));
+        // Types without a Rust counterpart are untyped.
+        let other = multiexp_launchers("G1_other");
+        assert!(other.contains(
+            "pub bases: ::ag_cuda_proxy::PointerArg<'b, \
+             ::ag_cuda_proxy::Untyped>,"
+        ));
+    }
+
+    #[test]
+    fn test_field_launchers() {
+        let source = "
+            KERNEL void Fr_scale(GLOBAL Fr_repr *x, Fr factor, uint n) {}
+            KERNEL void Fr_count(uint n) {}
+        ";
+        let kernels = KernelSignature::parse_all(
+            source,
+            &Bindings::new().bind("FIELD", "Fr"),
+        );
+        let launchers = launchers(&kernels);
+
+        assert!(launchers.contains(
+            "pub struct FieldScaleArgs<'b, Field: ::ag_types::GpuName>
+    where
+        Field: ::ag_types::PrimeFieldRepr, {"
+        ));
+        assert!(
+            launchers.contains("pub factor: ::ag_cuda_proxy::ValueArg<Field>,")
+        );
+        // The bounds of the generic parameter are in one place.
+        assert!(launchers.contains(
+            "fn field_scale<'b, Field: ::ag_types::GpuName + \
+             ::ag_types::PrimeFieldRepr + ::ag_types::KernelArg>("
+        ));
+        assert!(launchers.contains(
+            "<Field as ::ag_types::PrimeFieldRepr>::Repr: \
+             ::ag_types::KernelArg,;"
+        ));
+        // Arguments of no generated type do not take the generic parameter.
+        assert!(launchers.contains("pub struct FieldCountArgs {"));
+        assert!(launchers.contains("args: FieldCountArgs,\n"));
+    }
+}
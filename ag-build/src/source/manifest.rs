@@ -0,0 +1,157 @@
+//! A machine-readable description of the generated source.
+//!
+//! The manifest lists the generated items, the kernel entry points with
+//! their parameters and the constants of the fields. It is written next to
+//! the compiled kernel, so that crates embedding the kernel can check at run
+//! time that it contains the kernels they launch.
+
+use super::{
+    limb::{Limb, Limb32, Limb32Or64, Limb64},
+    signature::KernelSignature,
+    template::FieldLimbs,
+};
+
+use serde::Serialize;
+
+/// An item of the generated source, e.g. a field or an FFT.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ManifestItem {
+    /// The kind of the item, e.g. `field`, `curve` or `multiexp`.
+    pub kind: String,
+    /// The name of the item, usually the name of its field or curve point.
+    pub name: String,
+}
+
+/// The constants of a field, as hexadecimal numbers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FieldConstants {
+    /// The name of the field in the generated source.
+    pub name: String,
+    pub modulus: String,
+    /// `1` in Montgomery form, i.e. `R mod P`.
+    pub one: String,
+    /// `R^2 mod P`.
+    pub r2: String,
+    /// `-P^-1 mod 2^LIMB_BITS`, the factor of the Montgomery reduction.
+    pub inv: String,
+}
+
+impl FieldConstants {
+    /// The constants of a field, named `name` in the generated source with
+    /// limbs of `limb_size`.
+    pub(crate) fn new(
+        name: &str, field: &FieldLimbs, limb_size: Limb32Or64,
+    ) -> Self {
+        let inv = match limb_size {
+            Limb32Or64::Limb32 => {
+                let p = Limb32::from_u32_limbs(&field.modulus);
+                Limb32::calc_inv(p[0]).value() as u64
+            }
+            Limb32Or64::Limb64 => {
+                let p = Limb64::from_u32_limbs(&field.modulus);
+                Limb64::calc_inv(p[0]).value()
+            }
+        };
+        Self {
+            name: name.to_owned(),
+            modulus: to_hex(&field.modulus),
+            one: to_hex(&field.one),
+            r2: to_hex(&field.r2),
+            inv: format!("{:#x}", inv),
+        }
+    }
+}
+
+/// The description of a generated source, see
+/// [`SourceBuilder::manifest_32_bit_limbs`].
+///
+/// [`SourceBuilder::manifest_32_bit_limbs`]:
+/// crate::SourceBuilder::manifest_32_bit_limbs
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct KernelManifest {
+    /// The SHA-256 digest of the generated source.
+    pub digest: String,
+    /// The size of the limbs the source was generated with, 32 or 64.
+    pub limb_bits: u32,
+    /// The items in the order they appear in the source.
+    pub items: Vec<ManifestItem>,
+    /// The fields other than extension fields.
+    pub fields: Vec<FieldConstants>,
+    /// The kernel entry points, see [`SourceBuilder::kernels`].
+    ///
+    /// [`SourceBuilder::kernels`]: crate::SourceBuilder::kernels
+    pub kernels: Vec<KernelSignature>,
+}
+
+impl KernelManifest {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("manifests are serializable")
+    }
+}
+
+/// Formats little-endian limbs as a hexadecimal number.
+pub(crate) fn to_hex(limbs: &[u32]) -> String {
+    let digits: String = limbs
+        .iter()
+        .rev()
+        .map(|limb| format!("{:08x}", limb))
+        .collect();
+    let digits = digits.trim_start_matches('0');
+    format!("0x{}", if digits.is_empty() { "0" } else { digits })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SourceBuilder;
+
+    use ag_types::GpuName;
+    use chosen_ark_suite::{Fq2, Fr};
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[0x1, 0xab]), "0xab00000001");
+        assert_eq!(to_hex(&[0, 0]), "0x0");
+    }
+
+    #[test]
+    fn test_manifest() {
+        let source = SourceBuilder::new().add_fft::<Fr>().add_field::<Fq2>();
+        let manifest = source.manifest_32_bit_limbs();
+
+        let digest = Sha256::digest(source.build_32_bit_limbs().as_bytes());
+        assert_eq!(manifest.digest, hex::encode(digest));
+        assert_eq!(manifest.limb_bits, 32);
+
+        let items: Vec<_> = manifest
+            .items
+            .iter()
+            .map(|item| (item.kind.as_str(), item.name.clone()))
+            .collect();
+        assert!(items.contains(&("field", Fr::name())));
+        assert!(items.contains(&("extension field", Fq2::name())));
+        assert!(items.contains(&("fft", Fr::name())));
+
+        // The extension field has no constants of its own, its sub-field is
+        // listed instead.
+        assert_eq!(manifest.fields.len(), 2);
+        let fr = manifest.fields.iter().find(|f| f.name == Fr::name());
+        assert_eq!(
+            fr.unwrap().modulus,
+            concat!(
+                "0x73eda753299d7d483339d80809a1d805",
+                "53bda402fffe5bfeffffffff00000001"
+            )
+        );
+        assert!(manifest.fields.iter().all(|f| f.name != Fq2::name()));
+        // The modulus ends in 0x00000001, so -P^-1 is -1.
+        assert_eq!(fr.unwrap().inv, "0xffffffff");
+        let manifest_64 = source.manifest(Limb32Or64::Limb64);
+        let fr = manifest_64.fields.iter().find(|f| f.name == Fr::name());
+        assert_eq!(fr.unwrap().inv, format!("{:#x}", 0xfffffffeffffffffu64));
+
+        assert_eq!(manifest.kernels, source.kernels());
+        assert!(manifest.to_json().contains("\"limb_bits\": 32"));
+    }
+}
@@ -0,0 +1,223 @@
+//! Where the lines of the generated source come from.
+//!
+//! The generated source is made of the `cl/*.cl` templates, code generated
+//! for every field and the appended sources. Before every part the builder
+//! emits a `#line` directive, so that compiler diagnostics point at the
+//! template file instead of the generated source. The directives name the
+//! templates relative to the crate, e.g. `ag-build/cl/field.cl`, as the
+//! source is hashed and must not depend on where `ag-build` is checked out.
+//! The [`SourceMap`] records the absolute paths, together with the item
+//! (field, curve or kernel) each part was generated for, which the directives
+//! cannot express.
+
+use super::engine::{self, Bindings, TemplateError};
+
+use serde::Serialize;
+
+/// The origin of a part of the generated source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Origin {
+    /// A file in `cl/`, instantiated with the names of the item.
+    Template(&'static str),
+    /// A template added with `SourceBuilder::add_template`, by its name.
+    User(String),
+    /// Code generated by `ag-build`, described by the string.
+    Generated(&'static str),
+}
+
+impl Origin {
+    /// The file name recorded in the source map. Generated code is named by
+    /// the item, as there is no file.
+    fn file(&self, item: Option<&str>) -> String {
+        match (self, item) {
+            (Origin::Template(file), _) => {
+                format!("{}/cl/{}", env!("CARGO_MANIFEST_DIR"), file)
+            }
+            (Origin::User(name), _) => name.clone(),
+            (Origin::Generated(what), Some(item)) => {
+                format!("<ag-build: {} of {}>", what, item)
+            }
+            (Origin::Generated(what), None) => format!("<ag-build: {}>", what),
+        }
+    }
+
+    /// The file name used in `#line` directives, like [`Origin::file`] but
+    /// with the templates relative to the crate.
+    fn line_file(&self, item: Option<&str>) -> String {
+        match self {
+            Origin::Template(file) => format!("ag-build/cl/{}", file),
+            _ => self.file(item),
+        }
+    }
+}
+
+/// A part of the source of an item.
+pub(crate) struct Segment {
+    pub(crate) origin: Origin,
+    pub(crate) text: String,
+}
+
+impl Segment {
+    pub(crate) fn template(file: &'static str, text: String) -> Self {
+        Self {
+            origin: Origin::Template(file),
+            text,
+        }
+    }
+
+    pub(crate) fn user(name: &str, text: String) -> Self {
+        Self {
+            origin: Origin::User(name.to_owned()),
+            text,
+        }
+    }
+
+    pub(crate) fn generated(what: &'static str, text: String) -> Self {
+        Self {
+            origin: Origin::Generated(what),
+            text,
+        }
+    }
+
+    /// Instantiates the text of the segment as a template.
+    pub(crate) fn try_render(
+        self, bindings: &Bindings,
+    ) -> Result<Self, TemplateError> {
+        let text =
+            engine::render(&self.origin.file(None), &self.text, bindings)?;
+        Ok(Self { text, ..self })
+    }
+
+    /// Instantiates a built-in template, panics if it does not match its
+    /// bindings.
+    pub(crate) fn render(self, bindings: &Bindings) -> Self {
+        self.try_render(bindings)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+/// Lines of the generated source that come from the same part.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SourceRange {
+    /// The first line in the generated source, starting at 1.
+    pub start: usize,
+    /// The line after the last one.
+    pub end: usize,
+    /// The template file, or a description of the generated code in angle
+    /// brackets.
+    pub file: String,
+    /// The item the lines were generated for, e.g. `multiexp
+    /// ark_bls12_381_g1_G1Affine`, `None` for the common code.
+    pub item: Option<String>,
+}
+
+/// The origins of the lines of a generated source, see
+/// [`SourceBuilder::build_32_bit_limbs_with_map`].
+///
+/// [`SourceBuilder::build_32_bit_limbs_with_map`]:
+/// crate::SourceBuilder::build_32_bit_limbs_with_map
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SourceMap {
+    pub ranges: Vec<SourceRange>,
+}
+
+impl SourceMap {
+    /// The range containing `line` of the generated source and the line in
+    /// its file.
+    pub fn locate(&self, line: usize) -> Option<(&SourceRange, usize)> {
+        let range = self
+            .ranges
+            .iter()
+            .find(|range| range.start <= line && line < range.end)?;
+        Some((range, line - range.start + 1))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self)
+            .expect("source maps are serializable")
+    }
+}
+
+/// Concatenates segments, recording them in a [`SourceMap`].
+pub(crate) struct SourceWriter {
+    source: String,
+    map: SourceMap,
+    /// The number of lines written so far.
+    lines: usize,
+    line_directives: bool,
+}
+
+impl SourceWriter {
+    pub(crate) fn new(line_directives: bool) -> Self {
+        Self {
+            source: String::new(),
+            map: SourceMap::default(),
+            lines: 0,
+            line_directives,
+        }
+    }
+
+    pub(crate) fn write(&mut self, item: Option<&str>, segment: &Segment) {
+        if self.line_directives {
+            let file = segment.origin.line_file(item);
+            let escaped = file.replace('\\', "\\\\").replace('"', "\\\"");
+            self.push(&format!("#line 1 \"{}\"\n", escaped));
+        }
+        let start = self.lines + 1;
+        self.push(&segment.text);
+        if !segment.text.ends_with('\n') {
+            self.push("\n");
+        }
+        self.map.ranges.push(SourceRange {
+            start,
+            end: self.lines + 1,
+            file: segment.origin.file(item),
+            item: item.map(str::to_owned),
+        });
+    }
+
+    /// Writes lines that are not recorded, e.g. separators.
+    pub(crate) fn push(&mut self, text: &str) {
+        self.source.push_str(text);
+        self.lines += text.matches('\n').count();
+    }
+
+    pub(crate) fn finish(self) -> (String, SourceMap) {
+        (self.source, self.map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_map() {
+        let mut writer = SourceWriter::new(true);
+        writer.write(None, &Segment::template("common.cl", "a\nb\n".into()));
+        writer.push("\n");
+        writer
+            .write(Some("field Fq"), &Segment::generated("params", "c".into()));
+        let (source, map) = writer.finish();
+
+        let lines: Vec<_> = source.lines().collect();
+        assert_eq!(lines[0], "#line 1 \"ag-build/cl/common.cl\"");
+        assert_eq!(lines[1..4], ["a", "b", ""]);
+        assert_eq!(lines[4], "#line 1 \"<ag-build: params of field Fq>\"");
+        assert_eq!(lines[5], "c");
+
+        let (range, line) = map.locate(6).unwrap();
+        assert_eq!(
+            (range.file.as_str(), range.item.as_deref(), line),
+            ("<ag-build: params of field Fq>", Some("field Fq"), 1)
+        );
+        let (range, line) = map.locate(3).unwrap();
+        let path = std::path::Path::new(&range.file);
+        assert!(path.is_absolute() && path.ends_with("ag-build/cl/common.cl"));
+        assert_eq!(line, 2);
+        // Neither the directives nor the separators belong to a range.
+        assert!(map.locate(1).is_none());
+        assert!(map.locate(4).is_none());
+        assert!(map.locate(7).is_none());
+    }
+}
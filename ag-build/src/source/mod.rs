@@ -1,6 +1,22 @@
+mod bigint;
 mod builder;
+mod check;
+mod elementwise;
+mod engine;
+mod launcher;
 mod limb;
+mod manifest;
+mod map;
+mod signature;
+mod spec;
 mod synthesis;
 mod template;
 
 pub use builder::SourceBuilder;
+pub use elementwise::{ElementwiseKernel, Expr};
+pub use engine::{Bindings, TemplateError};
+pub(crate) use limb::Limb32Or64;
+pub use manifest::{FieldConstants, KernelManifest, ManifestItem};
+pub use map::{SourceMap, SourceRange};
+pub use signature::{AddressSpace, KernelParam, KernelSignature};
+pub use spec::{CurveSpec, FieldSpec, SpecError};
@@ -0,0 +1,234 @@
+//! Machine-readable description of the kernel entry points in the generated
+//! source.
+
+use super::engine::Bindings;
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The address space a kernel parameter lives in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressSpace {
+    /// Passed by value.
+    Private,
+    /// `GLOBAL`, device memory.
+    Global,
+    /// `LOCAL`, shared memory of a block. On CUDA it is not passed at all,
+    /// the kernel uses the dynamic shared memory instead.
+    Local,
+    /// `CONSTANT`, constant memory.
+    Constant,
+}
+
+/// A parameter of a kernel entry point.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct KernelParam {
+    /// The name of the parameter.
+    pub name: String,
+    /// The type without address space qualifier and pointer, e.g. `uint`.
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// Whether the parameter is a pointer.
+    pub pointer: bool,
+    /// The address space of the parameter.
+    pub address_space: AddressSpace,
+}
+
+/// A kernel entry point (a `KERNEL` function) of the generated source.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct KernelSignature {
+    /// The name of the entry point in the compiled kernel.
+    pub name: String,
+    /// The name with the template placeholders, e.g. `POINT_multiexp`.
+    pub template: String,
+    /// The names the template placeholders are bound to.
+    pub bindings: BTreeMap<String, String>,
+    /// The parameters in declaration order.
+    pub params: Vec<KernelParam>,
+}
+
+impl KernelSignature {
+    /// Parses all `KERNEL` functions of `source`, which was instantiated
+    /// from a template with the given `bindings`.
+    pub(crate) fn parse_all(source: &str, bindings: &Bindings) -> Vec<Self> {
+        let source = strip_comments(source);
+        let bindings: BTreeMap<String, String> = bindings
+            .iter()
+            .map(|(placeholder, name)| {
+                (placeholder.to_owned(), name.to_owned())
+            })
+            .collect();
+
+        let mut result = Vec::new();
+        let mut rest = source.as_str();
+        while let Some(pos) = find_keyword(rest, "KERNEL") {
+            rest = &rest[pos + "KERNEL".len()..];
+            let (open, close) = match (rest.find('('), rest.find(')')) {
+                (Some(open), Some(close)) if open < close => (open, close),
+                _ => break,
+            };
+            let name = match rest[..open].split_whitespace().last() {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let params = rest[open + 1..close]
+                .split(',')
+                .filter(|param| !param.trim().is_empty())
+                .map(parse_param)
+                .collect();
+            result.push(Self {
+                template: templatize(&name, &bindings),
+                name,
+                bindings: bindings.clone(),
+                params,
+            });
+            rest = &rest[close..];
+        }
+        result
+    }
+}
+
+fn parse_param(param: &str) -> KernelParam {
+    let pointer = param.contains('*');
+    let tokens: Vec<_> = param
+        .split(|c: char| c.is_whitespace() || c == '*')
+        .filter(|token| !token.is_empty())
+        .collect();
+
+    let mut address_space = AddressSpace::Private;
+    let mut ty = Vec::new();
+    for token in &tokens[..tokens.len().saturating_sub(1)] {
+        match *token {
+            "GLOBAL" => address_space = AddressSpace::Global,
+            "LOCAL" => address_space = AddressSpace::Local,
+            "CONSTANT" => address_space = AddressSpace::Constant,
+            "const" => {}
+            other => ty.push(other),
+        }
+    }
+    // Pointers without qualifier point to global memory on CUDA.
+    if pointer && address_space == AddressSpace::Private {
+        address_space = AddressSpace::Global;
+    }
+
+    KernelParam {
+        name: tokens.last().map(|x| x.to_string()).unwrap_or_default(),
+        ty: ty.join(" "),
+        pointer,
+        address_space,
+    }
+}
+
+/// Replaces the bound names with their placeholders again. Longer names are
+/// replaced first, so that a name that is a prefix of another one does not
+/// break it.
+pub(super) fn templatize(
+    name: &str, bindings: &BTreeMap<String, String>,
+) -> String {
+    let mut bindings: Vec<_> = bindings.iter().collect();
+    bindings.sort_by_key(|(_, bound)| std::cmp::Reverse(bound.len()));
+    bindings
+        .into_iter()
+        .fold(name.to_string(), |name, (placeholder, bound)| {
+            name.replace(bound.as_str(), placeholder)
+        })
+}
+
+fn find_keyword(source: &str, keyword: &str) -> Option<usize> {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut offset = 0;
+    while let Some(pos) = source[offset..].find(keyword) {
+        let start = offset + pos;
+        let end = start + keyword.len();
+        let before = source[..start].chars().next_back();
+        let after = source[end..].chars().next();
+        // Skip `#define KERNEL ...` and identifiers containing the keyword.
+        let line_start = source[..start].rfind('\n').map_or(0, |x| x + 1);
+        if !before.is_some_and(is_ident)
+            && !after.is_some_and(is_ident)
+            && !source[line_start..start].trim_start().starts_with('#')
+        {
+            return Some(start);
+        }
+        offset = end;
+    }
+    None
+}
+
+fn strip_comments(source: &str) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find('/') {
+        result.push_str(&rest[..start]);
+        let comment = &rest[start..];
+        rest = if comment.starts_with("//") {
+            comment.find('\n').map_or("", |end| &comment[end..])
+        } else if comment.starts_with("/*") {
+            comment.find("*/").map_or("", |end| &comment[end + 2..])
+        } else {
+            result.push('/');
+            &comment[1..]
+        };
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kernels() {
+        let source = "
+            #define KERNEL extern \"C\" __global__
+            DEVICE void G1_helper(uint a) {}
+            /* KERNEL void commented_out(uint a) */
+            KERNEL void G1_radix_fft(GLOBAL G1_jacobian* x, // Source
+                                     LOCAL G1_jacobian* u_arg, // Local
+                                     uint n, // Number of elements
+                                     bool flag) {}
+            KERNEL void no_args() {}
+        ";
+        let kernels = KernelSignature::parse_all(
+            source,
+            &Bindings::new().bind("POINT", "G1"),
+        );
+
+        assert_eq!(kernels.len(), 2);
+        assert_eq!(kernels[0].name, "G1_radix_fft");
+        assert_eq!(kernels[0].template, "POINT_radix_fft");
+        assert_eq!(
+            kernels[0].params,
+            vec![
+                KernelParam {
+                    name: "x".into(),
+                    ty: "G1_jacobian".into(),
+                    pointer: true,
+                    address_space: AddressSpace::Global,
+                },
+                KernelParam {
+                    name: "u_arg".into(),
+                    ty: "G1_jacobian".into(),
+                    pointer: true,
+                    address_space: AddressSpace::Local,
+                },
+                KernelParam {
+                    name: "n".into(),
+                    ty: "uint".into(),
+                    pointer: false,
+                    address_space: AddressSpace::Private,
+                },
+                KernelParam {
+                    name: "flag".into(),
+                    ty: "bool".into(),
+                    pointer: false,
+                    address_space: AddressSpace::Private,
+                },
+            ]
+        );
+        assert_eq!(kernels[1].name, "no_args");
+        assert!(kernels[1].params.is_empty());
+    }
+}
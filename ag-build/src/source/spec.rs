@@ -0,0 +1,315 @@
+//! Fields and curves defined by their constants instead of arkworks types.
+//!
+//! [`SourceBuilder::add_field`](crate::SourceBuilder::add_field) takes the
+//! Montgomery constants from the [`GpuField`](ag_types::GpuField)
+//! implementation of an arkworks field. A [`FieldSpec`] computes them from
+//! the modulus, so that kernels for fields without an arkworks type can be
+//! generated, see [`SourceBuilder::add_field_spec`].
+//!
+//! [`SourceBuilder::add_field_spec`]: crate::SourceBuilder::add_field_spec
+
+use super::{bigint, elementwise::is_identifier, template::FieldLimbs};
+
+use std::fmt;
+
+/// An error of defining a field or curve.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpecError {
+    /// The name is not a C identifier.
+    InvalidName(String),
+    /// A number is neither decimal nor `0x`-prefixed hexadecimal.
+    InvalidNumber { name: String, number: String },
+    /// The limb size is neither 32 nor 64 bits.
+    InvalidLimbBits { name: String, limb_bits: u32 },
+    /// The modulus is not odd or smaller than 3, so there is no Montgomery
+    /// form.
+    InvalidModulus { name: String, modulus: String },
+    /// The `a` coefficient of the curve is not zero.
+    UnsupportedCurve { name: String },
+    /// A coefficient of the curve is not smaller than the modulus of its
+    /// base field.
+    InvalidCoefficient { name: String, coefficient: String },
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecError::InvalidName(name) => {
+                write!(f, "`{}` is not a valid name of a field or curve", name)
+            }
+            SpecError::InvalidNumber { name, number } => {
+                write!(f, "{}: `{}` is not a number", name, number)
+            }
+            SpecError::InvalidLimbBits { name, limb_bits } => write!(
+                f,
+                "{}: limbs have 32 or 64 bits, not {}",
+                name, limb_bits
+            ),
+            SpecError::InvalidModulus { name, modulus } => write!(
+                f,
+                "{}: the modulus {} is not an odd number greater than 1",
+                name, modulus
+            ),
+            SpecError::UnsupportedCurve { name } => write!(
+                f,
+                "{}: the kernels only support curves y^2 = x^3 + b, the `a` \
+                 coefficient must be zero",
+                name
+            ),
+            SpecError::InvalidCoefficient { name, coefficient } => write!(
+                f,
+                "{}: the coefficient {} is not smaller than the modulus of \
+                 the base field",
+                name, coefficient
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+/// A prime field defined by its modulus.
+///
+/// The Montgomery constants are computed for `R = 2^(limb_bits * n)`, where
+/// `n` is the number of `limb_bits`-bit limbs of the modulus, like arkworks
+/// does for 64-bit limbs. Hence a spec with the modulus of an arkworks field
+/// and 64-bit limbs generates the same source as the arkworks field.
+///
+/// ```
+/// use ag_build::{FieldSpec, SourceBuilder};
+///
+/// // The BabyBear field.
+/// let spec = FieldSpec::new("BabyBear", "0x78000001", 64)?;
+/// assert_eq!(spec.one(), [0x45dddde3, 0]);
+/// let source = SourceBuilder::new().add_fft_spec(&spec);
+/// assert_eq!(source.kernels()[0].name, "BabyBear_radix_fft");
+/// # Ok::<(), ag_build::SpecError>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldSpec {
+    name: String,
+    limb_bits: u32,
+    modulus: Vec<u32>,
+    one: Vec<u32>,
+    r2: Vec<u32>,
+}
+
+impl FieldSpec {
+    /// Defines the field with `modulus`, a decimal or `0x`-prefixed
+    /// hexadecimal number, named `name` in the generated source. `limb_bits`
+    /// is 32 or 64, the size of the limbs `R` is a power of.
+    pub fn new(
+        name: &str, modulus: &str, limb_bits: u32,
+    ) -> Result<Self, SpecError> {
+        if !is_identifier(name) {
+            return Err(SpecError::InvalidName(name.to_owned()));
+        }
+        if limb_bits != 32 && limb_bits != 64 {
+            return Err(SpecError::InvalidLimbBits {
+                name: name.to_owned(),
+                limb_bits,
+            });
+        }
+        let mut p = parse(name, modulus)?;
+        if p.is_empty() || p[0] % 2 == 0 || p == [1] {
+            return Err(SpecError::InvalidModulus {
+                name: name.to_owned(),
+                modulus: modulus.to_owned(),
+            });
+        }
+
+        let limb_bits_usize = limb_bits as usize;
+        let r_bits =
+            bigint::bits(&p).div_ceil(limb_bits_usize) * limb_bits_usize;
+        p.resize(r_bits / 32, 0);
+        Ok(Self {
+            name: name.to_owned(),
+            limb_bits,
+            one: bigint::pow2_mod(r_bits, &p),
+            r2: bigint::pow2_mod(2 * r_bits, &p),
+            modulus: p,
+        })
+    }
+
+    /// The name in the generated source.
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn limb_bits(&self) -> u32 { self.limb_bits }
+
+    /// The modulus as 32-bit limbs, least significant limb first.
+    pub fn modulus(&self) -> &[u32] { &self.modulus }
+
+    /// `R mod P`, i.e. `1` in Montgomery form, like
+    /// [`GpuField::one`](ag_types::GpuField::one).
+    pub fn one(&self) -> &[u32] { &self.one }
+
+    /// `R ^ 2 mod P`, like [`GpuField::r2`](ag_types::GpuField::r2).
+    pub fn r2(&self) -> &[u32] { &self.r2 }
+
+    pub(crate) fn limbs(&self) -> FieldLimbs {
+        FieldLimbs {
+            one: self.one.clone(),
+            r2: self.r2.clone(),
+            modulus: self.modulus.clone(),
+        }
+    }
+}
+
+/// A curve `y^2 = x^3 + a * x + b` over the field `base`, whose scalars are
+/// elements of `scalar`, defined by its coefficients.
+///
+/// The kernels implement the point arithmetic for `a = 0`, like that of
+/// BLS12-381 and BN254, so other curves are rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CurveSpec {
+    name: String,
+    base: FieldSpec,
+    scalar: FieldSpec,
+    b: Vec<u32>,
+}
+
+impl CurveSpec {
+    /// Defines the curve with the coefficients `a` and `b`, numbers like the
+    /// modulus of a [`FieldSpec`], whose points are named `name` in the
+    /// generated source.
+    pub fn new(
+        name: &str, base: FieldSpec, scalar: FieldSpec, a: &str, b: &str,
+    ) -> Result<Self, SpecError> {
+        if !is_identifier(name) {
+            return Err(SpecError::InvalidName(name.to_owned()));
+        }
+        if !parse(name, a)?.is_empty() {
+            return Err(SpecError::UnsupportedCurve {
+                name: name.to_owned(),
+            });
+        }
+        let b_limbs = parse(name, b)?;
+        if bigint::cmp(&b_limbs, &base.modulus).is_ge() {
+            return Err(SpecError::InvalidCoefficient {
+                name: name.to_owned(),
+                coefficient: b.to_owned(),
+            });
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            base,
+            scalar,
+            b: b_limbs,
+        })
+    }
+
+    /// The name of the points in the generated source.
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn base(&self) -> &FieldSpec { &self.base }
+
+    pub fn scalar(&self) -> &FieldSpec { &self.scalar }
+
+    /// The `b` coefficient as 32-bit limbs, least significant limb first.
+    pub fn b(&self) -> &[u32] { &self.b }
+}
+
+fn parse(name: &str, number: &str) -> Result<Vec<u32>, SpecError> {
+    bigint::parse(number).ok_or_else(|| SpecError::InvalidNumber {
+        name: name.to_owned(),
+        number: number.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SourceBuilder;
+
+    use ag_types::{GpuField, GpuName};
+    use chosen_ark_suite::{Fq, Fr, G1Affine};
+
+    const FR_MODULUS: &str = concat!(
+        "0x73eda753299d7d483339d80809a1d805",
+        "53bda402fffe5bfeffffffff00000001"
+    );
+    const FQ_MODULUS: &str = concat!(
+        "0x1a0111ea397fe69a4b1ba7b6434bacd764774b84f38512bf",
+        "6730d2a0f6b0f6241eabfffeb153ffffb9feffffffffaaab"
+    );
+
+    #[test]
+    fn test_constants() {
+        for limb_bits in [32, 64] {
+            let fr = FieldSpec::new("Fr", FR_MODULUS, limb_bits).unwrap();
+            assert_eq!(fr.modulus(), Fr::modulus());
+            assert_eq!(fr.one(), Fr::one());
+            assert_eq!(fr.r2(), Fr::r2());
+
+            let fq = FieldSpec::new("Fq", FQ_MODULUS, limb_bits).unwrap();
+            assert_eq!(fq.modulus(), Fq::modulus());
+            assert_eq!(fq.one(), Fq::one());
+            assert_eq!(fq.r2(), Fq::r2());
+        }
+
+        // With 32-bit limbs `R` is the next power of 2^32.
+        let p = FieldSpec::new("P", "0xffffffffffffffffffffffc5", 32).unwrap();
+        assert_eq!(p.modulus().len(), 3);
+        assert_eq!(p.one(), [0x3b, 0, 0]);
+        let p = FieldSpec::new("P", "0xffffffffffffffffffffffc5", 64).unwrap();
+        assert_eq!(p.one(), [0, 0x3b, 0, 0]);
+    }
+
+    #[test]
+    fn test_same_source() {
+        let fr = FieldSpec::new(&Fr::name(), FR_MODULUS, 64).unwrap();
+        let spec = SourceBuilder::new().add_fft_spec(&fr);
+        let ark = SourceBuilder::new().add_fft::<Fr>();
+        assert_eq!(spec.build_32_bit_limbs(), ark.build_32_bit_limbs());
+        assert_eq!(spec.build_64_bit_limbs(), ark.build_64_bit_limbs());
+        assert_eq!(spec.manifest_32_bit_limbs(), ark.manifest_32_bit_limbs());
+
+        let fq = FieldSpec::new(&Fq::name(), FQ_MODULUS, 64).unwrap();
+        let curve =
+            CurveSpec::new(&G1Affine::name(), fq, fr, "0", "4").unwrap();
+        let spec = SourceBuilder::new().add_multiexp_spec(&curve);
+        let ark = SourceBuilder::new().add_multiexp::<G1Affine>();
+        assert_eq!(spec.build_32_bit_limbs(), ark.build_32_bit_limbs());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |modulus, limb_bits| {
+            FieldSpec::new("F", modulus, limb_bits)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error("0x1z", 64), "F: `0x1z` is not a number");
+        assert_eq!(error("17", 16), "F: limbs have 32 or 64 bits, not 16");
+        for modulus in ["0", "1", "0x10"] {
+            assert_eq!(
+                error(modulus, 64),
+                format!(
+                    "F: the modulus {} is not an odd number greater than 1",
+                    modulus
+                )
+            );
+        }
+        assert_eq!(
+            FieldSpec::new("2F", "17", 32),
+            Err(SpecError::InvalidName("2F".to_owned()))
+        );
+
+        let f = FieldSpec::new("F", "17", 32).unwrap();
+        let curve = |a, b| CurveSpec::new("E", f.clone(), f.clone(), a, b);
+        assert!(curve("0", "0x10").is_ok());
+        assert_eq!(
+            curve("1", "3"),
+            Err(SpecError::UnsupportedCurve {
+                name: "E".to_owned()
+            })
+        );
+        assert_eq!(
+            curve("0", "17"),
+            Err(SpecError::InvalidCoefficient {
+                name: "E".to_owned(),
+                coefficient: "17".to_owned()
+            })
+        );
+    }
+}
@@ -5,15 +5,37 @@ use std::{
     marker::PhantomData,
 };
 
-use super::{limb::Limb32Or64, template::*};
+use super::{
+    elementwise::ElementwiseKernel,
+    engine::{render, Bindings, TemplateError},
+    limb::Limb32Or64,
+    map::Segment,
+    spec::{CurveSpec, FieldSpec},
+    template::*,
+};
 
 /// This trait is used to uniquely identify items by some identifier (`name`)
 /// and to return the GPU source code they produce.
 pub trait NameAndSource {
     /// The name to identify the item.
     fn name(&self) -> String;
+    /// The parts of the GPU source code that is generated.
+    fn segments(&self, limb: Limb32Or64) -> Vec<Segment>;
     /// The GPU source code that is generated.
-    fn source(&self, limb: Limb32Or64) -> String;
+    fn source(&self, limb: Limb32Or64) -> String {
+        let texts: Vec<_> = self
+            .segments(limb)
+            .into_iter()
+            .map(|segment| segment.text)
+            .collect();
+        texts.join("\n")
+    }
+    /// The template placeholders and the names they are bound to in the
+    /// generated source.
+    fn bindings(&self) -> Bindings { Bindings::new() }
+    /// The constants of the field, if the item is a field that is not an
+    /// extension field.
+    fn field_limbs(&self) -> Option<FieldLimbs> { None }
 }
 
 impl PartialEq for dyn NameAndSource {
@@ -97,16 +119,19 @@ impl<F: GpuField> NameAndSource for Field<F> {
         }
     }
 
-    fn source(&self, limb: Limb32Or64) -> String {
+    fn segments(&self, limb: Limb32Or64) -> Vec<Segment> {
         match self {
             Self::Field(_) => {
                 // If it's an extension field.
                 if let Some(sub_field_name) = F::sub_field_name() {
-                    String::from(FIELD2_SRC)
-                        .replace("FIELD2", &F::name())
-                        .replace("FIELD", &sub_field_name)
+                    let bindings = Bindings::new()
+                        .name::<F>("FIELD2")
+                        .bind("FIELD", sub_field_name);
+                    let source = FIELD2_SRC.into();
+                    vec![Segment::template("field2.cl", source)
+                        .render(&bindings)]
                 } else {
-                    field_source::<F>(limb).replace("FIELD", &F::name())
+                    field_segments(limb, &F::name(), &FieldLimbs::of::<F>())
                 }
             }
             Self::SubField(sub_field_name) => {
@@ -116,78 +141,223 @@ impl<F: GpuField> NameAndSource for Field<F> {
                 // functions do *not* use the name of the field, else we might
                 // generate the sub-field named like the
                 // extension field.
-                field_source::<F>(limb).replace("FIELD", sub_field_name)
+                field_segments(limb, sub_field_name, &FieldLimbs::of::<F>())
             }
         }
     }
+
+    fn field_limbs(&self) -> Option<FieldLimbs> {
+        match self {
+            Self::Field(_) if F::sub_field_name().is_some() => None,
+            // The sub-field has the constants of the extension field.
+            _ => Some(FieldLimbs::of::<F>()),
+        }
+    }
+}
+
+/// A prime field defined by a [`FieldSpec`].
+pub struct SpecField(FieldSpec);
+
+impl SpecField {
+    pub fn new(spec: FieldSpec) -> Self { Self(spec) }
+}
+
+impl NameAndSource for SpecField {
+    fn name(&self) -> String { self.0.name().to_owned() }
+
+    fn segments(&self, limb: Limb32Or64) -> Vec<Segment> {
+        let limbs = self.0.limbs();
+        if let Limb32Or64::Limb64 = limb {
+            assert_eq!(
+                limbs.modulus.len() % 2,
+                0,
+                "field `{}` has an odd number of 32-bit limbs, it cannot be \
+                 generated with 64-bit limbs",
+                self.0.name()
+            );
+        }
+        field_segments(limb, self.0.name(), &limbs)
+    }
+
+    fn field_limbs(&self) -> Option<FieldLimbs> { Some(self.0.limbs()) }
+}
+
+/// The names of a curve in the generated source.
+pub struct CurveNames {
+    point: String,
+    base: String,
+    scalar: String,
+}
+
+impl CurveNames {
+    pub fn of<C: GpuCurveName>() -> Self {
+        Self {
+            point: C::Affine::name(),
+            base: C::Base::name(),
+            scalar: C::Scalar::name(),
+        }
+    }
+
+    pub fn of_spec(spec: &CurveSpec) -> Self {
+        Self {
+            point: spec.name().to_owned(),
+            base: spec.base().name().to_owned(),
+            scalar: spec.scalar().name().to_owned(),
+        }
+    }
 }
 
 /// Struct that generates FFT GPU source code.
-pub struct Fft<F: GpuName>(PhantomData<F>);
+pub struct Fft {
+    field: String,
+}
 
-impl<F: GpuName> Fft<F> {
-    pub fn new() -> Self { Self(PhantomData) }
+impl Fft {
+    pub fn new(field: String) -> Self { Self { field } }
 }
 
-impl<F: GpuName> NameAndSource for Fft<F> {
-    fn name(&self) -> String { F::name() }
+impl NameAndSource for Fft {
+    fn name(&self) -> String { self.field.clone() }
+
+    fn segments(&self, _limb: Limb32Or64) -> Vec<Segment> {
+        let segment = Segment::template("fft.cl", FFT_SRC.into());
+        vec![segment.render(&self.bindings())]
+    }
 
-    fn source(&self, _limb: Limb32Or64) -> String {
-        String::from(FFT_SRC).replace("FIELD", &F::name())
+    fn bindings(&self) -> Bindings {
+        Bindings::new().bind("FIELD", &self.field)
     }
 }
 
 /// Struct that generates FFT for G1 GPU source code.
-pub struct Ec<C: GpuCurveName>(PhantomData<C>);
+pub struct Ec(CurveNames);
 
-impl<C: GpuCurveName> Ec<C> {
-    pub fn new() -> Self { Self(PhantomData) }
+impl Ec {
+    pub fn new(names: CurveNames) -> Self { Self(names) }
 }
 
-impl<C: GpuCurveName> NameAndSource for Ec<C> {
-    fn name(&self) -> String { C::Affine::name() }
+impl NameAndSource for Ec {
+    fn name(&self) -> String { self.0.point.clone() }
+
+    fn segments(&self, _limb: Limb32Or64) -> Vec<Segment> {
+        let segment = Segment::template("ec.cl", EC_SRC.into());
+        vec![segment.render(&self.bindings())]
+    }
 
-    fn source(&self, _limb: Limb32Or64) -> String {
-        String::from(EC_SRC)
-            .replace("BASE", &C::Base::name())
-            .replace("POINT", &C::Affine::name())
-            .replace("SCALAR", &C::Scalar::name())
+    fn bindings(&self) -> Bindings {
+        Bindings::new()
+            .bind("BASE", &self.0.base)
+            .bind("POINT", &self.0.point)
+            .bind("SCALAR", &self.0.scalar)
     }
 }
 
 /// Struct that generates FFT for G1 GPU source code.
-pub struct EcFft<C: GpuCurveName>(PhantomData<C>);
+pub struct EcFft(CurveNames);
 
-impl<C: GpuCurveName> EcFft<C> {
-    pub fn new() -> Self { Self(PhantomData) }
+impl EcFft {
+    pub fn new(names: CurveNames) -> Self { Self(names) }
 }
 
-impl<C: GpuCurveName> NameAndSource for EcFft<C> {
-    fn name(&self) -> String { C::Affine::name() }
+impl NameAndSource for EcFft {
+    fn name(&self) -> String { self.0.point.clone() }
+
+    fn segments(&self, _limb: Limb32Or64) -> Vec<Segment> {
+        let segment = Segment::template("ec-fft.cl", EC_FFT_SRC.into());
+        vec![segment.render(&self.bindings())]
+    }
 
-    fn source(&self, _limb: Limb32Or64) -> String {
-        String::from(EC_FFT_SRC)
-            .replace("POINT", &C::Affine::name())
-            .replace("SCALAR", &C::Scalar::name())
+    fn bindings(&self) -> Bindings {
+        Bindings::new()
+            .bind("POINT", &self.0.point)
+            .bind("SCALAR", &self.0.scalar)
     }
 }
 
 /// Struct that generates multiexp GPU source code.
-#[derive(Default)]
-pub struct Multiexp<C: GpuCurveName>(PhantomData<C>);
+pub struct Multiexp(CurveNames);
 
-impl<C: GpuCurveName> Multiexp<C> {
-    pub fn new() -> Self { Self(PhantomData) }
+impl Multiexp {
+    pub fn new(names: CurveNames) -> Self { Self(names) }
 }
 
-impl<C: GpuCurveName> NameAndSource for Multiexp<C> {
-    fn name(&self) -> String { C::Affine::name() }
+impl NameAndSource for Multiexp {
+    fn name(&self) -> String { self.0.point.clone() }
+
+    fn segments(&self, _limb: Limb32Or64) -> Vec<Segment> {
+        let segment = Segment::template("multiexp.cl", MULTIEXP_SRC.into());
+        vec![segment.render(&self.bindings())]
+    }
+
+    fn bindings(&self) -> Bindings {
+        Bindings::new()
+            .bind("POINT", &self.0.point)
+            .bind("SCALAR", &self.0.scalar)
+    }
+}
+
+/// A template added with `SourceBuilder::add_template`, instantiated with
+/// its bindings.
+pub struct UserTemplate {
+    name: String,
+    bindings: Bindings,
+    source: String,
+}
+
+impl UserTemplate {
+    pub fn new(
+        name: &str, source: &str, bindings: Bindings,
+    ) -> Result<Self, TemplateError> {
+        Ok(Self {
+            source: render(name, source, &bindings)?,
+            name: name.to_owned(),
+            bindings,
+        })
+    }
+}
+
+impl NameAndSource for UserTemplate {
+    /// The template and its bindings, so that it can be instantiated for
+    /// several fields or curves, but only once for each.
+    fn name(&self) -> String {
+        let values: Vec<_> = self.bindings.iter().map(|(_, v)| v).collect();
+        format!("{}({})", self.name, values.join(", "))
+    }
 
-    fn source(&self, _limb: Limb32Or64) -> String {
-        String::from(MULTIEXP_SRC)
-            .replace("POINT", &C::Affine::name())
-            .replace("SCALAR", &C::Scalar::name())
+    fn segments(&self, _limb: Limb32Or64) -> Vec<Segment> {
+        vec![Segment::user(&self.name, self.source.clone())]
     }
+
+    fn bindings(&self) -> Bindings { self.bindings.clone() }
+}
+
+/// An elementwise kernel added with `SourceBuilder::add_elementwise`.
+pub struct Elementwise<F: GpuField> {
+    kernel: ElementwiseKernel,
+    _field: PhantomData<F>,
+}
+
+impl<F: GpuField> Elementwise<F> {
+    pub fn new(kernel: ElementwiseKernel) -> Self {
+        Self {
+            kernel,
+            _field: PhantomData,
+        }
+    }
+}
+
+impl<F: GpuField> NameAndSource for Elementwise<F> {
+    fn name(&self) -> String {
+        format!("{}({})", self.kernel.name(), F::name())
+    }
+
+    fn segments(&self, _limb: Limb32Or64) -> Vec<Segment> {
+        let segment =
+            Segment::generated("elementwise kernel", self.kernel.template());
+        vec![segment.render(&self.bindings())]
+    }
+
+    fn bindings(&self) -> Bindings { Bindings::new().name::<F>("FIELD") }
 }
 
 #[cfg(test)]
@@ -203,10 +373,15 @@ impl<C: GpuCurveName> Test<C> {
 impl<C: GpuCurveName> NameAndSource for Test<C> {
     fn name(&self) -> String { C::Affine::name() }
 
-    fn source(&self, _limb: Limb32Or64) -> String {
-        String::from(TEST_SRC)
-            .replace("FIELD", &C::Base::name())
-            .replace("POINT", &C::Affine::name())
-            .replace("SCALAR", &C::Scalar::name())
+    fn segments(&self, _limb: Limb32Or64) -> Vec<Segment> {
+        let segment = Segment::template("test.cl", TEST_SRC.into());
+        vec![segment.render(&self.bindings())]
+    }
+
+    fn bindings(&self) -> Bindings {
+        Bindings::new()
+            .name::<C::Base>("FIELD")
+            .name::<C::Affine>("POINT")
+            .name::<C::Scalar>("SCALAR")
     }
 }
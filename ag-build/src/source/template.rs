@@ -1,4 +1,8 @@
-use super::limb::{Limb, Limb32, Limb32Or64, Limb64};
+use super::{
+    engine::Bindings,
+    limb::{Limb, Limb32, Limb32Or64, Limb64},
+    map::Segment,
+};
 use ag_types::GpuField;
 use std::fmt::Write;
 
@@ -21,7 +25,7 @@ pub static TEST_SRC: &str = include_cl!("test.cl");
 
 pub fn const_field<L: Limb>(name: &str, limbs: Vec<L>) -> String {
     format!(
-        "CONSTANT FIELD {} = {{ {{ {} }} }};",
+        "CONSTANT ${{FIELD}} {} = {{ {{ {} }} }};",
         name,
         limbs
             .iter()
@@ -31,29 +35,46 @@ pub fn const_field<L: Limb>(name: &str, limbs: Vec<L>) -> String {
     )
 }
 
-/// Generates CUDA/OpenCL constants and type definitions of prime-field `F`
-pub fn params<F, L>() -> String
-where
-    F: GpuField,
-    L: Limb,
-{
-    let one = L::one_limbs::<F>(); // Get Montgomery form of F::one()
-    let p = L::modulus_limbs::<F>(); // Get field modulus in non-Montgomery form
-    let r2 = L::calculate_r2::<F>();
+/// The constants of a prime field as 32-bit limbs, least significant limb
+/// first, see [`GpuField`].
+pub struct FieldLimbs {
+    /// `R mod P`, i.e. `1` in Montgomery form.
+    pub one: Vec<u32>,
+    /// `R ^ 2 mod P`.
+    pub r2: Vec<u32>,
+    pub modulus: Vec<u32>,
+}
+
+impl FieldLimbs {
+    pub fn of<F: GpuField>() -> Self {
+        Self {
+            one: F::one(),
+            r2: F::r2(),
+            modulus: F::modulus(),
+        }
+    }
+}
+
+/// Generates CUDA/OpenCL constants and type definitions of a prime field
+pub fn params<L: Limb>(field: &FieldLimbs) -> String {
+    let one = L::from_u32_limbs(&field.one); // Montgomery form of one
+    let p = L::from_u32_limbs(&field.modulus); // In non-Montgomery form
+    let r2 = L::from_u32_limbs(&field.r2);
     let limbs = one.len(); // Number of limbs
     let inv = L::calc_inv(p[0]);
-    let limb_def = format!("#define FIELD_limb {}", L::opencl_type());
-    let limbs_def = format!("#define FIELD_LIMBS {}", limbs);
-    let limb_bits_def = format!("#define FIELD_LIMB_BITS {}", L::bits());
-    let p_def = const_field("FIELD_P", p);
-    let r2_def = const_field("FIELD_R2", r2);
-    let one_def = const_field("FIELD_ONE", one);
-    let zero_def = const_field("FIELD_ZERO", vec![L::zero(); limbs]);
-    let inv_def = format!("#define FIELD_INV {}", inv.value());
+    let limb_def = format!("#define ${{FIELD}}_limb {}", L::opencl_type());
+    let limbs_def = format!("#define ${{FIELD}}_LIMBS {}", limbs);
+    let limb_bits_def = format!("#define ${{FIELD}}_LIMB_BITS {}", L::bits());
+    let p_def = const_field("${FIELD}_P", p);
+    let r2_def = const_field("${FIELD}_R2", r2);
+    let one_def = const_field("${FIELD}_ONE", one);
+    let zero_def = const_field("${FIELD}_ZERO", vec![L::zero(); limbs]);
+    let inv_def = format!("#define ${{FIELD}}_INV {}", inv.value());
     let type_def =
-        "typedef struct { FIELD_limb val[FIELD_LIMBS]; } FIELD;".to_string();
+        "typedef struct { ${FIELD}_limb val[${FIELD}_LIMBS]; } ${FIELD};"
+            .to_string();
     let type_repr_def =
-        "typedef struct { FIELD_limb val[FIELD_LIMBS]; } FIELD_repr;"
+        "typedef struct { ${FIELD}_limb val[${FIELD}_LIMBS]; } ${FIELD}_repr;"
             .to_string();
     [
         limb_def,
@@ -70,39 +91,45 @@ where
     .join("\n")
 }
 
-pub fn field_source<F: GpuField>(limb: Limb32Or64) -> String {
-    match limb {
-        Limb32Or64::Limb32 => [
-            params::<F, Limb32>(),
-            field_add_sub_nvidia::<F, Limb32>().expect("preallocated"),
-            String::from(FIELD_SRC),
-        ]
-        .join("\n"),
-        Limb32Or64::Limb64 => [
-            params::<F, Limb64>(),
-            field_add_sub_nvidia::<F, Limb64>().expect("preallocated"),
-            String::from(FIELD_SRC),
-        ]
-        .join("\n"),
-    }
+/// The source of the prime field with the constants `field`, named `name`.
+pub fn field_segments(
+    limb: Limb32Or64, name: &str, field: &FieldLimbs,
+) -> Vec<Segment> {
+    let bindings = Bindings::new().bind("FIELD", name);
+    let len = field.modulus.len();
+    let (params, add_sub) = match limb {
+        Limb32Or64::Limb32 => (
+            params::<Limb32>(field),
+            field_add_sub_nvidia::<Limb32>(len).expect("preallocated"),
+        ),
+        Limb32Or64::Limb64 => (
+            params::<Limb64>(field),
+            field_add_sub_nvidia::<Limb64>(len / 2).expect("preallocated"),
+        ),
+    };
+    [
+        Segment::generated("field parameters", params),
+        Segment::generated("PTX field addition and subtraction", add_sub),
+        Segment::template("field.cl", String::from(FIELD_SRC)),
+    ]
+    .into_iter()
+    .map(|segment| segment.render(&bindings))
+    .collect()
 }
 
-/// Generates PTX-Assembly implementation of FIELD_add_/FIELD_sub_
-fn field_add_sub_nvidia<F, L>() -> Result<String, std::fmt::Error>
-where
-    F: GpuField,
-    L: Limb,
-{
+/// Generates PTX-Assembly implementation of FIELD_add_/FIELD_sub_ for `len`
+/// limbs
+fn field_add_sub_nvidia<L: Limb>(
+    len: usize,
+) -> Result<String, std::fmt::Error> {
     let mut result = String::new();
     let (ptx_type, ptx_reg) = L::ptx_info();
 
     writeln!(result, "#if defined(OPENCL_NVIDIA) || defined(CUDA)\n")?;
     for op in &["sub", "add"] {
-        let len = L::one_limbs::<F>().len();
-
         writeln!(
             result,
-            "DEVICE FIELD FIELD_{}_nvidia(FIELD a, FIELD b) {{",
+            "DEVICE ${{FIELD}} ${{FIELD}}_{}_nvidia(${{FIELD}} a, ${{FIELD}} b) {{",
             op
         )?;
         if len > 1 {
@@ -1,11 +1,11 @@
 fn main() {
-    use ag_build::{generate, SourceBuilder};
+    use ag_build::{generate_from_metadata, BuildPolicy};
 
-    let source = SourceBuilder::new()
-        .add_ec_fft::<ark_bls12_381::G1Affine>()
-        .add_multiexp::<ark_bls12_381::G1Affine>()
-        .add_ec_fft::<ark_bn254::G1Affine>()
-        .add_multiexp::<ark_bn254::G1Affine>();
-
-    generate(&source);
+    // The kernels are listed in `[package.metadata.ag-build]`, only those of
+    // the enabled curve features are generated.
+    //
+    // Crates depending on this one still build without the CUDA toolkit, the
+    // workspace then fails to load at run time.
+    let policy = BuildPolicy::from_env().unwrap_or(BuildPolicy::Stub);
+    generate_from_metadata(policy);
 }
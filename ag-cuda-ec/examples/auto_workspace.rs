@@ -0,0 +1,91 @@
+//! Calls the wrappers `auto_workspace` generates for the signatures it
+//! supports. Building the example checks that they compile, running it
+//! needs a GPU.
+
+mod workspace {
+    use ag_cuda_proxy::CudaWorkspace;
+    use ag_cuda_workspace_macro::construct_workspace;
+
+    const FATBIN: &[u8] = include_bytes!(env!("_EC_GPU_CUDA_KERNEL_FATBIN"));
+
+    // Public in the crate for the functions of the sibling module.
+    construct_workspace!(pub(crate) || CudaWorkspace::from_bytes(FATBIN));
+}
+
+mod calls {
+    use ag_cuda_proxy::{ActiveWorkspace, CudaError, KernelArg, LoadError};
+    use ag_cuda_workspace_macro::auto_workspace;
+
+    /// An error type of the caller, only convertible from `CudaError` and
+    /// `LoadError`.
+    #[derive(Debug)]
+    pub enum Error {
+        Cuda(CudaError),
+        Load(LoadError),
+        Empty,
+    }
+
+    impl From<CudaError> for Error {
+        fn from(error: CudaError) -> Self { Error::Cuda(error) }
+    }
+
+    impl From<LoadError> for Error {
+        fn from(error: LoadError) -> Self { Error::Load(error) }
+    }
+
+    /// A generic function with a where clause, a parameter named like the
+    /// workspace of the `_with` wrappers and parameters with patterns.
+    #[auto_workspace(
+        async,
+        global = super::workspace::GLOBAL,
+        pool = super::workspace::POOL
+    )]
+    pub fn count<T, const N: usize>(
+        active: &ActiveWorkspace, workspace: &[[T; N]], mut skip: usize,
+        (first, last): (usize, usize),
+    ) -> Result<usize, Error>
+    where
+        T: KernelArg + Sync,
+    {
+        active.stream()?;
+        if workspace.is_empty() {
+            return Err(Error::Empty);
+        }
+        skip += first;
+        Ok((workspace.len() * N).saturating_sub(skip + last))
+    }
+}
+
+/// Fails to compile unless `future` is `Send`.
+fn assert_send<F: Send>(future: F) -> F { future }
+
+fn report(wrapper: &str, result: Result<usize, calls::Error>) {
+    match result {
+        Ok(count) => println!("{}: {}", wrapper, count),
+        Err(calls::Error::Cuda(e)) => println!("{}: {}", wrapper, e),
+        Err(calls::Error::Load(e)) => println!("{}: {}", wrapper, e),
+        Err(calls::Error::Empty) => println!("{}: empty", wrapper),
+    }
+}
+
+fn main() {
+    let values = [[1u32, 2], [3, 4]];
+
+    report("st", calls::count_st(&values, 1, (0, 1)));
+    report("mt", calls::count_mt(&values, 1, (0, 1)));
+    match workspace::GLOBAL.get() {
+        Ok(global) => {
+            report("with", calls::count_with(global, &values, 1, (0, 1)));
+            // The futures of the async wrappers are awaited, not leaked.
+            let with =
+                unsafe { calls::count_with_async(global, &values, 1, (0, 1)) };
+            report("with_async", ag_cuda_proxy::block_on(with));
+        }
+        Err(e) => println!("no global workspace: {}", e),
+    }
+
+    let st = assert_send(unsafe { calls::count_st_async(&values, 1, (0, 1)) });
+    report("st_async", ag_cuda_proxy::block_on(st));
+    let mt = assert_send(unsafe { calls::count_mt_async(&values, 1, (0, 1)) });
+    report("mt_async", ag_cuda_proxy::block_on(mt));
+}
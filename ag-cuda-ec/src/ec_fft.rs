@@ -1,18 +1,20 @@
-use crate::pairing_suite::{Affine, Curve, Scalar};
-use ag_cuda_proxy::{ActiveWorkspace, DeviceParam, KernelConfig};
+use crate::{
+    kernels::{KernelLaunchers, PointRadixFftArgs},
+    pairing_suite::{Affine, Curve, Scalar},
+};
+use ag_cuda_proxy::{
+    ActiveWorkspace, DeviceParam, KernelConfig, LaunchResult, PointerArg,
+};
 use ag_cuda_workspace_macro::auto_workspace;
-use ag_types::GpuName;
 use ark_ff::Field;
 use ark_std::Zero;
-use rustacuda::error::CudaResult;
-use std::time::Instant;
 
-use crate::{GLOBAL, LOCAL};
+use crate::{GLOBAL, POOL};
 
-#[auto_workspace]
+#[auto_workspace(async)]
 pub fn radix_ec_fft(
     workspace: &ActiveWorkspace, input: &mut Vec<Curve>, omegas: &[Scalar],
-) -> CudaResult<()> {
+) -> LaunchResult<()> {
     const MAX_LOG2_RADIX: u32 = 8;
 
     let n = input.len();
@@ -31,7 +33,7 @@ pub fn radix_ec_fft(
     let stream = workspace.stream()?;
     input_gpu.to_device(&stream)?;
 
-    let mut kernel = workspace.create_kernel()?;
+    let mut kernel = workspace.create_kernel_on(&stream);
 
     // Specifies log2 of `p`, (http://www.bealto.com/gpu-fft_group-1.html)
     let mut log_p = 0u32;
@@ -65,29 +67,18 @@ pub fn radix_ec_fft(
                 * physical_local_work_size as usize,
         };
 
-        let kernel_name = format!("{}_radix_fft", Affine::name());
-        dbg!(kernel_name.clone());
-
-        let now = Instant::now();
-
-        kernel = kernel
-            .func(&kernel_name)?
-            .dev_arg(&input_gpu)?
-            .dev_arg(&output_gpu)?
-            .in_ref(&twiddle)?
-            .in_ref_slice(&omegas[..])?
-            .empty()?
-            .val(n)?
-            .val(log_p)?
-            .val(deg)?
-            .val(virtual_local_work_size)?
-            .val(max_deg)?
-            .launch(config)?
-            .complete()?;
-
-        let dur = now.elapsed().as_secs() * 1000
-            + now.elapsed().subsec_millis() as u64;
-        println!("GPU (inner) took {}ms.", dur);
+        let args = PointRadixFftArgs {
+            x: (&input_gpu).into(),
+            y: (&output_gpu).into(),
+            pq: PointerArg::in_ref(&twiddle),
+            omegas: PointerArg::in_slice(omegas),
+            n,
+            lgp: log_p,
+            deg,
+            vbs: virtual_local_work_size,
+            max_deg,
+        };
+        kernel = kernel.point_radix_fft::<Affine>(config, args)?.complete()?;
 
         log_p += deg;
         DeviceParam::swap_device_pointer(&mut input_gpu, &mut output_gpu);
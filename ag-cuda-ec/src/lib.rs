@@ -1,4 +1,9 @@
 pub mod ec_fft;
+pub mod kernels {
+    //! Typed launchers for the kernels in the embedded fatbin, generated by
+    //! `ag-build` from the kernel signatures.
+    include!(env!("_EC_GPU_CUDA_KERNEL_LAUNCHERS"));
+}
 pub mod multiexp;
 pub mod pairing_suite;
 pub mod test_tools;
@@ -9,5 +14,8 @@ use ag_cuda_workspace_macro::construct_workspace;
 
 const FATBIN: &'static [u8] =
     include_bytes!(env!("_EC_GPU_CUDA_KERNEL_FATBIN"));
+const MANIFEST: &str = include_str!(env!("_EC_GPU_CUDA_KERNEL_MANIFEST"));
 
-construct_workspace!(|| CudaWorkspace::from_bytes(FATBIN).unwrap());
+construct_workspace!(|| CudaWorkspace::from_bytes_with_manifest(
+    FATBIN, MANIFEST
+));
@@ -1,29 +1,44 @@
-use crate::pairing_suite::{Affine, Curve, Scalar};
-use ag_cuda_proxy::{ActiveWorkspace, DeviceData, KernelConfig};
+use crate::{
+    kernels::{KernelLaunchers, PointMultiexpArgs},
+    pairing_suite::{Affine, Curve, Scalar},
+};
+use ag_cuda_proxy::{
+    ActiveWorkspace, DeviceData, KernelConfig, LaunchResult, PointerArg,
+};
 use ag_cuda_workspace_macro::auto_workspace;
-use ag_types::{GpuName, GpuRepr, PrimeFieldRepr};
+use ag_types::{GpuRepr, PrimeFieldRepr};
 use ark_std::Zero;
-use rustacuda::error::CudaResult;
-use std::time::Instant;
+use zeroize::Zeroizing;
 
-use crate::{GLOBAL, LOCAL};
+use crate::{GLOBAL, POOL};
 
 #[auto_workspace]
 pub fn upload_multiexp_bases(
     workspace: &ActiveWorkspace, bases: &[Affine],
-) -> CudaResult<DeviceData> {
+) -> LaunchResult<DeviceData> {
     let bases_gpu_repr: Vec<_> =
         bases.iter().map(GpuRepr::to_gpu_repr).collect();
     let stream = workspace.stream()?;
-    DeviceData::upload(&bases_gpu_repr, &stream)
+    Ok(DeviceData::upload(&bases_gpu_repr, &stream)?)
 }
 
-#[auto_workspace]
+/// Converts the exponents to the representation taken by
+/// [`multiple_multiexp`]. The copy is zeroized on drop, as exponents are
+/// usually derived from a private witness.
+pub fn exponents_repr(
+    exponents: &[Scalar],
+) -> Zeroizing<Vec<<Scalar as PrimeFieldRepr>::Repr>> {
+    Zeroizing::new(exponents.iter().map(PrimeFieldRepr::to_bigint).collect())
+}
+
+/// The exponents and the buckets, which depend on them, are zeroed on the
+/// device after the call.
+#[auto_workspace(async)]
 pub fn multiple_multiexp(
     workspace: &ActiveWorkspace, bases_gpu: &DeviceData,
     exponents: &[<Scalar as PrimeFieldRepr>::Repr], num_chunks: usize,
     window_size: usize, neg_is_cheap: bool,
-) -> CudaResult<Vec<Curve>> {
+) -> LaunchResult<Vec<Curve>> {
     let num_windows = (256 + window_size - 1) / window_size;
     let num_bases =
         bases_gpu.size() / std::mem::size_of::<<Affine as GpuRepr>::Repr>();
@@ -41,9 +56,8 @@ pub fn multiple_multiexp(
 
     let buckets = DeviceData::uninitialized(
         work_units * bucket_len * std::mem::size_of::<Curve>(),
-    )?;
-
-    let kernel = workspace.create_kernel()?;
+    )?
+    .sensitive();
 
     let local_work_size = num_windows; // most efficient: 32 - 128
     let global_work_size = work_units / local_work_size;
@@ -54,41 +68,33 @@ pub fn multiple_multiexp(
         shared_mem: 0,
     };
 
-    let kernel_name = format!("{}_multiexp", Affine::name());
-
-    let now = Instant::now();
-
-    kernel
-        .func(&kernel_name)?
-        .dev_data(bases_gpu)?
-        .out_slice(&mut output)?
-        .in_ref_slice(&exponents)?
-        .dev_data(&buckets)?
-        .val(input_len as u32)?
-        .val(num_lines as u32)?
-        .val(num_chunks as u32)?
-        .val(num_windows as u32)?
-        .val(window_size as u32)?
-        .val(neg_is_cheap)?
-        .launch(config)?
+    let args = PointMultiexpArgs {
+        bases: bases_gpu.into(),
+        results: PointerArg::out_slice(&mut output),
+        exps: PointerArg::in_slice(exponents).sensitive(),
+        buckets: (&buckets).into(),
+        line_len: input_len as u32,
+        n_lines: num_lines as u32,
+        n_chunks: num_chunks as u32,
+        n_chunk_threads: num_windows as u32,
+        window_bits: window_size as u32,
+        neg_is_cheap: neg_is_cheap as u32,
+    };
+    workspace
+        .point_multiexp::<Affine>(config, args)?
         .complete()?;
 
-    let dur =
-        now.elapsed().as_secs() * 1000 + now.elapsed().subsec_millis() as u64;
-    println!("GPU (inner) took {}ms.", dur);
-
     Ok(output)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::pairing_suite::{Curve, Scalar};
-    use ag_types::PrimeFieldRepr;
     use ark_ec::VariableBaseMSM;
     use ark_std::rand::thread_rng;
 
     use super::*;
-    use crate::test_tools::random_input;
+    use crate::test_tools::{join_on_threads, random_input};
 
     #[test]
     fn test_multiexp_batch() {
@@ -103,8 +109,7 @@ mod tests {
         let exponents = random_input::<Scalar, _>(INPUT_LEN, &mut rng);
 
         let bases_gpu = upload_multiexp_bases_mt(&bases).unwrap();
-        let exponents_repr: Vec<_> =
-            exponents.iter().map(|x| x.to_bigint()).collect();
+        let exponents_repr = exponents_repr(&exponents);
 
         let cpu_output: Vec<_> = bases
             .chunks(CHUNK_SIZE)
@@ -142,6 +147,66 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_multiexp_async() {
+        let mut rng = thread_rng();
+
+        const CHUNK_SIZE: usize = 64;
+        const CHUNK_NUM: usize = 16;
+        const INPUT_LEN: usize = CHUNK_SIZE * CHUNK_NUM;
+
+        let bases = random_input(INPUT_LEN, &mut rng);
+        let exponents = random_input::<Scalar, _>(INPUT_LEN, &mut rng);
+
+        let bases_gpu = upload_multiexp_bases_mt(&bases).unwrap();
+        let exponents_repr = exponents_repr(&exponents);
+
+        let cpu_output: Vec<_> = bases
+            .chunks(CHUNK_SIZE)
+            .zip(exponents_repr.chunks(CHUNK_SIZE))
+            .map(|(bs, er)| Curve::msm_bigint(bs, er))
+            .collect();
+
+        // The calls share the pool and the global workspace while the
+        // executor moves them between its threads. Their futures are all
+        // awaited.
+        let window_sizes = 1..=8;
+        let futures = window_sizes
+            .clone()
+            .map(|window_size| {
+                let neg_is_cheap = window_size % 2 == 1;
+                if window_size < 6 {
+                    Box::pin(unsafe {
+                        multiple_multiexp_mt_async(
+                            &bases_gpu,
+                            &exponents_repr,
+                            CHUNK_NUM,
+                            window_size,
+                            neg_is_cheap,
+                        )
+                    }) as _
+                } else {
+                    Box::pin(unsafe {
+                        multiple_multiexp_st_async(
+                            &bases_gpu,
+                            &exponents_repr,
+                            CHUNK_NUM,
+                            window_size,
+                            neg_is_cheap,
+                        )
+                    }) as _
+                }
+            })
+            .collect();
+
+        let outputs: Vec<LaunchResult<Vec<Curve>>> =
+            join_on_threads(futures, 4);
+        assert_eq!(outputs.len(), window_sizes.count());
+        for gpu_output in outputs {
+            assert_eq!(gpu_output.unwrap(), cpu_output);
+        }
+    }
 }
 
 #[cfg(feature = "never")]
@@ -149,7 +214,7 @@ mod tests {
 pub fn multiexp_gpu(
     workspace: &ActiveWorkspace, bases: &[<Affine as GpuRepr>::Repr],
     exponents: &[<Scalar as PrimeFieldRepr>::Repr],
-) -> CudaResult<Affine> {
+) -> LaunchResult<Affine> {
     const MAX_WINDOW_SIZE: usize = 10;
     let work_units = 128 * 256; // TODO device.work_units
     let num_terms = bases.len();
@@ -187,8 +252,6 @@ pub fn multiexp_gpu(
 
     let kernel_name = format!("{}_multiexp", Affine::name());
 
-    let now = Instant::now();
-
     //let mut output_gpu = DeviceParam::new(&mut output)?;
 
     //dbg!(bases.len(), bucket.len(), output.len(), exponents.len(), num_terms,
@@ -207,10 +270,6 @@ pub fn multiexp_gpu(
         .launch(config)?
         .complete()?;
 
-    let dur =
-        now.elapsed().as_secs() * 1000 + now.elapsed().subsec_millis() as u64;
-    println!("GPU (inner) took {}ms.", dur);
-
     output_gpu.to_host(&stream)?;
 
     let mut acc = Curve::zero();
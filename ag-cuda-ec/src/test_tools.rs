@@ -1,5 +1,16 @@
 use ark_ff::UniformRand;
 use ark_std::rand::Rng;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread,
+};
 
 pub fn random_input<T: UniformRand, R: Rng>(
     length: usize, rng: &mut R,
@@ -13,3 +24,127 @@ pub fn random_input_by_cycle<T: UniformRand + Clone, R: Rng>(
     let meta = random_input(period, rng);
     meta.iter().cycle().cloned().take(length).collect()
 }
+
+type Task<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Runs the futures on `threads` threads and returns their outputs. Like a
+/// multi-threaded runtime, a future may be polled on another thread after
+/// each wakeup, so the futures must be `Send`.
+pub fn join_on_threads<'a, T: Send + 'a>(
+    futures: Vec<Task<'a, T>>, threads: usize,
+) -> Vec<T> {
+    let queue = Arc::new(TaskQueue {
+        indices: Mutex::new((0..futures.len()).collect()),
+        available: Condvar::new(),
+        remaining: AtomicUsize::new(futures.len()),
+    });
+    let slots: Vec<_> = futures
+        .into_iter()
+        .map(|future| {
+            Mutex::new(TaskSlot {
+                future: Some(future),
+                running: false,
+                woken: false,
+            })
+        })
+        .collect();
+    let outputs: Vec<_> = slots.iter().map(|_| Mutex::new(None)).collect();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while let Some(index) = queue.pop() {
+                    let mut future = {
+                        let mut slot = slots[index].lock().unwrap();
+                        if slot.running {
+                            slot.woken = true;
+                            continue;
+                        }
+                        match slot.future.take() {
+                            Some(future) => {
+                                slot.running = true;
+                                future
+                            }
+                            None => continue,
+                        }
+                    };
+
+                    let waker = Waker::from(Arc::new(TaskWaker {
+                        index,
+                        queue: queue.clone(),
+                    }));
+                    let poll =
+                        future.as_mut().poll(&mut Context::from_waker(&waker));
+
+                    let mut slot = slots[index].lock().unwrap();
+                    slot.running = false;
+                    match poll {
+                        Poll::Ready(output) => {
+                            *outputs[index].lock().unwrap() = Some(output);
+                            queue.finish_one();
+                        }
+                        Poll::Pending => {
+                            slot.future = Some(future);
+                            if std::mem::take(&mut slot.woken) {
+                                queue.push(index);
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    outputs
+        .into_iter()
+        .map(|output| output.into_inner().unwrap().unwrap())
+        .collect()
+}
+
+struct TaskSlot<'a, T> {
+    future: Option<Task<'a, T>>,
+    running: bool,
+    woken: bool,
+}
+
+struct TaskQueue {
+    indices: Mutex<VecDeque<usize>>,
+    available: Condvar,
+    remaining: AtomicUsize,
+}
+
+impl TaskQueue {
+    fn push(&self, index: usize) {
+        self.indices.lock().unwrap().push_back(index);
+        self.available.notify_one();
+    }
+
+    /// The next task to poll, `None` once all the tasks are finished.
+    fn pop(&self) -> Option<usize> {
+        let mut indices = self.indices.lock().unwrap();
+        loop {
+            if let Some(index) = indices.pop_front() {
+                return Some(index);
+            }
+            if self.remaining.load(Ordering::SeqCst) == 0 {
+                return None;
+            }
+            indices = self.available.wait(indices).unwrap();
+        }
+    }
+
+    fn finish_one(&self) {
+        let _indices = self.indices.lock().unwrap();
+        self.remaining.fetch_sub(1, Ordering::SeqCst);
+        self.available.notify_all();
+    }
+}
+
+struct TaskWaker {
+    index: usize,
+    queue: Arc<TaskQueue>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) { self.queue.push(self.index); }
+}
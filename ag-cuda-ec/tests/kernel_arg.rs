@@ -0,0 +1,52 @@
+#![cfg(feature = "bn254")]
+
+use std::mem::size_of;
+
+use ag_cuda_proxy::{KernelArg, PointerArg, ValueArg};
+use ag_cuda_workspace_macro::KernelArg;
+use ark_bn254::{Fr, G1Projective};
+use ark_ff::Field;
+
+#[derive(Clone, Copy, Debug, PartialEq, KernelArg)]
+#[repr(C)]
+struct Pair {
+    a: u32,
+    b: u32,
+}
+
+#[derive(Clone, Copy, KernelArg)]
+#[repr(transparent)]
+struct Scalar(Fr);
+
+#[derive(Clone, Copy, KernelArg)]
+#[repr(C)]
+struct Nested {
+    pair: Pair,
+    limbs: [u64; 2],
+    scalar: Fr,
+    point: G1Projective,
+}
+
+fn is_kernel_arg<T: KernelArg>() -> usize {
+    let () = T::ASSERT_LAYOUT;
+    size_of::<T>()
+}
+
+#[test]
+fn test_derive() {
+    assert_eq!(is_kernel_arg::<Pair>(), 8);
+    assert_eq!(is_kernel_arg::<Scalar>(), size_of::<Fr>());
+    assert_eq!(is_kernel_arg::<Nested>(), 8 + 16 + 32 + 3 * 32);
+    assert_eq!(is_kernel_arg::<[Nested; 2]>(), 2 * size_of::<Nested>());
+
+    // The derived types are accepted wherever the kernels take arguments.
+    let pairs = [Pair { a: 1, b: 2 }; 4];
+    let _ = PointerArg::in_slice(&pairs);
+    let _ = ValueArg::new(Scalar(Fr::ONE));
+    let _ = ValueArg::new(Nested {
+        pair: pairs[0],
+        limbs: [3, 4],
+        scalar: Fr::ONE,
+        point: G1Projective::default(),
+    });
+}
@@ -0,0 +1,441 @@
+//! Non-blocking completion of kernel tasks and blocking calls.
+//!
+//! A [`TaskCompletion`] adds a callback behind the work of a task to its
+//! stream. The driver calls it once the work is done, which wakes the
+//! future, so awaiting a task neither parks the executor thread nor polls
+//! the device.
+//!
+//! A [`BlockingTask`] runs a blocking call, e.g. an `auto_workspace` wrapper,
+//! on a thread of a bounded pool and resolves to its result. It is `Send` if
+//! the call and its result are, so it can be spawned on a multi-threaded
+//! executor, while the workspace is activated on the thread of the call.
+
+use crate::{
+    kernel::{Kernel, KernelTask},
+    stream::CudaStream,
+};
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+use rustacuda::error::CudaResult;
+
+/// The result of a task shared between the future and the thread finishing
+/// it.
+struct CompletionState<T> {
+    inner: Mutex<StateInner<T>>,
+    finished: Condvar,
+}
+
+struct StateInner<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+impl<T> Default for CompletionState<T> {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(StateInner {
+                result: None,
+                waker: None,
+            }),
+            finished: Condvar::new(),
+        }
+    }
+}
+
+impl<T> CompletionState<T> {
+    fn finish(&self, result: T) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.result = Some(result);
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+        self.finished.notify_all();
+    }
+
+    fn poll(&self, waker: &Waker) -> Option<T> {
+        let mut inner = self.inner.lock().unwrap();
+        let result = inner.result.take();
+        if result.is_none() {
+            inner.waker = Some(waker.clone());
+        }
+        result
+    }
+
+    fn is_finished(&self) -> bool {
+        self.inner.lock().unwrap().result.is_some()
+    }
+
+    /// Blocks until the result is set, unless it was already taken.
+    fn wait(&self) {
+        let inner = self.inner.lock().unwrap();
+        drop(
+            self.finished
+                .wait_while(inner, |inner| inner.result.is_none())
+                .unwrap(),
+        );
+    }
+}
+
+/// A future resolving once the work queued on a stream so far is completed.
+pub(crate) struct StreamCompletion {
+    stream: CudaStream,
+    state: Arc<CompletionState<CudaResult<()>>>,
+    finished: bool,
+}
+
+impl StreamCompletion {
+    pub(crate) fn new(stream: &CudaStream) -> CudaResult<Self> {
+        let state = Arc::new(CompletionState::default());
+        let callback_state = state.clone();
+        stream.driver().add_callback(Box::new(move |status| {
+            callback_state.finish(status)
+        }))?;
+        Ok(Self {
+            stream: stream.clone(),
+            state,
+            finished: false,
+        })
+    }
+}
+
+impl Future for StreamCompletion {
+    type Output = CudaResult<()>;
+
+    fn poll(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        assert!(!self.finished, "StreamCompletion polled after completion");
+        match self.state.poll(cx.waker()) {
+            None => Poll::Pending,
+            Some(result) => {
+                self.finished = true;
+                Poll::Ready(result)
+            }
+        }
+    }
+}
+
+impl Drop for StreamCompletion {
+    fn drop(&mut self) {
+        if !self.finished && !self.state.is_finished() {
+            let _ = self.stream.synchronize();
+        }
+    }
+}
+
+/// A future resolving to the kernel once all the work of a launched task,
+/// including copying the outputs back to the host, is completed.
+///
+/// Created by [`PendingTask::completion`](crate::PendingTask::completion).
+/// Dropping an unfinished `TaskCompletion` blocks until the stream is
+/// synchronized, as the device may still write to the borrowed outputs. Like
+/// the task, it is not `Send`, it has to be awaited on the thread the
+/// workspace is active on.
+pub struct TaskCompletion<'a, 'b> {
+    // Dropped first, so that the stream is done before the parameters of the
+    // task are released.
+    completion: StreamCompletion,
+    task: Option<KernelTask<'a, 'b>>,
+}
+
+impl<'a, 'b> TaskCompletion<'a, 'b> {
+    pub(crate) fn new(task: KernelTask<'a, 'b>) -> CudaResult<Self> {
+        Ok(Self {
+            completion: StreamCompletion::new(task.stream())?,
+            task: Some(task),
+        })
+    }
+}
+
+impl<'a, 'b> Future for TaskCompletion<'a, 'b> {
+    type Output = CudaResult<Kernel<'a>>;
+
+    fn poll(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        match Pin::new(&mut self.completion).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(result) => {
+                let task = self
+                    .task
+                    .take()
+                    .expect("TaskCompletion polled after completion");
+                Poll::Ready(result.map(|()| task.into_kernel()))
+            }
+        }
+    }
+}
+
+/// The calls sent to the threads of the pool.
+type Call = Box<dyn FnOnce() + Send>;
+
+/// The threads running blocking calls. Threads are started while more calls
+/// are queued than threads wait for them, up to the available parallelism,
+/// and are kept for later calls. Further calls wait for a free thread.
+struct BlockingPool {
+    queue: Mutex<BlockingQueue>,
+    queued: Condvar,
+}
+
+struct BlockingQueue {
+    calls: VecDeque<Call>,
+    threads: usize,
+    idle: usize,
+}
+
+static BLOCKING_POOL: BlockingPool = BlockingPool {
+    queue: Mutex::new(BlockingQueue {
+        calls: VecDeque::new(),
+        threads: 0,
+        idle: 0,
+    }),
+    queued: Condvar::new(),
+};
+
+impl BlockingPool {
+    fn max_threads() -> usize {
+        thread::available_parallelism().map_or(1, usize::from)
+    }
+
+    /// Queues `call`, which must not panic.
+    fn run(&'static self, call: Call) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.calls.push_back(call);
+        if queue.calls.len() > queue.idle && queue.threads < Self::max_threads()
+        {
+            queue.threads += 1;
+            drop(queue);
+            thread::Builder::new()
+                .name("cuda-blocking".into())
+                .spawn(move || self.work())
+                .expect("Cannot spawn a thread of the blocking pool.");
+        } else {
+            drop(queue);
+            self.queued.notify_one();
+        }
+    }
+
+    fn work(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            while let Some(call) = queue.calls.pop_front() {
+                drop(queue);
+                call();
+                queue = self.queue.lock().unwrap();
+            }
+            queue.idle += 1;
+            queue = self
+                .queued
+                .wait_while(queue, |queue| queue.calls.is_empty())
+                .unwrap();
+            queue.idle -= 1;
+        }
+    }
+}
+
+/// A future resolving to the result of a blocking call, which runs on a
+/// thread of a bounded pool.
+///
+/// Created by the `_async` functions `#[auto_workspace(async)]` generates.
+/// Dropping an unfinished `BlockingTask` blocks until the call returns, as
+/// the call may still use the borrowed arguments. As the pool is bounded, a
+/// call must not wait for another blocking task, it may never be started.
+pub struct BlockingTask<'a, T> {
+    state: Arc<CompletionState<thread::Result<T>>>,
+    finished: bool,
+    _call: PhantomData<&'a ()>,
+}
+
+impl<'a, T: Send + 'a> BlockingTask<'a, T> {
+    /// Queues `call` on the blocking pool.
+    ///
+    /// # Safety
+    ///
+    /// The task must be awaited or dropped, it must not be leaked, e.g. with
+    /// `std::mem::forget`: the call may borrow data for `'a`, which is only
+    /// guaranteed to stay alive by the drop of the task waiting for the call.
+    pub unsafe fn new<F: FnOnce() -> T + Send + 'a>(call: F) -> Self {
+        let state = Arc::new(CompletionState::default());
+        let call_state = state.clone();
+        let run: Box<dyn FnOnce() + Send + 'a> = Box::new(move || {
+            call_state.finish(panic::catch_unwind(AssertUnwindSafe(call)))
+        });
+        // The drop waits for the call, so `'a` outlives it.
+        let run: Call = std::mem::transmute(run);
+        BLOCKING_POOL.run(run);
+        Self {
+            state,
+            finished: false,
+            _call: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> Future for BlockingTask<'a, T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        assert!(!self.finished, "BlockingTask polled after completion");
+        match self.state.poll(cx.waker()) {
+            None => Poll::Pending,
+            Some(result) => {
+                self.finished = true;
+                match result {
+                    Ok(output) => Poll::Ready(output),
+                    Err(payload) => panic::resume_unwind(payload),
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T> Drop for BlockingTask<'a, T> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.state.wait();
+        }
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) { self.0.unpark(); }
+}
+
+/// Runs a future to completion on the current thread, parking it while the
+/// future is pending.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{FakeStream, Op};
+
+    use rustacuda::error::CudaError;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    /// Counts its wakeups.
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) { self.0.fetch_add(1, Ordering::SeqCst); }
+    }
+
+    #[test]
+    fn test_block_on_wakeup() {
+        let state = Arc::new(CompletionState::default());
+        let notifier = state.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            notifier.finish(Ok(()));
+        });
+
+        let result =
+            block_on(std::future::poll_fn(|cx| match state.poll(cx.waker()) {
+                Some(result) => Poll::Ready(result),
+                None => Poll::Pending,
+            }));
+        assert_eq!(result, Ok::<(), CudaError>(()));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_stream_completion() {
+        let fake = FakeStream::new();
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut completion = StreamCompletion::new(&fake.stream()).unwrap();
+        assert_eq!(fake.take_ops(), [Op::Callback]);
+        assert!(Pin::new(&mut completion).poll(&mut cx).is_pending());
+        assert!(Pin::new(&mut completion).poll(&mut cx).is_pending());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+
+        // The driver calls back once the work is done, without a poll.
+        fake.complete(Err(CudaError::LaunchFailed));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            Pin::new(&mut completion).poll(&mut cx),
+            Poll::Ready(Err(CudaError::LaunchFailed))
+        );
+        drop(completion);
+        assert!(fake.take_ops().is_empty());
+
+        // Dropping an unfinished completion waits for the stream.
+        let completion = StreamCompletion::new(&fake.stream()).unwrap();
+        drop(completion);
+        assert_eq!(fake.take_ops(), [Op::Callback, Op::Synchronize]);
+    }
+
+    #[test]
+    fn test_blocking_task() {
+        let input = [1u32, 2, 3];
+        let task = unsafe { BlockingTask::new(|| input.iter().sum::<u32>()) };
+        assert_eq!(block_on(task), 6);
+
+        // Dropping the task waits for the call.
+        let mut finished = false;
+        let task = unsafe {
+            BlockingTask::new(|| {
+                thread::sleep(Duration::from_millis(10));
+                finished = true;
+            })
+        };
+        drop(task);
+        assert!(finished);
+
+        let task = unsafe { BlockingTask::new(|| panic!("failed call")) };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| block_on(task)));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_blocking_pool() {
+        let (running, peak) = (&AtomicUsize::new(0), &AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..2 * BlockingPool::max_threads() + 1)
+            .map(|i| unsafe {
+                BlockingTask::new(move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i
+                })
+            })
+            .collect();
+        for (i, task) in tasks.into_iter().enumerate() {
+            assert_eq!(block_on(task), i);
+        }
+
+        let peak = peak.load(Ordering::SeqCst);
+        assert!(peak >= 1 && peak <= BlockingPool::max_threads());
+        let threads = BLOCKING_POOL.queue.lock().unwrap().threads;
+        assert!(threads <= BlockingPool::max_threads());
+    }
+}
@@ -1,30 +1,160 @@
+use crate::memory::MemoryAccount;
+
 use rustacuda::{
     context::Context,
     error::{CudaError, CudaResult},
 };
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, ThreadId},
+    time::Duration,
+};
 
 pub struct CudaContext {
     context: Context,
-    in_use: AtomicBool,
+    lock: ThreadLock,
+    memory: Arc<MemoryAccount>,
 }
 
 impl CudaContext {
     pub fn new(context: Context) -> Self {
         Self {
             context,
-            in_use: AtomicBool::new(false),
+            lock: ThreadLock::default(),
+            memory: Arc::default(),
+        }
+    }
+
+    /// The device memory allocated in the context.
+    pub(crate) fn memory(&self) -> &Arc<MemoryAccount> { &self.memory }
+
+    /// Locks the context for the current thread. The lock is reentrant: a
+    /// thread already holding it locks it again immediately. Otherwise waits
+    /// until the holding thread releases it, at most for `timeout` if given,
+    /// and fails with `ContextAlreadyInUse` when the wait times out.
+    pub fn lock(&self, timeout: Option<Duration>) -> CudaResult<&Context> {
+        self.lock.lock(timeout)?;
+        Ok(&self.context)
+    }
+
+    /// Releases one lock of the current thread. Returns whether the context
+    /// is free again.
+    pub fn unlock(&self) -> bool { self.lock.unlock() }
+}
+
+/// A lock held by a thread, which may lock it again while holding it.
+#[derive(Default)]
+pub(crate) struct ThreadLock {
+    owner: Mutex<Owner>,
+    released: Condvar,
+}
+
+/// The thread holding the lock and how many times it locked it.
+#[derive(Default)]
+struct Owner {
+    thread: Option<ThreadId>,
+    depth: usize,
+}
+
+impl ThreadLock {
+    /// Locks for the current thread, see [`CudaContext::lock`].
+    pub(crate) fn lock(&self, timeout: Option<Duration>) -> CudaResult<()> {
+        let current = thread::current().id();
+        let mut owner = self.owner.lock().unwrap();
+
+        if owner.thread != Some(current) {
+            let in_use = |owner: &mut Owner| owner.thread.is_some();
+            owner = match timeout {
+                None => self.released.wait_while(owner, in_use).unwrap(),
+                Some(timeout) => {
+                    self.released
+                        .wait_timeout_while(owner, timeout, in_use)
+                        .unwrap()
+                        .0
+                }
+            };
+            if owner.thread.is_some() {
+                return Err(CudaError::ContextAlreadyInUse);
+            }
+            owner.thread = Some(current);
         }
+
+        owner.depth += 1;
+        Ok(())
     }
 
-    pub fn lock(&self) -> CudaResult<&Context> {
-        let in_use = self.in_use.fetch_or(true, Ordering::SeqCst);
-        if in_use {
-            Err(CudaError::ContextAlreadyInUse)
-        } else {
-            Ok(&self.context)
+    /// Releases one lock of the current thread. Returns whether the lock is
+    /// free again.
+    pub(crate) fn unlock(&self) -> bool {
+        let mut owner = self.owner.lock().unwrap();
+        owner.depth -= 1;
+        if owner.depth > 0 {
+            return false;
         }
+
+        owner.thread = None;
+        drop(owner);
+        self.released.notify_one();
+        true
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_reentrant_depth() {
+        let lock = ThreadLock::default();
+        lock.lock(None).unwrap();
+        lock.lock(Some(Duration::ZERO)).unwrap();
+        assert!(!lock.unlock());
 
-    pub fn unlock(&self) { self.in_use.store(false, Ordering::SeqCst); }
+        thread::scope(|scope| {
+            // Still held once, another thread cannot take it.
+            let other = scope.spawn(|| lock.lock(Some(Duration::ZERO)));
+            assert_eq!(
+                other.join().unwrap(),
+                Err(CudaError::ContextAlreadyInUse)
+            );
+        });
+        assert!(lock.unlock());
+
+        thread::scope(|scope| {
+            let other = scope.spawn(|| {
+                lock.lock(Some(Duration::ZERO))?;
+                Ok::<_, CudaError>(lock.unlock())
+            });
+            assert_eq!(other.join().unwrap(), Ok(true));
+        });
+    }
+
+    #[test]
+    fn test_timeout() {
+        let lock = ThreadLock::default();
+        let (locked, wait) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        thread::scope(|scope| {
+            let lock = &lock;
+            scope.spawn(move || {
+                lock.lock(None).unwrap();
+                locked.send(()).unwrap();
+                released.recv().unwrap();
+                assert!(lock.unlock());
+            });
+            wait.recv().unwrap();
+
+            let timeout = Duration::from_millis(10);
+            assert_eq!(
+                lock.lock(Some(timeout)),
+                Err(CudaError::ContextAlreadyInUse)
+            );
+
+            // Waits until the holder releases it.
+            release.send(()).unwrap();
+            lock.lock(Some(Duration::from_secs(10))).unwrap();
+            assert!(lock.unlock());
+        });
+    }
 }
@@ -1,32 +1,109 @@
-use std::{cell::Cell, thread_local};
+use std::{
+    cell::Cell, marker::PhantomData, sync::Arc, thread_local, time::Duration,
+};
 
-use rustacuda::{context::ContextStack, error::CudaResult};
+use rustacuda::{
+    context::ContextStack,
+    error::{CudaError, CudaResult},
+};
 
-use crate::context::CudaContext;
+use crate::{context::CudaContext, memory::MemoryAccount};
 
 thread_local! {
-    static CONTEXT_GUARD: Cell<bool> = Cell::new(false);
+    /// The context of the workspace activated on this thread.
+    static CONTEXT_GUARD: Cell<Option<*const CudaContext>> =
+        const { Cell::new(None) };
 }
 
-fn lock_cu_context() -> bool { !CONTEXT_GUARD.replace(true) }
+/// Marks `context` as the active one of this thread. Fails if another
+/// context is active, activating the same one again is fine. Returns whether
+/// the context was not active before.
+fn lock_cu_context(context: *const CudaContext) -> CudaResult<bool> {
+    match CONTEXT_GUARD.get() {
+        None => {
+            CONTEXT_GUARD.set(Some(context));
+            Ok(true)
+        }
+        Some(active) if active == context => Ok(false),
+        Some(_) => Err(CudaError::ContextAlreadyInUse),
+    }
+}
 
-fn release_cu_context() { CONTEXT_GUARD.replace(false); }
+fn release_cu_context() { CONTEXT_GUARD.set(None); }
 
-/// A guard guarantee that only a single workspace can be activated at one time.
-pub struct WorkspaceContextGuard<'a>(&'a CudaContext);
+/// The memory account of the workspace activated on this thread.
+pub(crate) fn active_memory() -> Option<Arc<MemoryAccount>> {
+    // The pointer is only set while a guard borrows the context.
+    CONTEXT_GUARD
+        .get()
+        .map(|context| unsafe { (*context).memory().clone() })
+}
+
+/// A guard guarantee that only a single workspace can be activated at one time
+/// on a thread. The same workspace may be activated again while it is active,
+/// e.g. by nested calls.
+///
+/// The context is pushed on the stack of the current thread, so the guard
+/// must not be sent to another thread, even when held across an `.await`.
+pub struct WorkspaceContextGuard<'a>(&'a CudaContext, PhantomData<*const ()>);
 
 impl<'a> WorkspaceContextGuard<'a> {
-    pub(crate) fn new(context: &'a CudaContext) -> CudaResult<Self> {
-        assert!(lock_cu_context(), "Duplicated workspace context");
-        ContextStack::push(&context.lock()?.get_unowned())?;
-        Ok(WorkspaceContextGuard(context))
+    /// Activates the context, waiting at most `timeout` if given while
+    /// another thread uses it.
+    pub(crate) fn new(
+        context: &'a CudaContext, timeout: Option<Duration>,
+    ) -> CudaResult<Self> {
+        let first = lock_cu_context(context)?;
+        let cu_context = match context.lock(timeout) {
+            Ok(cu_context) => cu_context.get_unowned(),
+            Err(e) => {
+                if first {
+                    release_cu_context();
+                }
+                return Err(e);
+            }
+        };
+        if let Err(e) = ContextStack::push(&cu_context) {
+            if context.unlock() {
+                release_cu_context();
+            }
+            return Err(e);
+        }
+        Ok(WorkspaceContextGuard(context, PhantomData))
     }
 }
 
 impl<'a> Drop for WorkspaceContextGuard<'a> {
     fn drop(&mut self) {
         ContextStack::pop().expect("Cannot remove context.");
-        self.0.unlock();
-        release_cu_context();
+        if self.0.unlock() {
+            release_cu_context();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_context_per_thread() {
+        // Only the addresses are compared, the contexts are not accessed.
+        let contexts = [0u8; 2];
+        let [a, b] = contexts.each_ref().map(|c| c as *const u8 as usize);
+
+        // A fresh thread, with no context active yet.
+        std::thread::spawn(move || {
+            let a = a as *const CudaContext;
+            let b = b as *const CudaContext;
+            assert_eq!(lock_cu_context(a), Ok(true));
+            assert_eq!(lock_cu_context(a), Ok(false));
+            assert_eq!(lock_cu_context(b), Err(CudaError::ContextAlreadyInUse));
+            release_cu_context();
+            assert_eq!(lock_cu_context(b), Ok(true));
+            release_cu_context();
+        })
+        .join()
+        .unwrap();
     }
 }
@@ -0,0 +1,200 @@
+//! Device capabilities, queried through a small driver layer so that the
+//! code planning launches can be tested with a fake device instead of a GPU.
+
+use crate::limits::DeviceLimits;
+
+use rustacuda::{
+    device::{Device, DeviceAttribute},
+    error::{CudaError, CudaResult},
+    function::FunctionAttribute,
+};
+#[cfg(any(test, feature = "testing"))]
+use std::collections::HashMap;
+
+/// The driver calls the device information is read from.
+pub(crate) trait Driver {
+    fn name(&self) -> CudaResult<String>;
+    fn total_memory(&self) -> CudaResult<usize>;
+    fn attribute(&self, attribute: DeviceAttribute) -> CudaResult<i32>;
+}
+
+impl Driver for Device {
+    fn name(&self) -> CudaResult<String> { Device::name(*self) }
+
+    fn total_memory(&self) -> CudaResult<usize> { Device::total_memory(*self) }
+
+    fn attribute(&self, attribute: DeviceAttribute) -> CudaResult<i32> {
+        self.get_attribute(attribute)
+    }
+}
+
+/// The driver calls the attributes of a kernel function are read from, see
+/// [`FunctionLimits::query`](crate::limits::FunctionLimits::query).
+pub(crate) trait FunctionDriver {
+    fn attribute(&self, attribute: FunctionAttribute) -> CudaResult<i32>;
+}
+
+/// The capabilities of a device, queried once per workspace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: String,
+    /// The global memory in bytes.
+    pub total_memory: usize,
+    /// The compute capability as `(major, minor)`, e.g. `(8, 6)`.
+    pub compute_capability: (u32, u32),
+    pub multiprocessors: usize,
+    pub max_threads_per_multiprocessor: usize,
+    pub warp_size: usize,
+    pub max_threads_per_block: usize,
+    pub max_block_dim_x: usize,
+    pub max_grid_dim_x: usize,
+    pub max_shared_mem_per_block: usize,
+    pub max_registers_per_block: usize,
+}
+
+impl DeviceInfo {
+    pub(crate) fn query(driver: &impl Driver) -> CudaResult<Self> {
+        let get = |attribute| {
+            let value = driver.attribute(attribute)?;
+            usize::try_from(value).map_err(|_| CudaError::InvalidValue)
+        };
+        let major = get(DeviceAttribute::ComputeCapabilityMajor)?;
+        let minor = get(DeviceAttribute::ComputeCapabilityMinor)?;
+        Ok(Self {
+            name: driver.name()?,
+            total_memory: driver.total_memory()?,
+            compute_capability: (major as u32, minor as u32),
+            multiprocessors: get(DeviceAttribute::MultiprocessorCount)?,
+            max_threads_per_multiprocessor: get(
+                DeviceAttribute::MaxThreadsPerMultiprocessor,
+            )?,
+            warp_size: get(DeviceAttribute::WarpSize)?,
+            max_threads_per_block: get(DeviceAttribute::MaxThreadsPerBlock)?,
+            max_block_dim_x: get(DeviceAttribute::MaxBlockDimX)?,
+            max_grid_dim_x: get(DeviceAttribute::MaxGridDimX)?,
+            max_shared_mem_per_block: get(
+                DeviceAttribute::MaxSharedMemoryPerBlock,
+            )?,
+            max_registers_per_block: get(
+                DeviceAttribute::MaxRegistersPerBlock,
+            )?,
+        })
+    }
+
+    /// The limits launch configurations are checked against.
+    pub fn limits(&self) -> DeviceLimits {
+        DeviceLimits {
+            max_threads_per_block: self.max_threads_per_block,
+            max_block_dim_x: self.max_block_dim_x,
+            max_grid_dim_x: self.max_grid_dim_x,
+            max_shared_mem_per_block: self.max_shared_mem_per_block,
+        }
+    }
+
+    /// The number of threads the device runs at once when fully occupied.
+    pub fn max_resident_threads(&self) -> usize {
+        self.multiprocessors * self.max_threads_per_multiprocessor
+    }
+}
+
+/// An in-memory device for tests without a GPU. It starts with the
+/// attributes of a compute capability 8.6 device, which can be overridden
+/// or removed to test the handling of other devices and of driver errors.
+/// Only available to the tests of this crate and with the `testing` feature.
+#[cfg(any(test, feature = "testing"))]
+#[doc(hidden)]
+#[derive(Clone, Debug)]
+pub struct FakeDevice {
+    name: String,
+    total_memory: usize,
+    attributes: HashMap<DeviceAttribute, i32>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl FakeDevice {
+    pub fn new(name: &str) -> Self {
+        use DeviceAttribute::*;
+        let attributes = [
+            (ComputeCapabilityMajor, 8),
+            (ComputeCapabilityMinor, 6),
+            (MultiprocessorCount, 82),
+            (MaxThreadsPerMultiprocessor, 1536),
+            (WarpSize, 32),
+            (MaxThreadsPerBlock, 1024),
+            (MaxBlockDimX, 1024),
+            (MaxGridDimX, i32::MAX),
+            (MaxSharedMemoryPerBlock, 48 << 10),
+            (MaxRegistersPerBlock, 64 << 10),
+        ];
+        Self {
+            name: name.to_string(),
+            total_memory: 24 << 30,
+            attributes: attributes.into_iter().collect(),
+        }
+    }
+
+    pub fn with_total_memory(mut self, bytes: usize) -> Self {
+        self.total_memory = bytes;
+        self
+    }
+
+    pub fn with_attribute(
+        mut self, attribute: DeviceAttribute, value: i32,
+    ) -> Self {
+        self.attributes.insert(attribute, value);
+        self
+    }
+
+    /// Removes an attribute, querying it then fails with `InvalidValue`.
+    pub fn without_attribute(mut self, attribute: DeviceAttribute) -> Self {
+        self.attributes.remove(&attribute);
+        self
+    }
+
+    /// Queries the device like a workspace queries a real one.
+    pub fn info(&self) -> CudaResult<DeviceInfo> { DeviceInfo::query(self) }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Driver for FakeDevice {
+    fn name(&self) -> CudaResult<String> { Ok(self.name.clone()) }
+
+    fn total_memory(&self) -> CudaResult<usize> { Ok(self.total_memory) }
+
+    fn attribute(&self, attribute: DeviceAttribute) -> CudaResult<i32> {
+        self.attributes
+            .get(&attribute)
+            .copied()
+            .ok_or(CudaError::InvalidValue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_device_info() {
+        let info = FakeDevice::new("fake")
+            .with_total_memory(8 << 30)
+            .with_attribute(DeviceAttribute::MaxThreadsPerBlock, 512)
+            .info()
+            .unwrap();
+        assert_eq!(info.name, "fake");
+        assert_eq!(info.total_memory, 8 << 30);
+        assert_eq!(info.compute_capability, (8, 6));
+        assert_eq!(info.limits().max_threads_per_block, 512);
+        assert_eq!(info.limits().max_shared_mem_per_block, 48 << 10);
+        assert_eq!(info.max_resident_threads(), 82 * 1536);
+
+        let missing = FakeDevice::new("fake")
+            .without_attribute(DeviceAttribute::WarpSize)
+            .info();
+        assert_eq!(missing.err(), Some(CudaError::InvalidValue));
+
+        let negative = FakeDevice::new("fake")
+            .with_attribute(DeviceAttribute::MultiprocessorCount, -1)
+            .info();
+        assert_eq!(negative.err(), Some(CudaError::InvalidValue));
+    }
+}
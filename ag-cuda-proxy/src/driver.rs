@@ -0,0 +1,390 @@
+//! The module, stream and event handles of the driver, owned by the crate.
+//!
+//! rustacuda keeps its handles private, while queuing a memset on a stream
+//! or making several streams wait for one event needs the raw handles. The
+//! handles are only used through the types of this module, which destroy
+//! them on drop.
+
+use cuda_driver_sys::{
+    CUdeviceptr, CUevent, CUfunction, CUfunction_attribute, CUmodule, CUresult,
+    CUstream,
+};
+use rustacuda::{
+    error::{CudaError, CudaResult},
+    function::FunctionAttribute,
+};
+use std::{
+    collections::HashMap,
+    ffi::{c_void, CStr, CString},
+    marker::PhantomData,
+    panic, ptr,
+    sync::Mutex,
+};
+
+use crate::{
+    device::FunctionDriver, kernel::KernelConfig, limits::FunctionLimits,
+    stream::StreamCallback,
+};
+
+const LOG_TARGET: &str = "ag_cuda_proxy::driver";
+
+/// The result of a driver call, with the errors rustacuda reports.
+pub(crate) fn driver_result(status: CUresult) -> CudaResult<()> {
+    use CUresult::*;
+
+    Err(match status {
+        CUDA_SUCCESS => return Ok(()),
+        CUDA_ERROR_INVALID_VALUE => CudaError::InvalidValue,
+        CUDA_ERROR_OUT_OF_MEMORY => CudaError::OutOfMemory,
+        CUDA_ERROR_NOT_INITIALIZED => CudaError::NotInitialized,
+        CUDA_ERROR_DEINITIALIZED => CudaError::Deinitialized,
+        CUDA_ERROR_NO_DEVICE => CudaError::NoDevice,
+        CUDA_ERROR_INVALID_DEVICE => CudaError::InvalidDevice,
+        CUDA_ERROR_INVALID_IMAGE => CudaError::InvalidImage,
+        CUDA_ERROR_INVALID_CONTEXT => CudaError::InvalidContext,
+        CUDA_ERROR_NO_BINARY_FOR_GPU => CudaError::NoBinaryForGpu,
+        CUDA_ERROR_INVALID_PTX => CudaError::InvalidPtx,
+        CUDA_ERROR_INVALID_HANDLE => CudaError::InvalidHandle,
+        CUDA_ERROR_NOT_FOUND => CudaError::NotFound,
+        CUDA_ERROR_NOT_READY => CudaError::NotReady,
+        CUDA_ERROR_ILLEGAL_ADDRESS => CudaError::IllegalAddress,
+        CUDA_ERROR_LAUNCH_OUT_OF_RESOURCES => CudaError::LaunchOutOfResources,
+        CUDA_ERROR_LAUNCH_TIMEOUT => CudaError::LaunchTimeout,
+        CUDA_ERROR_CONTEXT_IS_DESTROYED => CudaError::ContextIsDestroyed,
+        CUDA_ERROR_ASSERT => CudaError::AssertError,
+        CUDA_ERROR_HARDWARE_STACK_ERROR => CudaError::HardwareStackError,
+        CUDA_ERROR_ILLEGAL_INSTRUCTION => CudaError::IllegalInstruction,
+        CUDA_ERROR_MISALIGNED_ADDRESS => CudaError::MisalignedAddress,
+        CUDA_ERROR_INVALID_ADDRESS_SPACE => CudaError::InvalidAddressSpace,
+        CUDA_ERROR_INVALID_PC => CudaError::InvalidProgramCounter,
+        CUDA_ERROR_LAUNCH_FAILED => CudaError::LaunchFailed,
+        CUDA_ERROR_NOT_PERMITTED => CudaError::NotPermitted,
+        CUDA_ERROR_NOT_SUPPORTED => CudaError::NotSupported,
+        status => {
+            log::error!(
+                target: LOG_TARGET,
+                "the CUDA driver failed with {:?}",
+                status
+            );
+            CudaError::UnknownError
+        }
+    })
+}
+
+/// Logs the failure to destroy a handle, which cannot be reported on drop.
+fn log_destroy(status: CUresult, handle: &str) {
+    if let Err(e) = driver_result(status) {
+        log::error!(target: LOG_TARGET, "cannot destroy a {}: {}", handle, e);
+    }
+}
+
+/// A loaded module, unloaded on drop.
+pub(crate) struct Module {
+    raw: CUmodule,
+    /// The functions looked up so far, with their limits.
+    functions: Mutex<HashMap<CString, (CUfunction, FunctionLimits)>>,
+}
+
+impl Module {
+    /// Loads a fatbin, cubin or PTX image in the current context.
+    pub(crate) fn load(image: &[u8]) -> CudaResult<Self> {
+        let mut module = ptr::null_mut();
+        driver_result(unsafe {
+            cuda_driver_sys::cuModuleLoadData(
+                &mut module,
+                image.as_ptr() as *const c_void,
+            )
+        })?;
+        Ok(Self {
+            raw: module,
+            functions: Mutex::default(),
+        })
+    }
+
+    /// The function `name`, whose limits are queried on the first lookup.
+    pub(crate) fn function(&self, name: &CStr) -> CudaResult<Function<'_>> {
+        let mut functions = self.functions.lock().unwrap();
+        if let Some(&(raw, limits)) = functions.get(name) {
+            return Ok(Function {
+                raw,
+                limits,
+                _module: PhantomData,
+            });
+        }
+
+        let mut raw = ptr::null_mut();
+        driver_result(unsafe {
+            cuda_driver_sys::cuModuleGetFunction(
+                &mut raw,
+                self.raw,
+                name.as_ptr(),
+            )
+        })?;
+        let limits = FunctionLimits::query(&RawFunction(raw))?;
+        functions.insert(name.to_owned(), (raw, limits));
+        Ok(Function {
+            raw,
+            limits,
+            _module: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+impl Module {
+    /// A module without a driver handle, for tasks which are not launched.
+    pub(crate) fn fake() -> Self {
+        Self {
+            raw: ptr::null_mut(),
+            functions: Mutex::default(),
+        }
+    }
+
+    /// A function without a driver handle, with `limits`.
+    pub(crate) fn fake_function(&self, limits: FunctionLimits) -> Function<'_> {
+        Function {
+            raw: ptr::null_mut(),
+            limits,
+            _module: PhantomData,
+        }
+    }
+
+    /// Adds a function without a driver handle, so that tasks can look it up
+    /// by `name`.
+    pub(crate) fn insert_fake_function(
+        &self, name: &CStr, limits: FunctionLimits,
+    ) {
+        let mut functions = self.functions.lock().unwrap();
+        functions.insert(name.to_owned(), (ptr::null_mut(), limits));
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        if self.raw.is_null() {
+            return;
+        }
+        log_destroy(
+            unsafe { cuda_driver_sys::cuModuleUnload(self.raw) },
+            "module",
+        );
+    }
+}
+
+/// A kernel function of a [`Module`].
+#[derive(Clone, Copy)]
+pub(crate) struct Function<'a> {
+    raw: CUfunction,
+    limits: FunctionLimits,
+    _module: PhantomData<&'a Module>,
+}
+
+impl Function<'_> {
+    pub(crate) fn limits(&self) -> &FunctionLimits { &self.limits }
+}
+
+/// The handle of a function whose limits are queried.
+struct RawFunction(CUfunction);
+
+impl FunctionDriver for RawFunction {
+    fn attribute(&self, attribute: FunctionAttribute) -> CudaResult<i32> {
+        use CUfunction_attribute::*;
+
+        let attribute = match attribute {
+            FunctionAttribute::MaxThreadsPerBlock => {
+                CU_FUNC_ATTRIBUTE_MAX_THREADS_PER_BLOCK
+            }
+            FunctionAttribute::SharedMemorySizeBytes => {
+                CU_FUNC_ATTRIBUTE_SHARED_SIZE_BYTES
+            }
+            FunctionAttribute::ConstSizeBytes => {
+                CU_FUNC_ATTRIBUTE_CONST_SIZE_BYTES
+            }
+            FunctionAttribute::LocalSizeBytes => {
+                CU_FUNC_ATTRIBUTE_LOCAL_SIZE_BYTES
+            }
+            FunctionAttribute::NumRegisters => CU_FUNC_ATTRIBUTE_NUM_REGS,
+            FunctionAttribute::PtxVersion => CU_FUNC_ATTRIBUTE_PTX_VERSION,
+            FunctionAttribute::BinaryVersion => {
+                CU_FUNC_ATTRIBUTE_BINARY_VERSION
+            }
+            FunctionAttribute::CacheModeCa => CU_FUNC_ATTRIBUTE_CACHE_MODE_CA,
+            _ => return Err(CudaError::InvalidValue),
+        };
+        let mut value = 0;
+        driver_result(unsafe {
+            cuda_driver_sys::cuFuncGetAttribute(&mut value, attribute, self.0)
+        })?;
+        Ok(value)
+    }
+}
+
+/// A non-blocking stream of the context current when it was created,
+/// destroyed on drop.
+pub(crate) struct Stream(CUstream);
+
+impl Stream {
+    pub(crate) fn new() -> CudaResult<Self> {
+        let mut stream = ptr::null_mut();
+        driver_result(unsafe {
+            cuda_driver_sys::cuStreamCreate(
+                &mut stream,
+                cuda_driver_sys::CUstream_flags::CU_STREAM_NON_BLOCKING as u32,
+            )
+        })?;
+        Ok(Self(stream))
+    }
+
+    /// Queues copying `src` to `dst`, which must stay alive until the copy is
+    /// done.
+    pub(crate) unsafe fn copy_to_device(
+        &self, dst: CUdeviceptr, src: &[u8],
+    ) -> CudaResult<()> {
+        driver_result(cuda_driver_sys::cuMemcpyHtoDAsync_v2(
+            dst,
+            src.as_ptr() as *const c_void,
+            src.len(),
+            self.0,
+        ))
+    }
+
+    /// Queues copying `src` to `dst`, which must stay alive until the copy is
+    /// done.
+    pub(crate) unsafe fn copy_to_host(
+        &self, dst: &mut [u8], src: CUdeviceptr,
+    ) -> CudaResult<()> {
+        driver_result(cuda_driver_sys::cuMemcpyDtoHAsync_v2(
+            dst.as_mut_ptr() as *mut c_void,
+            src,
+            dst.len(),
+            self.0,
+        ))
+    }
+
+    /// Queues setting `len` bytes at `dst` to `value`.
+    pub(crate) unsafe fn memset(
+        &self, dst: CUdeviceptr, value: u8, len: usize,
+    ) -> CudaResult<()> {
+        driver_result(cuda_driver_sys::cuMemsetD8Async(dst, value, len, self.0))
+    }
+
+    pub(crate) unsafe fn launch(
+        &self, function: &Function, config: &KernelConfig, args: &[*mut c_void],
+    ) -> CudaResult<()> {
+        driver_result(cuda_driver_sys::cuLaunchKernel(
+            function.raw,
+            config.global_work_size as u32,
+            1,
+            1,
+            config.local_work_size as u32,
+            1,
+            1,
+            config.shared_mem as u32,
+            self.0,
+            args.as_ptr() as *mut *mut c_void,
+            ptr::null_mut(),
+        ))
+    }
+
+    /// Makes the work queued from now on wait for the work captured by
+    /// `event`, which may be recorded on another stream.
+    pub(crate) fn wait(&self, event: &Event) -> CudaResult<()> {
+        driver_result(unsafe {
+            cuda_driver_sys::cuStreamWaitEvent(self.0, event.0, 0)
+        })
+    }
+
+    pub(crate) fn add_callback(
+        &self, callback: StreamCallback,
+    ) -> CudaResult<()> {
+        let callback = Box::into_raw(Box::new(callback));
+        let result = driver_result(unsafe {
+            cuda_driver_sys::cuStreamAddCallback(
+                self.0,
+                Some(call_back),
+                callback as *mut c_void,
+                0,
+            )
+        });
+        if result.is_err() {
+            // The driver did not take the callback.
+            drop(unsafe { Box::from_raw(callback) });
+        }
+        result
+    }
+
+    pub(crate) fn synchronize(&self) -> CudaResult<()> {
+        driver_result(unsafe { cuda_driver_sys::cuStreamSynchronize(self.0) })
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        log_destroy(
+            unsafe { cuda_driver_sys::cuStreamDestroy_v2(self.0) },
+            "stream",
+        );
+    }
+}
+
+/// Calls the callback boxed by [`Stream::add_callback`] on a driver thread.
+unsafe extern "C" fn call_back(
+    _stream: CUstream, status: CUresult, callback: *mut c_void,
+) {
+    let callback = Box::from_raw(callback as *mut StreamCallback);
+    // Panics must not unwind into the driver.
+    let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        callback(driver_result(status))
+    }));
+}
+
+/// An event, destroyed on drop.
+pub(crate) struct Event(CUevent);
+
+impl Event {
+    /// Records a new event capturing the work queued on `stream` so far.
+    pub(crate) fn record(stream: &Stream) -> CudaResult<Self> {
+        let mut event = ptr::null_mut();
+        driver_result(unsafe {
+            cuda_driver_sys::cuEventCreate(
+                &mut event,
+                cuda_driver_sys::CUevent_flags::CU_EVENT_DEFAULT as u32,
+            )
+        })?;
+        let event = Self(event);
+        driver_result(unsafe {
+            cuda_driver_sys::cuEventRecord(event.0, stream.0)
+        })?;
+        Ok(event)
+    }
+
+    pub(crate) fn is_complete(&self) -> CudaResult<bool> {
+        match driver_result(unsafe { cuda_driver_sys::cuEventQuery(self.0) }) {
+            Ok(()) => Ok(true),
+            Err(CudaError::NotReady) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(crate) fn synchronize(&self) -> CudaResult<()> {
+        driver_result(unsafe { cuda_driver_sys::cuEventSynchronize(self.0) })
+    }
+
+    /// The milliseconds elapsed on the device since `start`.
+    pub(crate) fn elapsed_since(&self, start: &Event) -> CudaResult<f32> {
+        let mut millis = 0.0;
+        driver_result(unsafe {
+            cuda_driver_sys::cuEventElapsedTime(&mut millis, start.0, self.0)
+        })?;
+        Ok(millis)
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        log_destroy(
+            unsafe { cuda_driver_sys::cuEventDestroy_v2(self.0) },
+            "event",
+        );
+    }
+}
@@ -0,0 +1,269 @@
+//! An in-memory stream standing in for the driver in unit tests. Copies are
+//! executed immediately and every call is logged, kernels are simulated by
+//! the test on the memory the launch arguments point to. Callbacks and
+//! events wait for [`FakeStream::complete`] or a synchronization. The memory
+//! keeps the stream that allocated it, which stands in for its context.
+
+use crate::{
+    driver::Function,
+    kernel::KernelConfig,
+    memory::DeviceMemory,
+    stream::{
+        CudaEvent, CudaStream, DeviceEvent, StreamCallback, StreamDriver,
+    },
+};
+
+use rustacuda::error::{CudaError, CudaResult};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::c_void,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+/// A call on a [`FakeStream`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Op {
+    Alloc {
+        address: u64,
+        size: usize,
+    },
+    ToDevice {
+        address: u64,
+        offset: usize,
+        size: usize,
+    },
+    ToHost {
+        address: u64,
+        size: usize,
+    },
+    Memset {
+        address: u64,
+        value: u8,
+    },
+    Launch {
+        args: usize,
+    },
+    Record {
+        event: u64,
+    },
+    Wait {
+        event: u64,
+    },
+    Callback,
+    Synchronize,
+}
+
+type Bytes = Rc<RefCell<Vec<u8>>>;
+
+/// Fake device memory. Kernels receive a pointer to its address, like for a
+/// CUDA buffer.
+pub(crate) struct FakeMemory {
+    address: u64,
+    bytes: Bytes,
+    owner: Weak<FakeStream>,
+}
+
+impl FakeMemory {
+    pub(crate) fn len(&self) -> usize { self.bytes.borrow().len() }
+
+    pub(crate) fn address(&self) -> u64 { self.address }
+
+    /// A handle of the stream that allocated the memory.
+    pub(crate) fn owner(&self) -> CudaResult<CudaStream> {
+        let owner = self.owner.upgrade().ok_or(CudaError::InvalidContext)?;
+        Ok(owner.stream())
+    }
+
+    pub(crate) fn param_pointer(&self) -> *mut c_void {
+        &self.address as *const u64 as *mut c_void
+    }
+}
+
+/// A fake event, numbered in the order of recording on its stream. Every
+/// event is recorded a millisecond after the previous one.
+pub(crate) struct FakeEvent {
+    id: u64,
+    complete: Rc<Cell<bool>>,
+    owner: Weak<FakeStream>,
+}
+
+impl FakeEvent {
+    pub(crate) fn is_complete(&self) -> bool { self.complete.get() }
+
+    pub(crate) fn synchronize(&self) -> CudaResult<()> {
+        if !self.is_complete() {
+            let owner =
+                self.owner.upgrade().ok_or(CudaError::InvalidContext)?;
+            owner.synchronize()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn elapsed_since(
+        &self, start: &FakeEvent,
+    ) -> CudaResult<Duration> {
+        if !self.is_complete() || !start.is_complete() {
+            return Err(CudaError::NotReady);
+        }
+        Ok(Duration::from_millis(self.id - start.id))
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct FakeStream {
+    this: Weak<FakeStream>,
+    next_address: Cell<u64>,
+    memory: RefCell<HashMap<u64, Bytes>>,
+    ops: RefCell<Vec<Op>>,
+    callbacks: RefCell<Vec<StreamCallback>>,
+    recorded: Cell<u64>,
+    events: RefCell<Vec<Rc<Cell<bool>>>>,
+}
+
+impl FakeStream {
+    pub(crate) fn new() -> Rc<Self> {
+        Rc::new_cyclic(|this| Self {
+            this: this.clone(),
+            ..Default::default()
+        })
+    }
+
+    /// A stream handle backed by `self`.
+    pub(crate) fn stream(self: &Rc<Self>) -> CudaStream {
+        CudaStream::from_driver(self.clone())
+    }
+
+    /// Completes the queued work, i.e. calls the pending callbacks with
+    /// `status` and, on success, completes the recorded events.
+    pub(crate) fn complete(&self, status: CudaResult<()>) {
+        for callback in self.callbacks.take() {
+            callback(status);
+        }
+        if status.is_ok() {
+            for event in self.events.take() {
+                event.set(true);
+            }
+        }
+    }
+
+    /// Takes the calls logged so far.
+    pub(crate) fn take_ops(&self) -> Vec<Op> { self.ops.take() }
+
+    /// The memory a launch argument of a device pointer refers to.
+    ///
+    /// # Safety
+    ///
+    /// `arg` must be a pointer from `ParamIO::param_pointer` of a parameter
+    /// in fake device memory.
+    pub(crate) unsafe fn memory(&self, arg: *mut c_void) -> Bytes {
+        let address = *(arg as *const u64);
+        self.memory.borrow()[&address].clone()
+    }
+
+    fn fake(memory: &DeviceMemory) -> CudaResult<&FakeMemory> {
+        match memory {
+            DeviceMemory::Fake(memory) => Ok(memory),
+            DeviceMemory::Cuda(..) => Err(CudaError::InvalidValue),
+        }
+    }
+
+    fn log(&self, op: Op) { self.ops.borrow_mut().push(op); }
+}
+
+impl StreamDriver for FakeStream {
+    fn alloc(&self, size: usize) -> CudaResult<DeviceMemory> {
+        // Leave the null address unused, like the driver.
+        let address = self.next_address.get() + 0x100;
+        self.next_address.set(address);
+        let bytes = Rc::new(RefCell::new(vec![0xcc; size]));
+        self.memory.borrow_mut().insert(address, bytes.clone());
+        self.log(Op::Alloc { address, size });
+        Ok(DeviceMemory::Fake(FakeMemory {
+            address,
+            bytes,
+            owner: self.this.clone(),
+        }))
+    }
+
+    unsafe fn copy_to_device(
+        &self, dst: &mut DeviceMemory, offset: usize, src: &[u8],
+    ) -> CudaResult<()> {
+        let dst = Self::fake(dst)?;
+        dst.bytes.borrow_mut()[offset..offset + src.len()].copy_from_slice(src);
+        self.log(Op::ToDevice {
+            address: dst.address,
+            offset,
+            size: src.len(),
+        });
+        Ok(())
+    }
+
+    unsafe fn copy_to_host(
+        &self, dst: &mut [u8], src: &DeviceMemory,
+    ) -> CudaResult<()> {
+        let src = Self::fake(src)?;
+        dst.copy_from_slice(&src.bytes.borrow());
+        self.log(Op::ToHost {
+            address: src.address,
+            size: dst.len(),
+        });
+        Ok(())
+    }
+
+    unsafe fn memset(
+        &self, dst: &mut DeviceMemory, value: u8,
+    ) -> CudaResult<()> {
+        let dst = Self::fake(dst)?;
+        dst.bytes.borrow_mut().fill(value);
+        self.log(Op::Memset {
+            address: dst.address,
+            value,
+        });
+        Ok(())
+    }
+
+    unsafe fn launch(
+        &self, _function: &Function, _config: &KernelConfig,
+        args: &[*mut c_void],
+    ) -> CudaResult<()> {
+        self.log(Op::Launch { args: args.len() });
+        Ok(())
+    }
+
+    fn record(&self) -> CudaResult<CudaEvent> {
+        let id = self.recorded.get() + 1;
+        self.recorded.set(id);
+        let complete = Rc::new(Cell::new(false));
+        self.events.borrow_mut().push(complete.clone());
+        self.log(Op::Record { event: id });
+        Ok(CudaEvent(DeviceEvent::Fake(FakeEvent {
+            id,
+            complete,
+            owner: self.this.clone(),
+        })))
+    }
+
+    fn wait(&self, event: &CudaEvent) -> CudaResult<()> {
+        match &event.0 {
+            DeviceEvent::Fake(event) => {
+                self.log(Op::Wait { event: event.id });
+                Ok(())
+            }
+            DeviceEvent::Cuda(_) => Err(CudaError::InvalidValue),
+        }
+    }
+
+    fn add_callback(&self, callback: StreamCallback) -> CudaResult<()> {
+        self.callbacks.borrow_mut().push(callback);
+        self.log(Op::Callback);
+        Ok(())
+    }
+
+    fn synchronize(&self) -> CudaResult<()> {
+        self.complete(Ok(()));
+        self.log(Op::Synchronize);
+        Ok(())
+    }
+}
@@ -0,0 +1,428 @@
+//! A task graph of kernel launches and copies, scheduled across streams.
+//!
+//! Every node declares the buffers it reads and writes. A node depends on the
+//! last writer of every buffer it accesses and, if it writes a buffer, on the
+//! readers since that writer. Independent nodes are spread over several
+//! streams, dependencies between streams are enforced with events, so the
+//! host only waits once, when the whole graph is done.
+//!
+//! ```ignore
+//! let mut graph = TaskGraph::new();
+//! let (coeffs, evals) = (graph.buffer(), graph.buffer());
+//! graph.upload(&host_coeffs, &mut coeffs_gpu, coeffs);
+//! graph.kernel(&[coeffs], &[evals], |k| k.point_radix_fft::<G1>(cfg, args));
+//! graph.kernel(&[evals], &[], |k| k.point_multiexp::<G1>(cfg, args));
+//! graph.run(&workspace)?;
+//! ```
+
+use crate::{
+    kernel::{Kernel, PendingTask},
+    limits::LaunchResult,
+    module::ActiveWorkspace,
+    stream::{CudaEvent, CudaStream},
+    DeviceData,
+};
+
+use ag_types::KernelArg;
+use rustacuda::error::{CudaError, CudaResult};
+
+/// The number of streams a graph uses at most, unless configured with
+/// [`TaskGraph::max_streams`].
+pub const DEFAULT_MAX_STREAMS: usize = 4;
+
+/// A buffer declared in a [`TaskGraph`] to express dependencies between its
+/// nodes. It is only a name, the data itself is passed to the nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Buffer(usize);
+
+type Launch<'a, 'b> =
+    Box<dyn FnOnce(Kernel<'a>) -> LaunchResult<PendingTask<'a, 'b>> + 'b>;
+type CopyOp<'b> = Box<dyn FnOnce(&CudaStream) -> CudaResult<()> + 'b>;
+
+enum Operation<'a, 'b> {
+    Kernel(Launch<'a, 'b>),
+    Copy(CopyOp<'b>),
+}
+
+/// A graph of kernel launches and copies, see the [module](self)
+/// documentation.
+pub struct TaskGraph<'a, 'b> {
+    operations: Vec<Operation<'a, 'b>>,
+    deps: Vec<Vec<usize>>,
+    last_writer: Vec<Option<usize>>,
+    readers: Vec<Vec<usize>>,
+    max_streams: usize,
+}
+
+impl<'a, 'b> Default for TaskGraph<'a, 'b> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<'a, 'b> TaskGraph<'a, 'b> {
+    pub fn new() -> Self {
+        Self {
+            operations: Vec::new(),
+            deps: Vec::new(),
+            last_writer: Vec::new(),
+            readers: Vec::new(),
+            max_streams: DEFAULT_MAX_STREAMS,
+        }
+    }
+
+    /// Sets the number of streams the graph uses at most.
+    pub fn max_streams(mut self, max_streams: usize) -> Self {
+        assert!(max_streams > 0, "A graph needs at least one stream");
+        self.max_streams = max_streams;
+        self
+    }
+
+    /// Declares a new buffer.
+    pub fn buffer(&mut self) -> Buffer {
+        self.last_writer.push(None);
+        self.readers.push(Vec::new());
+        Buffer(self.last_writer.len() - 1)
+    }
+
+    /// Adds a kernel launch. `launch` receives a kernel on the stream chosen
+    /// for the node and launches it, e.g. with a typed launcher.
+    pub fn kernel<F>(
+        &mut self, reads: &[Buffer], writes: &[Buffer], launch: F,
+    ) where F: FnOnce(Kernel<'a>) -> LaunchResult<PendingTask<'a, 'b>> + 'b
+    {
+        self.push(reads, writes, Operation::Kernel(Box::new(launch)));
+    }
+
+    /// Adds a copy of `host` to `device`, which writes `buffer`.
+    pub fn upload<T: KernelArg>(
+        &mut self, host: &'b [T], device: &'b mut DeviceData, buffer: Buffer,
+    ) {
+        let copy = move |stream: &CudaStream| {
+            let bytes = as_bytes(host);
+            if bytes.len() != device.size() {
+                return Err(CudaError::InvalidValue);
+            }
+            let dst = device.device_mem_mut();
+            unsafe { stream.driver().copy_to_device(dst, 0, bytes) }
+        };
+        self.push(&[], &[buffer], Operation::Copy(Box::new(copy)));
+    }
+
+    /// Adds a copy of `device` to `host`, which reads `buffer`. The host
+    /// memory is written when [`run`](Self::run) returns.
+    pub fn download<T: KernelArg>(
+        &mut self, device: &'b DeviceData, host: &'b mut [T], buffer: Buffer,
+    ) {
+        let copy = move |stream: &CudaStream| {
+            let bytes = as_bytes_mut(host);
+            if bytes.len() != device.size() {
+                return Err(CudaError::InvalidValue);
+            }
+            let src = device.device_mem();
+            unsafe { stream.driver().copy_to_host(bytes, src) }
+        };
+        self.push(&[buffer], &[], Operation::Copy(Box::new(copy)));
+    }
+
+    fn push(
+        &mut self, reads: &[Buffer], writes: &[Buffer],
+        operation: Operation<'a, 'b>,
+    ) {
+        let node = self.operations.len();
+        let mut deps = Vec::new();
+        for &Buffer(buffer) in reads {
+            deps.extend(self.last_writer[buffer]);
+        }
+        for &Buffer(buffer) in writes {
+            deps.extend(self.last_writer[buffer]);
+            deps.extend(self.readers[buffer].iter().copied());
+        }
+        deps.sort_unstable();
+        deps.dedup();
+        deps.retain(|&dep| dep != node);
+
+        for &Buffer(buffer) in reads {
+            self.readers[buffer].push(node);
+        }
+        for &Buffer(buffer) in writes {
+            self.last_writer[buffer] = Some(node);
+            self.readers[buffer].clear();
+        }
+
+        self.operations.push(operation);
+        self.deps.push(deps);
+    }
+
+    /// Launches all the nodes and blocks until they are completed.
+    pub fn run(self, workspace: &ActiveWorkspace<'a>) -> LaunchResult<()> {
+        let plan = Plan::new(&self.deps, self.max_streams);
+        let streams = (0..plan.streams)
+            .map(|_| workspace.stream())
+            .collect::<CudaResult<Vec<_>>>()?;
+        self.run_on(&plan, &streams, |stream| {
+            workspace.create_kernel_on(stream)
+        })
+    }
+
+    /// Runs the nodes on `streams` as planned, with the kernels created by
+    /// `kernel`. The queued work borrows the data of the nodes, so all the
+    /// streams are waited for before returning, also if a node fails.
+    fn run_on(
+        self, plan: &Plan, streams: &[CudaStream],
+        kernel: impl Fn(&CudaStream) -> Kernel<'a>,
+    ) -> LaunchResult<()> {
+        let mut pending = Vec::new();
+        let launched = self.launch(plan, streams, kernel, &mut pending);
+        // After an error, the tasks are dropped instead, which waits for
+        // their streams.
+        let completed = launched.and_then(|()| {
+            pending
+                .into_iter()
+                .try_for_each(|task| Ok(task.complete().map(drop)?))
+        });
+
+        let mut synchronized = Ok(());
+        for stream in streams {
+            synchronized = synchronized.and(stream.synchronize());
+        }
+        completed?;
+        Ok(synchronized?)
+    }
+
+    /// Queues the nodes and collects the launched tasks in `pending`.
+    fn launch(
+        self, plan: &Plan, streams: &[CudaStream],
+        kernel: impl Fn(&CudaStream) -> Kernel<'a>,
+        pending: &mut Vec<PendingTask<'a, 'b>>,
+    ) -> LaunchResult<()> {
+        // The event of every node other streams wait for.
+        let mut events: Vec<Option<CudaEvent>> = Vec::new();
+        for (operation, node) in self.operations.into_iter().zip(&plan.nodes) {
+            let stream = &streams[node.stream];
+            for &dep in &node.waits {
+                let event = events[dep].as_ref();
+                stream.wait(event.expect("recorded for its consumers"))?;
+            }
+
+            match operation {
+                Operation::Kernel(launch) => {
+                    pending.push(launch(kernel(stream))?)
+                }
+                Operation::Copy(copy) => copy(stream)?,
+            }
+
+            events.push(
+                if node.consumers > 0 {
+                    Some(stream.record()?)
+                } else {
+                    None
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The stream assignment of the nodes.
+struct Plan {
+    streams: usize,
+    nodes: Vec<PlannedNode>,
+}
+
+struct PlannedNode {
+    stream: usize,
+    /// The nodes on other streams to wait for.
+    waits: Vec<usize>,
+    /// How many nodes on other streams wait for this one.
+    consumers: usize,
+}
+
+impl Plan {
+    /// Continues the stream of a dependency if the dependency is the last
+    /// node on it, otherwise starts on a new stream while there are less
+    /// than `max_streams`, or on the next stream in turn. A node waits only
+    /// for its latest dependency on each other stream.
+    fn new(deps: &[Vec<usize>], max_streams: usize) -> Self {
+        let mut nodes: Vec<PlannedNode> = Vec::with_capacity(deps.len());
+        let mut tails: Vec<usize> = Vec::new();
+        let mut next = 0;
+
+        for (node, deps) in deps.iter().enumerate() {
+            let continued = deps.iter().rev().find_map(|&dep| {
+                let stream = nodes[dep].stream;
+                (tails[stream] == dep).then_some(stream)
+            });
+            let stream = continued.unwrap_or_else(|| {
+                if tails.len() < max_streams {
+                    tails.push(node);
+                    tails.len() - 1
+                } else {
+                    next = (next + 1) % max_streams;
+                    next
+                }
+            });
+            tails[stream] = node;
+
+            let mut waits: Vec<usize> = Vec::new();
+            for &dep in deps.iter().rev() {
+                let dep_stream = nodes[dep].stream;
+                if dep_stream != stream
+                    && waits.iter().all(|&x| nodes[x].stream != dep_stream)
+                {
+                    waits.push(dep);
+                }
+            }
+            for &dep in &waits {
+                nodes[dep].consumers += 1;
+            }
+
+            nodes.push(PlannedNode {
+                stream,
+                waits,
+                consumers: 0,
+            });
+        }
+
+        Self {
+            streams: tails.len(),
+            nodes,
+        }
+    }
+}
+
+fn as_bytes<T: KernelArg>(val: &[T]) -> &[u8] {
+    let () = T::ASSERT_LAYOUT;
+    let size = std::mem::size_of_val(val);
+    unsafe { std::slice::from_raw_parts(val.as_ptr() as *const u8, size) }
+}
+
+fn as_bytes_mut<T: KernelArg>(val: &mut [T]) -> &mut [u8] {
+    let () = T::ASSERT_LAYOUT;
+    let size = std::mem::size_of_val(val);
+    unsafe { std::slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, size) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        driver::Module,
+        fake::{FakeStream, Op},
+        kernel::KernelConfig,
+        limits::{DeviceLimits, FunctionLimits, LaunchError},
+    };
+    use std::{cell::Cell, ffi::CString};
+
+    fn graph_deps(
+        accesses: &[(&[usize], &[usize])], buffers: usize,
+    ) -> Vec<Vec<usize>> {
+        let mut graph = TaskGraph::new();
+        let buffers: Vec<_> = (0..buffers).map(|_| graph.buffer()).collect();
+        for (reads, writes) in accesses {
+            let reads: Vec<_> = reads.iter().map(|&i| buffers[i]).collect();
+            let writes: Vec<_> = writes.iter().map(|&i| buffers[i]).collect();
+            graph.push(&reads, &writes, Operation::Copy(Box::new(|_| Ok(()))));
+        }
+        graph.deps
+    }
+
+    #[test]
+    fn test_dependencies() {
+        // upload a, upload b, read a -> c, read b -> d, read c and d, write a
+        let deps = graph_deps(
+            &[
+                (&[], &[0]),
+                (&[], &[1]),
+                (&[0], &[2]),
+                (&[1], &[3]),
+                (&[2, 3], &[0]),
+            ],
+            4,
+        );
+        assert_eq!(deps, vec![vec![], vec![], vec![0], vec![1], vec![0, 2, 3]]);
+    }
+
+    #[test]
+    fn test_plan() {
+        let deps = vec![vec![], vec![], vec![0], vec![1], vec![0, 2, 3]];
+        let plan = Plan::new(&deps, 4);
+
+        assert_eq!(plan.streams, 2);
+        let streams: Vec<_> = plan.nodes.iter().map(|x| x.stream).collect();
+        assert_eq!(streams, vec![0, 1, 0, 1, 1]);
+        assert_eq!(plan.nodes[4].waits, vec![2]);
+        assert_eq!(plan.nodes[2].consumers, 1);
+        assert!(plan.nodes[..4].iter().all(|x| x.waits.is_empty()));
+    }
+
+    #[test]
+    fn test_run_failing_node() {
+        let module = Module::fake();
+        let name = CString::new("fake").unwrap();
+        module.insert_fake_function(
+            &name,
+            FunctionLimits {
+                max_threads_per_block: 256,
+                static_shared_mem: 0,
+            },
+        );
+        let limits = DeviceLimits {
+            max_threads_per_block: 1024,
+            max_block_dim_x: 1024,
+            max_grid_dim_x: 1 << 16,
+            max_shared_mem_per_block: 48 << 10,
+        };
+        let config = KernelConfig {
+            global_work_size: 1,
+            local_work_size: 2,
+            shared_mem: 0,
+        };
+        let fakes = [FakeStream::new(), FakeStream::new()];
+        let streams: Vec<_> = fakes.iter().map(FakeStream::stream).collect();
+
+        let input = [1u32, 2];
+        let reached = Cell::new(false);
+        let mut graph = TaskGraph::new();
+        let (a, b) = (graph.buffer(), graph.buffer());
+        graph.kernel(&[], &[a], |k| {
+            k.func("fake")?.in_ref_slice(&input[..])?.launch(config)
+        });
+        let fail = |_: &CudaStream| Err(CudaError::InvalidValue);
+        graph.push(&[], &[b], Operation::Copy(Box::new(fail)));
+        let reach = |_: &CudaStream| {
+            reached.set(true);
+            Ok(())
+        };
+        graph.push(&[a], &[], Operation::Copy(Box::new(reach)));
+
+        let plan = Plan::new(&graph.deps, 2);
+        assert_eq!(plan.streams, 2);
+        let result = graph.run_on(&plan, &streams, |stream| {
+            Kernel::new(&module, &limits, stream.clone())
+        });
+        assert_eq!(result, Err(LaunchError::Cuda(CudaError::InvalidValue)));
+        assert!(!reached.get());
+
+        // The launched kernel is waited for before its input is freed, and
+        // both streams before returning.
+        assert!(matches!(
+            fakes[0].take_ops()[..],
+            [
+                Op::Alloc { .. },
+                Op::ToDevice { .. },
+                Op::Launch { args: 1 },
+                Op::Synchronize,
+                Op::Synchronize,
+            ]
+        ));
+        assert_eq!(fakes[1].take_ops(), [Op::Synchronize]);
+    }
+
+    #[test]
+    fn test_plan_stream_limit() {
+        let deps = vec![vec![]; 5];
+        let plan = Plan::new(&deps, 2);
+
+        assert_eq!(plan.streams, 2);
+        assert!(plan.nodes.iter().all(|x| x.stream < 2));
+    }
+}
@@ -1,21 +1,23 @@
 use crate::{
-    params::{DeviceParam, NullPointer},
+    completion::TaskCompletion,
+    driver::{Function, Module},
+    limits::{DeviceLimits, LaunchResult},
+    params::{DeviceParam, NullPointer, PointerArg, ValueArg},
+    profile::{FinishedProfile, TaskProfile},
+    stream::{CudaEvent, CudaStream},
     DeviceData,
 };
+use ag_types::KernelArg;
 
 use super::params::{Param, ParamIO};
 
 use std::{
     ffi::{c_void, CString},
     iter::once,
+    time::Instant,
 };
 
-use rustacuda::{
-    error::CudaResult, function::Function, module::Module, stream::Stream,
-};
-
-#[cfg(feature = "timer")]
-use std::time::Instant;
+use rustacuda::error::CudaResult;
 
 #[derive(Debug, Clone, Copy)]
 pub struct KernelConfig {
@@ -24,16 +26,28 @@ pub struct KernelConfig {
     pub shared_mem: usize,
 }
 
+/// The kernels of a module, launched on a stream. Like the [`CudaStream`] it
+/// holds, a kernel is not `Send`: it stays on the thread of its workspace.
 pub struct Kernel<'a> {
     module: &'a Module,
-    stream: Stream,
+    limits: &'a DeviceLimits,
+    stream: CudaStream,
 }
 
 impl<'a> Kernel<'a> {
-    pub fn new(module: &'a Module, stream: Stream) -> Self {
-        Self { module, stream }
+    pub(crate) fn new(
+        module: &'a Module, limits: &'a DeviceLimits, stream: CudaStream,
+    ) -> Self {
+        Self {
+            module,
+            limits,
+            stream,
+        }
     }
 
+    /// The stream the kernel is launched on.
+    pub fn stream(&self) -> &CudaStream { &self.stream }
+
     pub fn func<'b>(self, name: &str) -> CudaResult<KernelTask<'a, 'b>> {
         KernelTask::new(self, name)
     }
@@ -41,93 +55,199 @@ impl<'a> Kernel<'a> {
 
 type Params<'b> = Vec<Box<dyn ParamIO + 'b>>;
 
+/// The parameters of the calls of a task. The parameters of launched calls
+/// are kept until they are copied back and the stream they were launched on
+/// is synchronized, as the device may still use them.
+#[derive(Default)]
+pub(crate) struct Args<'b> {
+    current: Params<'b>,
+    launched: Vec<Params<'b>>,
+    /// The stream of the launched calls, while their work may be pending.
+    stream: Option<CudaStream>,
+}
+
+impl<'b> Args<'b> {
+    pub(crate) fn push(&mut self, param: Box<dyn ParamIO + 'b>) {
+        self.current.push(param);
+    }
+
+    fn set_sensitive(&mut self) {
+        for param in &mut self.current {
+            param.set_sensitive();
+        }
+    }
+
+    /// The pointers to the parameters of the next call.
+    pub(crate) fn pointers(&self) -> Vec<*mut c_void> {
+        self.current.iter().map(|x| x.param_pointer()).collect()
+    }
+
+    /// The bytes copied to and from the device for the next call.
+    pub(crate) fn transfer_size(&self) -> (usize, usize) {
+        self.current
+            .iter()
+            .map(|x| x.transfer_size())
+            .fold((0, 0), |(a, b), (c, d)| (a + c, b + d))
+    }
+
+    /// Keeps the parameters of the launched call for the copy back and
+    /// starts the parameters of the next call.
+    pub(crate) fn next_call(&mut self) {
+        self.launched.push(std::mem::take(&mut self.current));
+    }
+
+    /// Enqueues copying the outputs of all the calls back to the host.
+    pub(crate) fn copy_back(&mut self, stream: &CudaStream) -> CudaResult<()> {
+        let calls = self.launched.iter_mut().chain(once(&mut self.current));
+        for params in calls {
+            for param in params.iter_mut() {
+                param.after_call(stream)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Notes that the current call is launched on `stream`.
+    pub(crate) fn launched_on(&mut self, stream: &CudaStream) {
+        self.stream = Some(stream.clone());
+    }
+
+    /// Notes that the work of the launched calls is completed.
+    pub(crate) fn completed(&mut self) { self.stream = None; }
+
+    /// Waits for the launched calls and releases the parameters of all the
+    /// calls.
+    pub(crate) fn release(&mut self) -> CudaResult<()> {
+        if let Some(stream) = &self.stream {
+            stream.synchronize()?;
+        }
+        self.completed();
+        self.launched.clear();
+        self.current.clear();
+        Ok(())
+    }
+}
+
+impl Drop for Args<'_> {
+    /// Waits for the launched calls of a task dropped before its work is
+    /// completed, before their buffers are freed.
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.synchronize();
+        }
+    }
+}
+
+/// The calls of a kernel function and their parameters. Not `Send`, like
+/// its [`Kernel`].
 pub struct KernelTask<'a, 'b> {
     k: Kernel<'a>,
-    launched: Vec<Params<'b>>,
     function: Function<'a>,
-    args: Params<'b>,
-    #[cfg(feature = "timer")]
+    args: Args<'b>,
+    name: String,
     instant: Instant,
+    profile: Option<TaskProfile>,
+    finished: Vec<FinishedProfile>,
+    sensitive: bool,
 }
 
 pub struct PendingTask<'a, 'b>(KernelTask<'a, 'b>);
 
 impl<'a, 'b> KernelTask<'a, 'b> {
     pub fn new(kernel: Kernel<'a>, name: &str) -> CudaResult<Self> {
-        #[cfg(feature = "timer")]
         let instant = Instant::now();
+        let profile = TaskProfile::begin(&kernel.stream);
 
         let function_name =
             CString::new(name).expect("Kernel name must not contain nul bytes");
-        let function = kernel.module.get_function(&function_name)?;
-
-        #[cfg(feature = "timer")]
-        println!(
-            "[{:?}] Elapsed {:.3} us (get function)",
-            std::thread::current().id(),
-            instant.elapsed().as_nanos() as f64 / 1000.0,
-        );
+        let function = kernel.module.function(&function_name)?;
 
-        Ok(KernelTask {
+        let task = KernelTask {
             k: kernel,
-            launched: vec![],
             function,
-            args: Vec::new(),
-            #[cfg(feature = "timer")]
+            args: Args::default(),
+            name: name.to_string(),
             instant,
-        })
+            profile,
+            finished: Vec::new(),
+            sensitive: false,
+        };
+        task.elapsed("get function");
+        Ok(task)
     }
 
+    /// Logs the time since the task was created at trace level.
     #[inline]
-    #[allow(unused_variables)]
     pub fn elapsed(&self, note: &str) {
-        #[cfg(feature = "timer")]
-        {
-            println!(
-                "[{:?}] Elapsed {:.3} us ({})",
-                std::thread::current().id(),
-                self.instant.elapsed().as_nanos() as f64 / 1000.0,
-                note
-            );
-        }
+        log::trace!(
+            "{}: {:?} elapsed ({})",
+            self.name,
+            self.instant.elapsed(),
+            note
+        );
+    }
+
+    /// Makes the launch wait until the work captured by `event`, e.g. on
+    /// another stream, is completed.
+    pub fn after(self, event: &CudaEvent) -> CudaResult<Self> {
+        self.k.stream.wait(event)?;
+        Ok(self)
     }
 
-    pub fn val<T: 'static>(mut self, input: T) -> CudaResult<Self> {
+    /// Zeroes the device copies of the host parameters of the task, passed
+    /// before or after, once the call is done, e.g. for scalars derived from
+    /// a private witness. `DeviceData` and `DeviceParam` arguments are marked
+    /// with their own `sensitive`.
+    pub fn sensitive(mut self) -> Self {
+        self.sensitive = true;
+        self.args.set_sensitive();
+        self
+    }
+
+    pub fn val<T: KernelArg>(mut self, input: T) -> CudaResult<Self> {
         self.receive_param(Param::InVal(input))?;
         Ok(self)
     }
 
-    pub fn in_ref<T>(mut self, input: &'b T) -> CudaResult<Self> {
+    pub fn in_ref<T: KernelArg>(mut self, input: &'b T) -> CudaResult<Self> {
         self.receive_param(Param::InRef(input))?;
         Ok(self)
     }
 
-    pub fn in_mut<T>(mut self, input: &'b mut T) -> CudaResult<Self> {
+    pub fn in_mut<T: KernelArg>(
+        mut self, input: &'b mut T,
+    ) -> CudaResult<Self> {
         self.receive_param(Param::InMut(input))?;
         Ok(self)
     }
 
-    pub fn out<T>(mut self, output: &'b mut T) -> CudaResult<Self> {
+    pub fn out<T: KernelArg>(mut self, output: &'b mut T) -> CudaResult<Self> {
         self.receive_param(Param::Out(output))?;
         Ok(self)
     }
 
-    pub fn in_ref_slice<T>(mut self, input: &'b [T]) -> CudaResult<Self> {
+    pub fn in_ref_slice<T: KernelArg>(
+        mut self, input: &'b [T],
+    ) -> CudaResult<Self> {
         self.receive_param(Param::InRefSlice(input))?;
         Ok(self)
     }
 
-    pub fn in_mut_slice<T>(mut self, input: &'b mut [T]) -> CudaResult<Self> {
+    pub fn in_mut_slice<T: KernelArg>(
+        mut self, input: &'b mut [T],
+    ) -> CudaResult<Self> {
         self.receive_param(Param::InMutSlice(input))?;
         Ok(self)
     }
 
-    pub fn out_slice<T>(mut self, output: &'b mut [T]) -> CudaResult<Self> {
+    pub fn out_slice<T: KernelArg>(
+        mut self, output: &'b mut [T],
+    ) -> CudaResult<Self> {
         self.receive_param(Param::OutSlice(output))?;
         Ok(self)
     }
 
-    pub fn dev_arg<T>(
+    pub fn dev_arg<T: KernelArg>(
         mut self, output: &'b DeviceParam<'_, T>,
     ) -> CudaResult<Self> {
         self.args.push(Box::new(output));
@@ -141,46 +261,106 @@ impl<'a, 'b> KernelTask<'a, 'b> {
         Ok(self)
     }
 
+    pub fn ptr<T>(mut self, arg: PointerArg<'b, T>) -> CudaResult<Self> {
+        let mut param = arg.into_param(&self.k.stream)?;
+        if self.sensitive {
+            param.set_sensitive();
+        }
+        self.args.push(param);
+        self.elapsed("pointer param");
+        Ok(self)
+    }
+
+    pub fn value<T>(mut self, arg: ValueArg<T>) -> CudaResult<Self> {
+        self.args.push(arg.into_param());
+        self.elapsed("value param");
+        Ok(self)
+    }
+
     pub fn empty(mut self) -> CudaResult<Self> {
         self.args.push(Box::new(NullPointer));
         self.elapsed("empty param");
         Ok(self)
     }
 
-    fn receive_param<T>(&mut self, arg: Param<'b, T>) -> CudaResult<()> {
-        let b = arg.before_call(&self.k.stream)?;
-        self.args.push(Box::new((arg, b)));
+    fn receive_param<T: KernelArg>(
+        &mut self, arg: Param<'b, T>,
+    ) -> CudaResult<()> {
+        let buffer = arg.before_call(&self.k.stream)?;
+        let mut param = (arg, buffer);
+        if self.sensitive {
+            param.set_sensitive();
+        }
+        self.args.push(Box::new(param));
         self.elapsed("param");
         Ok(())
     }
 
+    /// Checks `config` against the limits of the device and the kernel.
+    /// Call it before passing the parameters to fail before uploading them,
+    /// [`launch`](Self::launch) checks it again anyway.
+    pub fn validate(self, config: &KernelConfig) -> LaunchResult<Self> {
+        self.k
+            .limits
+            .check(&self.name, config, self.function.limits())?;
+        Ok(self)
+    }
+
     pub fn launch(
-        self, config: KernelConfig,
-    ) -> CudaResult<PendingTask<'a, 'b>> {
-        let args: Vec<*mut c_void> =
-            self.args.iter().map(|x| x.param_pointer()).collect();
+        mut self, config: KernelConfig,
+    ) -> LaunchResult<PendingTask<'a, 'b>> {
+        self.k
+            .limits
+            .check(&self.name, &config, self.function.limits())?;
+        let args = self.args.pointers();
         self.elapsed("before launch");
+        if let Some(profile) = &mut self.profile {
+            profile.launched(config, self.args.transfer_size().0);
+        }
         unsafe {
-            self.k.stream.launch(
-                &self.function,
-                config.global_work_size as u32,
-                config.local_work_size as u32,
-                config.shared_mem as u32,
-                &args,
-            )?
+            self.k
+                .stream
+                .driver()
+                .launch(&self.function, &config, &args)?
         }
+        self.args.launched_on(&self.k.stream);
 
         self.elapsed("after launch");
         Ok(PendingTask(self))
     }
+
+    pub(crate) fn stream(&self) -> &CudaStream { &self.k.stream }
+
+    /// Emits the profiles of the launches whose work is queued completely.
+    pub(crate) fn report(&mut self) {
+        for profile in self.finished.drain(..) {
+            profile.emit();
+        }
+    }
+
+    /// Releases the parameters, once the work of the task is completed.
+    pub(crate) fn into_kernel(mut self) -> Kernel<'a> {
+        self.args.completed();
+        self.report();
+        self.k
+    }
 }
 
 impl<'a, 'b> PendingTask<'a, 'b> {
+    /// Records an event capturing the launched kernel, e.g. to let a task on
+    /// another stream wait for it or to measure the time it took.
+    pub fn record(&self) -> CudaResult<CudaEvent> { self.0.k.stream.record() }
+
+    /// Copies the outputs back, waits for the launched call and starts the
+    /// next call of the function, taking new parameters.
     pub fn next_call(mut self) -> CudaResult<KernelTask<'a, 'b>> {
         self.sync_back()?;
 
         let mut task = self.0;
-        task.launched.push(std::mem::take(&mut task.args));
+        task.args.next_call();
+        task.report();
+        task.instant = Instant::now();
+        task.profile = TaskProfile::begin(&task.k.stream);
 
         Ok(task)
     }
@@ -191,35 +371,207 @@ impl<'a, 'b> PendingTask<'a, 'b> {
         let mut task = self.next_call()?;
         let function_name =
             CString::new(name).expect("Kernel name must not contain nul bytes");
-        let function = task.k.module.get_function(&function_name)?;
+        let function = task.k.module.function(&function_name)?;
         task.function = function;
+        task.name = name.to_string();
 
         Ok(task)
     }
 
     pub fn complete(mut self) -> CudaResult<Kernel<'a>> {
         self.sync_back()?;
+        Ok(self.0.into_kernel())
+    }
 
-        let kernel = self.0.k;
-        kernel.stream.synchronize()?;
-        Ok(kernel)
+    /// Like [`complete`](Self::complete), but returns a future instead of
+    /// blocking until the stream is done. The parameters are kept alive until
+    /// the future resolves.
+    pub fn completion(mut self) -> CudaResult<TaskCompletion<'a, 'b>> {
+        self.copy_back()?;
+        TaskCompletion::new(self.0)
     }
 
+    /// Copies the outputs back, waits for the stream and releases the
+    /// parameters.
     fn sync_back(&mut self) -> CudaResult<()> {
+        self.copy_back()?;
+        self.0.args.release()
+    }
+
+    /// Enqueues copying the outputs back to the host.
+    fn copy_back(&mut self) -> CudaResult<()> {
         let kernel = &mut self.0;
-        let tasks = kernel.launched.iter_mut().chain(once(&mut kernel.args));
-        for task_args in tasks {
-            for mut param in task_args.drain(..) {
-                param.after_call(&kernel.k.stream)?;
-                #[cfg(feature = "timer")]
-                println!(
-                    "[{:?}] Elapsed {} us (back)",
-                    std::thread::current().id(),
-                    kernel.instant.elapsed().as_nanos() as f64 / 1000.0,
-                );
-            }
+        kernel.args.copy_back(&kernel.k.stream)?;
+        kernel.elapsed("back");
+
+        if let Some(profile) = kernel.profile.take() {
+            let bytes = kernel.args.transfer_size().1;
+            kernel.finished.extend(profile.end(
+                &kernel.name,
+                bytes,
+                &kernel.k.stream,
+            ));
         }
-
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fake::{FakeStream, Op},
+        limits::{FunctionLimits, LaunchError, Limit},
+    };
+
+    /// Calls `f` with a task on `stream`. The module and the function have no
+    /// driver handles, so only the parts not calling the driver can be used.
+    /// The function allows 256 threads per block.
+    fn with_task<'b, R>(
+        stream: CudaStream, f: impl for<'a> FnOnce(KernelTask<'a, 'b>) -> R,
+    ) -> R {
+        let module = Module::fake();
+        let limits = DeviceLimits {
+            max_threads_per_block: 1024,
+            max_block_dim_x: 1024,
+            max_grid_dim_x: 1 << 16,
+            max_shared_mem_per_block: 48 << 10,
+        };
+        f(KernelTask {
+            k: Kernel::new(&module, &limits, stream),
+            function: module.fake_function(FunctionLimits {
+                max_threads_per_block: 256,
+                static_shared_mem: 0,
+            }),
+            args: Args::default(),
+            name: "fake".into(),
+            instant: Instant::now(),
+            profile: None,
+            finished: Vec::new(),
+            sensitive: false,
+        })
+    }
+
+    #[test]
+    fn test_after_and_record() {
+        let (first, second) = (FakeStream::new(), FakeStream::new());
+
+        let mut output = [0u32; 2];
+        let event = with_task(first.stream(), |task| {
+            let mut task = task.out_slice(&mut output[..]).unwrap();
+            let stream = task.stream().clone();
+            task.args.launched_on(&stream);
+            let pending = PendingTask(task);
+            let event = pending.record().unwrap();
+            pending.complete().unwrap();
+            event
+        });
+        assert!(matches!(
+            &first.take_ops()[..],
+            [
+                Op::Alloc { .. },
+                Op::Record { event: 1 },
+                Op::ToHost { size: 8, .. },
+                Op::Synchronize,
+            ]
+        ));
+        assert!(event.is_complete().unwrap());
+
+        with_task(second.stream(), |task| {
+            let task = task.after(&event).unwrap();
+            task.into_kernel();
+        });
+        assert_eq!(second.take_ops(), [Op::Wait { event: 1 }]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = |local_work_size| KernelConfig {
+            global_work_size: 1,
+            local_work_size,
+            shared_mem: 0,
+        };
+        with_task(FakeStream::new().stream(), |task| {
+            let task = task.validate(&config(256)).unwrap();
+            // The function allows less threads than the device.
+            assert!(matches!(
+                task.validate(&config(512)),
+                Err(LaunchError::InvalidConfig {
+                    limit: Limit::BlockSize,
+                    max: 256,
+                    ..
+                })
+            ));
+        });
+    }
+
+    #[test]
+    fn test_drop_pending_task() {
+        let fake = FakeStream::new();
+
+        let input = [1u32, 2];
+        with_task(fake.stream(), |task| {
+            let mut task = task.in_ref_slice(&input[..]).unwrap();
+            let stream = task.stream().clone();
+            task.args.launched_on(&stream);
+            fake.take_ops();
+            drop(PendingTask(task));
+        });
+        // The launched call may still read its input, which is only freed
+        // once the stream is done.
+        assert_eq!(fake.take_ops(), [Op::Synchronize]);
+    }
+
+    fn push<'b, T: KernelArg>(
+        args: &mut Args<'b>, param: Param<'b, T>, stream: &CudaStream,
+    ) {
+        let buffer = param.before_call(stream).unwrap();
+        args.push(Box::new((param, buffer)));
+    }
+
+    #[test]
+    fn test_copy_back_all_calls() {
+        let fake = FakeStream::new();
+        let stream = fake.stream();
+        let input = [1u32, 2];
+        let (mut first, mut second) = ([0u32; 2], [0u32; 2]);
+        let mut args = Args::default();
+
+        // Each call doubles the input into its own output.
+        for output in [&mut first, &mut second] {
+            push(&mut args, Param::InRefSlice(&input[..]), &stream);
+            push(&mut args, Param::InVal(7u32), &stream);
+            push(&mut args, Param::OutSlice(&mut output[..]), &stream);
+            args.push(Box::new(NullPointer));
+
+            let pointers = args.pointers();
+            assert_eq!(pointers.len(), 4);
+            assert_eq!(unsafe { *(pointers[1] as *const u32) }, 7);
+            assert_eq!(args.transfer_size(), (8, 8));
+
+            let input = unsafe { fake.memory(pointers[0]) };
+            let output = unsafe { fake.memory(pointers[2]) };
+            let doubled: Vec<u8> = input
+                .borrow()
+                .chunks(4)
+                .map(|x| u32::from_ne_bytes(x.try_into().unwrap()) * 2)
+                .flat_map(u32::to_ne_bytes)
+                .collect();
+            output.borrow_mut().copy_from_slice(&doubled);
+            args.next_call();
+        }
+        fake.take_ops();
+
+        args.copy_back(&stream).unwrap();
+        let copies = fake.take_ops();
+        assert_eq!(copies.len(), 2);
+        assert!(copies
+            .iter()
+            .all(|x| matches!(x, Op::ToHost { size: 8, .. })));
+        args.release().unwrap();
+        drop(args);
+
+        assert_eq!(first, [2, 4]);
+        assert_eq!(second, [2, 4]);
+    }
+}
@@ -1,12 +1,47 @@
+mod completion;
 mod context;
 mod ctx_stack_guard;
+mod device;
+mod driver;
+#[cfg(test)]
+mod fake;
+mod graph;
 mod kernel;
+mod limits;
+mod memory;
 mod module;
 mod params;
+mod pool;
+mod profile;
+mod stream;
 
-pub use kernel::KernelConfig;
-pub use module::{ActiveWorkspace, CudaWorkspace};
-pub use params::{DeviceData, DeviceParam, ParamIO};
+pub use ag_types::KernelArg;
+pub use completion::{block_on, BlockingTask, TaskCompletion};
+pub use device::DeviceInfo;
+#[cfg(any(test, feature = "testing"))]
+#[doc(hidden)]
+pub use device::FakeDevice;
+pub use graph::{Buffer, TaskGraph, DEFAULT_MAX_STREAMS};
+pub use kernel::{Kernel, KernelConfig, KernelTask, PendingTask};
+pub use limits::{DeviceLimits, LaunchError, LaunchResult, Limit};
+pub use memory::MemoryUsage;
+pub use module::{
+    ActiveWorkspace, CudaWorkspace, IntoWorkspaceResult, LazyWorkspace,
+    LoadError, LoadResult,
+};
+pub use params::{
+    DeviceData, DeviceParam, ParamIO, PointerArg, Untyped, ValueArg,
+};
+pub use pool::{PooledWorkspace, WorkspacePool, DEFAULT_POOL_SIZE};
+pub use profile::{
+    set_profile_sink, KernelSummary, LaunchRecord, ProfileReport, ProfileSink,
+    Profiler,
+};
+pub use rustacuda::{
+    device::DeviceAttribute,
+    error::{CudaError, CudaResult},
+};
+pub use stream::{CudaEvent, CudaStream};
 
 pub fn cuda_init() {
     use rustacuda::{init, CudaFlags};
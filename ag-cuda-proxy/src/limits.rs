@@ -0,0 +1,258 @@
+use crate::{device::FunctionDriver, kernel::KernelConfig, module::LoadError};
+
+use rustacuda::{
+    error::{CudaError, CudaResult},
+    function::FunctionAttribute,
+};
+use std::fmt;
+
+/// The launch limits of a device, see [`DeviceInfo::limits`].
+///
+/// [`DeviceInfo::limits`]: crate::DeviceInfo::limits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceLimits {
+    pub max_threads_per_block: usize,
+    pub max_block_dim_x: usize,
+    pub max_grid_dim_x: usize,
+    pub max_shared_mem_per_block: usize,
+}
+
+/// The limits a kernel function adds to those of the device, queried once
+/// per function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FunctionLimits {
+    /// The threads per block the function allows, which may be less than
+    /// the device allows.
+    pub(crate) max_threads_per_block: usize,
+    /// The static shared memory the function uses, in bytes.
+    pub(crate) static_shared_mem: usize,
+}
+
+impl FunctionLimits {
+    pub(crate) fn query(function: &impl FunctionDriver) -> CudaResult<Self> {
+        let get = |attribute| {
+            let value = function.attribute(attribute)?;
+            usize::try_from(value).map_err(|_| CudaError::InvalidValue)
+        };
+        Ok(Self {
+            max_threads_per_block: get(FunctionAttribute::MaxThreadsPerBlock)?,
+            static_shared_mem: get(FunctionAttribute::SharedMemorySizeBytes)?,
+        })
+    }
+}
+
+impl DeviceLimits {
+    /// Checks `config` against the limits of the device and of the
+    /// function.
+    pub(crate) fn check(
+        &self, kernel: &str, config: &KernelConfig, function: &FunctionLimits,
+    ) -> LaunchResult<()> {
+        let grid = self.max_grid_dim_x;
+        let block = self
+            .max_threads_per_block
+            .min(self.max_block_dim_x)
+            .min(function.max_threads_per_block);
+        let shared = self
+            .max_shared_mem_per_block
+            .saturating_sub(function.static_shared_mem);
+
+        check_limit(kernel, Limit::GridSize, config.global_work_size, 1, grid)?;
+        check_limit(
+            kernel,
+            Limit::BlockSize,
+            config.local_work_size,
+            1,
+            block,
+        )?;
+        check_limit(kernel, Limit::SharedMemory, config.shared_mem, 0, shared)
+    }
+}
+
+fn check_limit(
+    kernel: &str, limit: Limit, requested: usize, min: usize, max: usize,
+) -> LaunchResult<()> {
+    if (min..=max).contains(&requested) {
+        Ok(())
+    } else {
+        Err(LaunchError::InvalidConfig {
+            kernel: kernel.to_string(),
+            limit,
+            requested,
+            min,
+            max,
+        })
+    }
+}
+
+/// A launch parameter constrained by the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// `global_work_size`, the number of blocks.
+    GridSize,
+    /// `local_work_size`, the number of threads per block.
+    BlockSize,
+    /// `shared_mem`, the dynamic shared memory per block in bytes.
+    SharedMemory,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::GridSize => "grid size",
+            Limit::BlockSize => "block size",
+            Limit::SharedMemory => "shared memory",
+        })
+    }
+}
+
+/// An error of launching a kernel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LaunchError {
+    Cuda(CudaError),
+    /// The workspace of the launch could not be constructed.
+    Load(LoadError),
+    /// The launch configuration exceeds a limit of the device or the kernel.
+    InvalidConfig {
+        kernel: String,
+        limit: Limit,
+        requested: usize,
+        min: usize,
+        max: usize,
+    },
+}
+
+pub type LaunchResult<T> = Result<T, LaunchError>;
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LaunchError::Cuda(e) => write!(f, "{}", e),
+            LaunchError::Load(e) => write!(f, "{}", e),
+            LaunchError::InvalidConfig {
+                kernel,
+                limit,
+                requested,
+                min,
+                max,
+            } => write!(
+                f,
+                "kernel {}: {} {} is out of the range {}..={}",
+                kernel, limit, requested, min, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LaunchError {}
+
+impl From<CudaError> for LaunchError {
+    fn from(e: CudaError) -> Self { LaunchError::Cuda(e) }
+}
+
+impl From<LoadError> for LaunchError {
+    fn from(e: LoadError) -> Self { LaunchError::Load(e) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{device::FakeDevice, DeviceAttribute};
+
+    /// A kernel function allowing `max_threads` threads per block and using
+    /// `static_shared` bytes of static shared memory.
+    struct FakeFunction {
+        max_threads: i32,
+        static_shared: i32,
+    }
+
+    impl FunctionDriver for FakeFunction {
+        fn attribute(&self, attribute: FunctionAttribute) -> CudaResult<i32> {
+            match attribute {
+                FunctionAttribute::MaxThreadsPerBlock => Ok(self.max_threads),
+                FunctionAttribute::SharedMemorySizeBytes => {
+                    Ok(self.static_shared)
+                }
+                _ => Err(CudaError::InvalidValue),
+            }
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let limits = FakeDevice::new("fake")
+            .with_attribute(DeviceAttribute::MaxGridDimX, 1 << 16)
+            .info()
+            .unwrap()
+            .limits();
+        let function = FunctionLimits::query(&FakeFunction {
+            max_threads: 256,
+            static_shared: 1 << 10,
+        })
+        .unwrap();
+        let config =
+            |global_work_size, local_work_size, shared_mem| KernelConfig {
+                global_work_size,
+                local_work_size,
+                shared_mem,
+            };
+        let check = |config| limits.check("k", &config, &function);
+
+        assert_eq!(check(config(1 << 16, 256, 47 << 10)), Ok(()));
+        // The function allows less threads than the device.
+        let error = check(config(1, 512, 0)).unwrap_err();
+        assert!(matches!(
+            error,
+            LaunchError::InvalidConfig {
+                limit: Limit::BlockSize,
+                requested: 512,
+                max: 256,
+                ..
+            }
+        ));
+        // The static shared memory leaves less for the dynamic one.
+        let error = check(config(1, 32, 48 << 10)).unwrap_err();
+        assert!(matches!(
+            error,
+            LaunchError::InvalidConfig {
+                limit: Limit::SharedMemory,
+                max,
+                ..
+            } if max == 47 << 10
+        ));
+        let error = check(config((1 << 16) + 1, 32, 0)).unwrap_err();
+        assert!(matches!(
+            error,
+            LaunchError::InvalidConfig {
+                limit: Limit::GridSize,
+                ..
+            }
+        ));
+        assert!(check(config(0, 32, 0)).is_err());
+    }
+
+    #[test]
+    fn test_query_function_limits() {
+        let function = FakeFunction {
+            max_threads: -1,
+            static_shared: 0,
+        };
+        assert_eq!(
+            FunctionLimits::query(&function),
+            Err(CudaError::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn test_check_limit() {
+        assert!(check_limit("k", Limit::BlockSize, 1024, 1, 1024).is_ok());
+        assert!(check_limit("k", Limit::SharedMemory, 0, 0, 48).is_ok());
+
+        let error = check_limit("G1_multiexp", Limit::BlockSize, 2048, 1, 1024);
+        let error = error.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "kernel G1_multiexp: block size 2048 is out of the range 1..=1024"
+        );
+        assert!(check_limit("k", Limit::GridSize, 0, 1, 8).is_err());
+    }
+}
@@ -0,0 +1,361 @@
+//! Device memory accounting per workspace.
+//!
+//! The buffers of `DeviceData`, `DeviceParam` and the parameters copied for a
+//! launch are counted against the workspace active on the allocating thread,
+//! until they are freed. Buffers allocated while no workspace is active are
+//! not counted.
+//!
+//! Buffers marked sensitive are zeroed before they are released, so the data
+//! is not left to the next user of the device memory. A buffer dropped
+//! without being zeroed is zeroed in the context it was allocated in, even if
+//! no workspace is active on the dropping thread.
+
+use crate::{ctx_stack_guard::active_memory, stream::CudaStream};
+
+use cuda_driver_sys::CUdeviceptr;
+use rustacuda::{
+    context::{ContextStack, CurrentContext, UnownedContext},
+    error::{CudaError, CudaResult},
+    memory::DeviceBuffer,
+};
+use std::{
+    ffi::c_void,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+const LOG_TARGET: &str = "ag_cuda_proxy::memory";
+
+/// The device memory held by the buffers of a workspace.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The bytes allocated now.
+    pub live: usize,
+    /// The most bytes allocated at once.
+    pub peak: usize,
+    /// The bytes the workspace may allocate at once, `None` if unlimited.
+    pub quota: Option<usize>,
+}
+
+/// The counters of a workspace. `usize::MAX` stands for no quota and no
+/// logging.
+pub(crate) struct MemoryAccount {
+    live: AtomicUsize,
+    peak: AtomicUsize,
+    quota: AtomicUsize,
+    log_threshold: AtomicUsize,
+}
+
+impl Default for MemoryAccount {
+    fn default() -> Self {
+        Self {
+            live: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            quota: AtomicUsize::new(usize::MAX),
+            log_threshold: AtomicUsize::new(usize::MAX),
+        }
+    }
+}
+
+impl MemoryAccount {
+    pub(crate) fn usage(&self) -> MemoryUsage {
+        let quota = self.quota.load(Ordering::Relaxed);
+        MemoryUsage {
+            live: self.live.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+            quota: (quota != usize::MAX).then_some(quota),
+        }
+    }
+
+    pub(crate) fn set_quota(&self, quota: Option<usize>) {
+        self.quota
+            .store(quota.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_log_threshold(&self, threshold: Option<usize>) {
+        self.log_threshold
+            .store(threshold.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    /// Counts `size` bytes, failing with `OutOfMemory` if they exceed the
+    /// quota.
+    fn reserve(self: &Arc<Self>, size: usize) -> CudaResult<Reservation> {
+        let quota = self.quota.load(Ordering::Relaxed);
+        let live = self
+            .live
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| {
+                live.checked_add(size).filter(|&x| x <= quota)
+            })
+            .map_err(|live| {
+                log::warn!(
+                    target: LOG_TARGET,
+                    "allocating {} B exceeds the quota of {} B, {} B live",
+                    size,
+                    quota,
+                    live
+                );
+                CudaError::OutOfMemory
+            })?
+            + size;
+        self.peak.fetch_max(live, Ordering::Relaxed);
+
+        if size >= self.log_threshold.load(Ordering::Relaxed) {
+            log::info!(
+                target: LOG_TARGET,
+                "allocated {} B on the device, {} B live",
+                size,
+                live
+            );
+        }
+        Ok(Reservation {
+            account: Some(self.clone()),
+            size,
+        })
+    }
+}
+
+/// Bytes counted against a workspace until dropped.
+struct Reservation {
+    account: Option<Arc<MemoryAccount>>,
+    size: usize,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(account) = &self.account {
+            account.live.fetch_sub(self.size, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A device buffer and its device pointer, which kernels receive by
+/// reference. It can be shared between threads, so that a `DeviceData` can
+/// be passed by reference to a blocking task. The fake memory of the tests
+/// cannot.
+pub(crate) struct CudaBuffer {
+    buffer: DeviceBuffer<u8>,
+    address: CUdeviceptr,
+}
+
+// SAFETY: a shared buffer only reads its length and its device pointer,
+// which is a plain address valid on every thread the context of the
+// allocation is current on, and passes them to driver calls, which are
+// thread-safe. Writing to or freeing the buffer takes it mutably or by value.
+unsafe impl Sync for CudaBuffer {}
+
+impl CudaBuffer {
+    pub(crate) fn len(&self) -> usize { self.buffer.len() }
+
+    pub(crate) fn address(&self) -> CUdeviceptr { self.address }
+}
+
+/// Device memory, faked in tests.
+pub(crate) enum DeviceMemory {
+    /// A buffer and the context it was allocated in.
+    Cuda(CudaBuffer, UnownedContext),
+    #[cfg(test)]
+    Fake(crate::fake::FakeMemory),
+}
+
+impl DeviceMemory {
+    pub(crate) fn uninitialized(size: usize) -> CudaResult<Self> {
+        let context = CurrentContext::get_current()?;
+        let buffer = unsafe { DeviceBuffer::uninitialized(size)? };
+        let address = buffer.as_ptr() as CUdeviceptr;
+        Ok(DeviceMemory::Cuda(CudaBuffer { buffer, address }, context))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            DeviceMemory::Cuda(buffer, _) => buffer.len(),
+            #[cfg(test)]
+            DeviceMemory::Fake(memory) => memory.len(),
+        }
+    }
+
+    /// The pointer passed to a kernel for this memory.
+    pub(crate) fn param_pointer(&self) -> *mut c_void {
+        match self {
+            DeviceMemory::Cuda(buffer, _) => {
+                &buffer.address as *const CUdeviceptr as *mut c_void
+            }
+            #[cfg(test)]
+            DeviceMemory::Fake(memory) => memory.param_pointer(),
+        }
+    }
+
+    /// Calls `f` with a new stream of the context the memory was allocated
+    /// in and waits for that stream. The context is current during the call,
+    /// whichever context is current on this thread.
+    fn in_own_context(
+        &mut self, f: impl FnOnce(&CudaStream, &mut Self) -> CudaResult<()>,
+    ) -> CudaResult<()> {
+        match self {
+            DeviceMemory::Cuda(_, context) => {
+                let context = context.clone();
+                ContextStack::push(&context)?;
+                let result = CudaStream::new().and_then(|stream| {
+                    f(&stream, self)?;
+                    stream.synchronize()
+                });
+                ContextStack::pop()?;
+                result
+            }
+            #[cfg(test)]
+            DeviceMemory::Fake(memory) => {
+                let stream = memory.owner()?;
+                f(&stream, self)?;
+                stream.synchronize()
+            }
+        }
+    }
+}
+
+/// A device buffer counted against the workspace active when it was
+/// allocated.
+pub(crate) struct TrackedBuffer {
+    memory: DeviceMemory,
+    sensitive: bool,
+    /// Whether the zeros are already queued and nothing was written since.
+    wiped: bool,
+    _reservation: Reservation,
+}
+
+impl TrackedBuffer {
+    pub(crate) fn uninitialized(size: usize) -> CudaResult<Self> {
+        Self::reserve(size, DeviceMemory::uninitialized)
+    }
+
+    /// Allocates the buffer through the driver of `stream`.
+    pub(crate) fn alloc(stream: &CudaStream, size: usize) -> CudaResult<Self> {
+        Self::reserve(size, |size| stream.driver().alloc(size))
+    }
+
+    fn reserve(
+        size: usize, alloc: impl FnOnce(usize) -> CudaResult<DeviceMemory>,
+    ) -> CudaResult<Self> {
+        let reservation = match active_memory() {
+            Some(account) => account.reserve(size)?,
+            None => Reservation {
+                account: None,
+                size,
+            },
+        };
+        Ok(Self {
+            memory: alloc(size)?,
+            sensitive: false,
+            wiped: false,
+            _reservation: reservation,
+        })
+    }
+
+    /// Zeroes the buffer before it is released.
+    pub(crate) fn set_sensitive(&mut self) { self.sensitive = true; }
+
+    pub(crate) fn is_sensitive(&self) -> bool { self.sensitive }
+
+    /// Queues zeroing the buffer on `stream`, after the work already queued.
+    /// Writing to the buffer afterwards zeroes it again on drop.
+    pub(crate) fn wipe(&mut self, stream: &CudaStream) -> CudaResult<()> {
+        unsafe { stream.driver().memset(&mut self.memory, 0)? };
+        self.wiped = true;
+        Ok(())
+    }
+
+    /// Zeroes the buffer on a new stream and waits for it. The work using
+    /// the buffer is done: the tasks borrowing or owning it wait for their
+    /// streams before they are dropped.
+    fn wipe_now(&mut self) -> CudaResult<()> {
+        self.memory.in_own_context(|stream, memory| unsafe {
+            stream.driver().memset(memory, 0)
+        })
+    }
+}
+
+impl Drop for TrackedBuffer {
+    fn drop(&mut self) {
+        if self.sensitive && !self.wiped {
+            if let Err(e) = self.wipe_now() {
+                log::error!(
+                    target: LOG_TARGET,
+                    "cannot zero a sensitive buffer of {} B: {}",
+                    self.memory.len(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+impl Deref for TrackedBuffer {
+    type Target = DeviceMemory;
+
+    fn deref(&self) -> &DeviceMemory { &self.memory }
+}
+
+impl DerefMut for TrackedBuffer {
+    fn deref_mut(&mut self) -> &mut DeviceMemory {
+        self.wiped = false;
+        &mut self.memory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{FakeStream, Op};
+
+    #[test]
+    fn test_quota() {
+        let account = Arc::new(MemoryAccount::default());
+        account.set_quota(Some(100));
+
+        let first = account.reserve(60).unwrap();
+        assert_eq!(account.reserve(50).err(), Some(CudaError::OutOfMemory));
+        let second = account.reserve(40).unwrap();
+        assert_eq!(
+            account.usage(),
+            MemoryUsage {
+                live: 100,
+                peak: 100,
+                quota: Some(100),
+            }
+        );
+
+        drop(first);
+        drop(second);
+        account.set_quota(None);
+        let usage = account.usage();
+        assert_eq!((usage.live, usage.peak, usage.quota), (0, 100, None));
+    }
+
+    #[test]
+    fn test_write_after_wipe() {
+        let fake = FakeStream::new();
+        let stream = fake.stream();
+        let mut buffer = TrackedBuffer::alloc(&stream, 8).unwrap();
+        buffer.set_sensitive();
+        buffer.wipe(&stream).unwrap();
+        assert!(buffer.wiped);
+
+        unsafe {
+            stream
+                .driver()
+                .copy_to_device(&mut buffer, 0, &[0xab; 8])
+                .unwrap()
+        };
+        assert!(!buffer.wiped);
+        let memory = unsafe { fake.memory(buffer.param_pointer()) };
+        fake.take_ops();
+
+        drop(buffer);
+        assert_eq!(*memory.borrow(), [0; 8]);
+        assert!(matches!(
+            fake.take_ops()[..],
+            [Op::Memset { value: 0, .. }, Op::Synchronize]
+        ));
+    }
+}
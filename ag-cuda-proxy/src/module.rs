@@ -1,30 +1,98 @@
 use crate::{
     context::CudaContext, ctx_stack_guard::WorkspaceContextGuard, cuda_init,
-    kernel::Kernel,
+    device::DeviceInfo, driver::Module, kernel::Kernel, limits::DeviceLimits,
+    memory::MemoryUsage, stream::CudaStream,
 };
 
+use once_cell::sync::OnceCell;
 use rustacuda::{
     context::{Context, ContextFlags, ContextStack},
     device::Device,
-    error::CudaResult,
-    module::Module,
-    stream::{Stream, StreamFlags},
+    error::{CudaError, CudaResult},
 };
+use serde::Deserialize;
+use std::{
+    ffi::{CStr, CString},
+    fmt,
+    time::Duration,
+};
+
+/// An error of loading the kernels of a fatbin.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    Cuda(CudaError),
+    /// The fatbin is a stub embedded by `ag-build` in place of kernels that
+    /// could not be compiled, with the build error.
+    Stub(String),
+    /// The manifest passed to
+    /// [`CudaWorkspace::from_bytes_with_manifest`] cannot be parsed.
+    InvalidManifest(String),
+    /// The fatbin lacks kernels listed in its manifest, it was not compiled
+    /// from the source the manifest describes.
+    MissingKernels {
+        /// The digest of the source the manifest describes.
+        digest: String,
+        kernels: Vec<String>,
+    },
+}
+
+pub type LoadResult<T> = Result<T, LoadError>;
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Cuda(e) => write!(f, "{}", e),
+            LoadError::Stub(message) => write!(
+                f,
+                "the CUDA kernels were not compiled at build time: {}",
+                message
+            ),
+            LoadError::InvalidManifest(message) => {
+                write!(f, "invalid kernel manifest: {}", message)
+            }
+            LoadError::MissingKernels { digest, kernels } => write!(
+                f,
+                "the fatbin lacks the kernels {} of its manifest, it was not \
+                 compiled from the source with digest {}",
+                kernels.join(", "),
+                digest
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<CudaError> for LoadError {
+    fn from(e: CudaError) -> Self { LoadError::Cuda(e) }
+}
 
 pub struct CudaWorkspace {
     // TODO: support multiple module
     module: Module,
     context: CudaContext,
+    info: DeviceInfo,
+    limits: DeviceLimits,
 }
 
 unsafe impl Send for CudaWorkspace {}
 unsafe impl Sync for CudaWorkspace {}
 
 impl CudaWorkspace {
-    pub fn from_bytes(bytes: &[u8]) -> CudaResult<Self> {
+    /// Loads the kernels of a fatbin. A stub embedded by `ag-build` in place
+    /// of kernels that could not be compiled fails with [`LoadError::Stub`],
+    /// carrying the build error.
+    pub fn from_bytes(bytes: &[u8]) -> LoadResult<Self> {
+        if let Some(error) = bytes.strip_prefix(ag_types::STUB_KERNEL_MAGIC) {
+            return Err(LoadError::Stub(
+                String::from_utf8_lossy(error).into_owned(),
+            ));
+        }
         cuda_init();
 
         let device = Device::get_device(0)?;
+        let info = DeviceInfo::query(&device)?;
+        let limits = info.limits();
 
         // Create a context associated to this device
         let ctx = Context::create_and_push(
@@ -32,18 +100,75 @@ impl CudaWorkspace {
             device,
         )?;
 
-        let maybe_module = Module::load_from_bytes(bytes);
+        let maybe_module = Module::load(bytes);
         ContextStack::pop().expect("Cannot remove context.");
 
         Ok(Self {
             context: CudaContext::new(ctx),
             module: maybe_module?,
+            info,
+            limits,
         })
     }
 
+    /// Like [`from_bytes`](Self::from_bytes), also checking that the fatbin
+    /// contains every kernel listed in `manifest`, the JSON manifest written
+    /// by `ag-build` next to it. Fails with [`LoadError::MissingKernels`] if
+    /// a kernel is missing and with [`LoadError::InvalidManifest`] if the
+    /// manifest is invalid.
+    pub fn from_bytes_with_manifest(
+        bytes: &[u8], manifest: &str,
+    ) -> LoadResult<Self> {
+        let workspace = Self::from_bytes(bytes)?;
+        {
+            let _active = workspace.activate()?;
+            check_manifest(manifest, |name| {
+                workspace.module.function(name).is_ok()
+            })?;
+        }
+        Ok(workspace)
+    }
+
+    /// The capabilities of the device.
+    pub fn device_info(&self) -> &DeviceInfo { &self.info }
+
+    /// The launch limits of the device.
+    pub fn limits(&self) -> &DeviceLimits { &self.limits }
+
+    /// The device memory held by the buffers allocated while the workspace
+    /// was active.
+    pub fn memory_usage(&self) -> MemoryUsage { self.context.memory().usage() }
+
+    /// Limits the device memory the buffers of the workspace may hold at
+    /// once, or removes the limit with `None`. An allocation exceeding the
+    /// quota fails with `OutOfMemory` before reaching the driver.
+    pub fn set_memory_quota(&self, quota: Option<usize>) {
+        self.context.memory().set_quota(quota);
+    }
+
+    /// Logs every allocation of at least `threshold` bytes at info level for
+    /// the `ag_cuda_proxy::memory` target, or stops logging with `None`.
+    pub fn set_memory_log_threshold(&self, threshold: Option<usize>) {
+        self.context.memory().set_log_threshold(threshold);
+    }
+
+    /// Activates the workspace on the current thread, waiting while another
+    /// thread uses it. Activating it again on the same thread, e.g. in nested
+    /// `auto_workspace` calls, is allowed. Fails with `ContextAlreadyInUse` if
+    /// another workspace is active on this thread.
     pub fn activate<'a>(&'a self) -> CudaResult<ActiveWorkspace<'a>> {
-        let guard = WorkspaceContextGuard::new(&self.context)?;
-        Ok(ActiveWorkspace(&self, guard))
+        let guard = WorkspaceContextGuard::new(&self.context, None)?;
+        Ok(ActiveWorkspace(self, guard))
+    }
+
+    /// Like [`activate`](Self::activate), but fails with
+    /// `ContextAlreadyInUse` if another thread still uses the workspace after
+    /// `timeout`.
+    pub fn activate_timeout<'a>(
+        &'a self, timeout: Duration,
+    ) -> CudaResult<ActiveWorkspace<'a>> {
+        let guard = WorkspaceContextGuard::new(&self.context, Some(timeout))?;
+        Ok(ActiveWorkspace(self, guard))
     }
 }
 
@@ -53,11 +178,179 @@ pub struct ActiveWorkspace<'a>(
 );
 
 impl<'a> ActiveWorkspace<'a> {
+    /// Creates a kernel on a new stream.
     pub fn create_kernel(&self) -> CudaResult<Kernel<'a>> {
-        Ok(Kernel::new(&self.0.module, self.stream()?))
+        Ok(self.create_kernel_on(&self.stream()?))
+    }
+
+    /// Creates a kernel launching on `stream`, after the work already queued
+    /// on it.
+    pub fn create_kernel_on(&self, stream: &CudaStream) -> Kernel<'a> {
+        Kernel::new(&self.0.module, &self.0.limits, stream.clone())
     }
 
-    pub fn stream(&self) -> CudaResult<Stream> {
-        Stream::new(StreamFlags::NON_BLOCKING, None)
+    /// Creates a new non-blocking stream.
+    pub fn stream(&self) -> CudaResult<CudaStream> { CudaStream::new() }
+}
+
+/// The part of the manifest written by `ag-build` that is checked.
+#[derive(Deserialize)]
+struct Manifest {
+    digest: String,
+    kernels: Vec<ManifestKernel>,
+}
+
+#[derive(Deserialize)]
+struct ManifestKernel {
+    name: String,
+}
+
+/// Checks that `has_kernel` is true for every kernel of `manifest`.
+fn check_manifest(
+    manifest: &str, has_kernel: impl Fn(&CStr) -> bool,
+) -> LoadResult<()> {
+    let manifest: Manifest = serde_json::from_str(manifest)
+        .map_err(|e| LoadError::InvalidManifest(e.to_string()))?;
+    let missing: Vec<_> = manifest
+        .kernels
+        .into_iter()
+        .map(|kernel| kernel.name)
+        .filter(|name| {
+            CString::new(name.as_str()).map_or(true, |n| !has_kernel(&n))
+        })
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(LoadError::MissingKernels {
+        digest: manifest.digest,
+        kernels: missing,
+    })
+}
+
+/// The result of a workspace constructor passed to `construct_workspace!`,
+/// either a `CudaWorkspace`, a `CudaResult<CudaWorkspace>` or a
+/// `LoadResult<CudaWorkspace>`.
+pub trait IntoWorkspaceResult {
+    fn into_workspace_result(self) -> LoadResult<CudaWorkspace>;
+}
+
+impl IntoWorkspaceResult for CudaWorkspace {
+    fn into_workspace_result(self) -> LoadResult<CudaWorkspace> { Ok(self) }
+}
+
+impl IntoWorkspaceResult for CudaResult<CudaWorkspace> {
+    fn into_workspace_result(self) -> LoadResult<CudaWorkspace> { Ok(self?) }
+}
+
+impl IntoWorkspaceResult for LoadResult<CudaWorkspace> {
+    fn into_workspace_result(self) -> LoadResult<CudaWorkspace> { self }
+}
+
+/// A workspace constructed on first use. A failed construction is not
+/// cached, the next use tries again.
+pub struct LazyWorkspace {
+    cell: OnceCell<CudaWorkspace>,
+    init: fn() -> LoadResult<CudaWorkspace>,
+}
+
+impl LazyWorkspace {
+    pub const fn new(init: fn() -> LoadResult<CudaWorkspace>) -> Self {
+        Self {
+            cell: OnceCell::new(),
+            init,
+        }
+    }
+
+    /// Returns the workspace, constructing it if needed.
+    pub fn get(&self) -> LoadResult<&CudaWorkspace> {
+        self.cell.get_or_try_init(self.init)
+    }
+
+    pub fn is_initialized(&self) -> bool { self.cell.get().is_some() }
+
+    /// Constructs the workspace if needed and activates it, see
+    /// [`CudaWorkspace::activate`].
+    pub fn activate(&self) -> LoadResult<ActiveWorkspace<'_>> {
+        Ok(self.get()?.activate()?)
+    }
+
+    /// Constructs the workspace if needed and activates it, see
+    /// [`CudaWorkspace::activate_timeout`].
+    pub fn activate_timeout(
+        &self, timeout: Duration,
+    ) -> LoadResult<ActiveWorkspace<'_>> {
+        Ok(self.get()?.activate_timeout(timeout)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustacuda::error::CudaError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_lazy_workspace_retries() {
+        static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+        static WORKSPACE: LazyWorkspace = LazyWorkspace::new(|| {
+            ATTEMPTS.fetch_add(1, Ordering::SeqCst);
+            Err(LoadError::Stub("cannot run nvcc".to_owned()))
+        });
+
+        let stub = LoadError::Stub("cannot run nvcc".to_owned());
+        assert_eq!(WORKSPACE.get().err(), Some(stub.clone()));
+        assert_eq!(WORKSPACE.activate().err(), Some(stub));
+        assert!(!WORKSPACE.is_initialized());
+        assert_eq!(ATTEMPTS.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_check_manifest() {
+        let manifest = r#"{
+            "digest": "abc",
+            "limb_bits": 32,
+            "kernels": [{"name": "Fr_radix_fft"}, {"name": "G1_multiexp"}]
+        }"#;
+        let has_fft = |name: &CStr| name.to_bytes() == b"Fr_radix_fft";
+
+        assert_eq!(check_manifest(manifest, |_| true), Ok(()));
+        let error = check_manifest(manifest, has_fft).unwrap_err();
+        assert_eq!(
+            error,
+            LoadError::MissingKernels {
+                digest: "abc".to_owned(),
+                kernels: vec!["G1_multiexp".to_owned()],
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "the fatbin lacks the kernels G1_multiexp of its manifest, it was \
+             not compiled from the source with digest abc"
+        );
+        let error = check_manifest("{\"kernels\": []}", |_| true);
+        assert!(matches!(error, Err(LoadError::InvalidManifest(_))));
+    }
+
+    #[test]
+    fn test_load_stub() {
+        let stub = [ag_types::STUB_KERNEL_MAGIC, b"cannot run nvcc"].concat();
+        let error = CudaWorkspace::from_bytes(&stub).err().unwrap();
+        assert_eq!(error, LoadError::Stub("cannot run nvcc".to_owned()));
+        assert_eq!(
+            error.to_string(),
+            "the CUDA kernels were not compiled at build time: cannot run nvcc"
+        );
+
+        let error = CudaWorkspace::from_bytes_with_manifest(&stub, "{}").err();
+        assert_eq!(error, Some(LoadError::Stub("cannot run nvcc".to_owned())));
+        assert_eq!(
+            Err(error.clone().unwrap()).into_workspace_result().err(),
+            error
+        );
+        assert_eq!(
+            Err(CudaError::NoDevice).into_workspace_result().err(),
+            Some(LoadError::Cuda(CudaError::NoDevice))
+        );
     }
 }
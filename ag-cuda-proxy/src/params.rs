@@ -1,12 +1,27 @@
-use rustacuda::{error::CudaResult, memory::DeviceBuffer, stream::Stream};
-use std::ffi::c_void;
+use crate::{
+    memory::{DeviceMemory, TrackedBuffer},
+    stream::CudaStream,
+};
+
+use ag_types::KernelArg;
+use rustacuda::error::{CudaError, CudaResult};
+use std::{any::TypeId, ffi::c_void, marker::PhantomData};
 
 pub trait ParamIO {
     fn param_pointer(&self) -> *mut c_void;
-    fn after_call(&mut self, stream: &Stream) -> CudaResult<()>;
+    fn after_call(&mut self, stream: &CudaStream) -> CudaResult<()>;
+
+    /// The bytes copied to the device before and back to the host after the
+    /// call.
+    fn transfer_size(&self) -> (usize, usize) { (0, 0) }
+
+    /// Zeroes the device memory copied for the parameter once the call is
+    /// done. Borrowed device buffers are not affected, mark them sensitive
+    /// themselves.
+    fn set_sensitive(&mut self) {}
 }
 
-pub(crate) enum Param<'a, T> {
+pub(crate) enum Param<'a, T: KernelArg> {
     InVal(T),
     InRef(&'a T),
     InMut(&'a mut T),
@@ -17,7 +32,7 @@ pub(crate) enum Param<'a, T> {
     OutSlice(&'a mut [T]),
 }
 
-impl<'a, T> Param<'a, T> {
+impl<'a, T: KernelArg> Param<'a, T> {
     fn items(&self) -> usize {
         match self {
             Param::InVal(_)
@@ -29,7 +44,10 @@ impl<'a, T> Param<'a, T> {
         }
     }
 
-    fn size(&self) -> usize { std::mem::size_of::<T>() * self.items() }
+    fn size(&self) -> usize {
+        let () = T::ASSERT_LAYOUT;
+        std::mem::size_of::<T>() * self.items()
+    }
 
     pub(crate) fn input_pointer(&self) -> Option<*const T> {
         match self {
@@ -43,6 +61,16 @@ impl<'a, T> Param<'a, T> {
         }
     }
 
+    fn is_output(&self) -> bool {
+        matches!(
+            self,
+            Param::InMut(_)
+                | Param::Out(_)
+                | Param::InMutSlice(_)
+                | Param::OutSlice(_)
+        )
+    }
+
     pub(crate) fn output_pointer(&mut self) -> Option<*mut T> {
         match self {
             Param::InVal(_) => None,
@@ -56,39 +84,37 @@ impl<'a, T> Param<'a, T> {
     }
 
     pub(crate) fn before_call(
-        &self, stream: &Stream,
-    ) -> CudaResult<Option<DeviceBuffer<u8>>> {
-        use rustacuda::memory::AsyncCopyDestination;
+        &self, stream: &CudaStream,
+    ) -> CudaResult<Option<TrackedBuffer>> {
         if let Param::InVal(_) = self {
             return Ok(None);
         }
 
         let size = self.size();
 
-        let mut buffer = unsafe { DeviceBuffer::<u8>::uninitialized(size)? };
+        let mut buffer = TrackedBuffer::alloc(stream, size)?;
 
         if let Some(pointer) = self.input_pointer() {
             let bytes = unsafe {
                 std::slice::from_raw_parts(pointer as *const u8, size)
             };
-            unsafe { buffer.async_copy_from(bytes, stream)? };
+            unsafe { stream.driver().copy_to_device(&mut buffer, 0, bytes)? };
         }
 
         Ok(Some(buffer))
     }
 }
 
-impl<'a, T> ParamIO for (Param<'a, T>, Option<DeviceBuffer<u8>>) {
+impl<'a, T: KernelArg> ParamIO for (Param<'a, T>, Option<TrackedBuffer>) {
     fn param_pointer(&self) -> *mut c_void {
         if let Param::InVal(x) = &self.0 {
             x as *const T as *mut c_void
         } else {
-            self.1.as_ref().unwrap() as *const _ as *mut c_void
+            self.1.as_ref().unwrap().param_pointer()
         }
     }
 
-    fn after_call(&mut self, stream: &Stream) -> CudaResult<()> {
-        use rustacuda::memory::AsyncCopyDestination;
+    fn after_call(&mut self, stream: &CudaStream) -> CudaResult<()> {
         let buffer = if let Some(x) = &mut self.1 {
             x
         } else {
@@ -102,22 +128,170 @@ impl<'a, T> ParamIO for (Param<'a, T>, Option<DeviceBuffer<u8>>) {
                     self.0.size(),
                 )
             };
-            unsafe { buffer.async_copy_to(bytes, stream)? };
+            unsafe { stream.driver().copy_to_host(bytes, buffer)? };
+        }
+        if buffer.is_sensitive() {
+            buffer.wipe(stream)?;
         }
 
         Ok(())
     }
+
+    fn transfer_size(&self) -> (usize, usize) {
+        if self.1.is_none() {
+            return (0, 0);
+        }
+        let size = self.0.size();
+        let to_device = if self.0.input_pointer().is_some() {
+            size
+        } else {
+            0
+        };
+        let to_host = if self.0.is_output() { size } else { 0 };
+        (to_device, to_host)
+    }
+
+    fn set_sensitive(&mut self) {
+        if let Some(buffer) = &mut self.1 {
+            buffer.set_sensitive();
+        }
+    }
+}
+
+type DeferredParam<'b> =
+    Box<dyn FnOnce(&CudaStream) -> CudaResult<Box<dyn ParamIO + 'b>> + 'b>;
+
+/// The element type of kernel arguments whose type the launchers generated
+/// by `ag-build` cannot name, e.g. structs declared in a custom source. Any
+/// argument converts to it with [`PointerArg::untyped`] or
+/// [`ValueArg::untyped`].
+pub enum Untyped {}
+
+/// A kernel argument that is passed as a device pointer to elements of the
+/// type `T`.
+///
+/// It is used by the typed launchers generated by `ag-build`, whose argument
+/// structs name `T` after the type of the kernel parameter. Host data is
+/// only copied to the device when the argument is pushed to a kernel task.
+pub struct PointerArg<'b, T>(DeferredParam<'b>, PhantomData<fn() -> T>);
+
+impl<'b, T> PointerArg<'b, T> {
+    fn deferred(param: DeferredParam<'b>) -> Self { Self(param, PhantomData) }
+
+    /// Zeroes the device copy once the call is done, see
+    /// [`KernelTask::sensitive`](crate::KernelTask::sensitive).
+    pub fn sensitive(self) -> Self {
+        let inner = self.0;
+        Self::deferred(Box::new(move |stream| {
+            let mut param = inner(stream)?;
+            param.set_sensitive();
+            Ok(param)
+        }))
+    }
+
+    /// Passes the argument to a parameter of a type without a Rust
+    /// counterpart.
+    pub fn untyped(self) -> PointerArg<'b, Untyped> {
+        PointerArg::deferred(self.0)
+    }
+
+    pub(crate) fn into_param(
+        self, stream: &CudaStream,
+    ) -> CudaResult<Box<dyn ParamIO + 'b>> {
+        (self.0)(stream)
+    }
+}
+
+impl<'b, T: KernelArg> PointerArg<'b, T> {
+    fn host(param: Param<'b, T>) -> Self {
+        Self::deferred(Box::new(move |stream| {
+            let buffer = param.before_call(stream)?;
+            Ok(Box::new((param, buffer)))
+        }))
+    }
+
+    /// Copies `input` to the device before the call.
+    pub fn in_ref(input: &'b T) -> Self { Self::host(Param::InRef(input)) }
+
+    /// Copies `input` to the device before and back after the call.
+    pub fn in_mut(input: &'b mut T) -> Self { Self::host(Param::InMut(input)) }
+
+    /// Copies `output` back from the device after the call.
+    pub fn out(output: &'b mut T) -> Self { Self::host(Param::Out(output)) }
+
+    /// Copies `input` to the device before the call.
+    pub fn in_slice(input: &'b [T]) -> Self {
+        Self::host(Param::InRefSlice(input))
+    }
+
+    /// Copies `input` to the device before and back after the call.
+    pub fn in_mut_slice(input: &'b mut [T]) -> Self {
+        Self::host(Param::InMutSlice(input))
+    }
+
+    /// Copies `output` back from the device after the call.
+    pub fn out_slice(output: &'b mut [T]) -> Self {
+        Self::host(Param::OutSlice(output))
+    }
+}
+
+/// Passes the data as elements of `T`. Pushing the argument to a kernel task
+/// fails with `InvalidValue` if the data was uploaded as elements of another
+/// type or its size is not a multiple of the size of `T`.
+impl<'b, T: KernelArg> From<&'b DeviceData> for PointerArg<'b, T> {
+    fn from(data: &'b DeviceData) -> Self {
+        Self::deferred(Box::new(move |_| {
+            data.check_element::<T>()?;
+            Ok(Box::new(data))
+        }))
+    }
+}
+
+impl<'b> From<&'b DeviceData> for PointerArg<'b, Untyped> {
+    fn from(data: &'b DeviceData) -> Self {
+        Self::deferred(Box::new(move |_| Ok(Box::new(data))))
+    }
+}
+
+impl<'a: 'b, 'b, T: KernelArg> From<&'b DeviceParam<'a, T>>
+    for PointerArg<'b, T>
+{
+    fn from(param: &'b DeviceParam<'a, T>) -> Self {
+        Self::deferred(Box::new(move |_| Ok(Box::new(param))))
+    }
+}
+
+/// A kernel argument of the non-primitive type `T` that is passed by value.
+pub struct ValueArg<T>(Box<dyn ParamIO>, PhantomData<fn() -> T>);
+
+impl<T: KernelArg> ValueArg<T> {
+    pub fn new(input: T) -> Self {
+        Self(Box::new((Param::InVal(input), None)), PhantomData)
+    }
 }
 
-pub struct DeviceParam<'a, T> {
+impl<T> ValueArg<T> {
+    /// Passes the argument to a parameter of a type without a Rust
+    /// counterpart.
+    pub fn untyped(self) -> ValueArg<Untyped> { ValueArg(self.0, PhantomData) }
+
+    pub(crate) fn into_param(self) -> Box<dyn ParamIO> { self.0 }
+}
+
+impl<T: KernelArg> From<T> for ValueArg<T> {
+    fn from(input: T) -> Self { Self::new(input) }
+}
+
+pub struct DeviceParam<'a, T: KernelArg> {
     host_mem: &'a mut [T],
-    device_mem: DeviceBuffer<u8>,
+    device_mem: TrackedBuffer,
 }
 
-impl<'a, T> DeviceParam<'a, T> {
+impl<'a, T: KernelArg> DeviceParam<'a, T> {
     pub fn new(val: &'a mut [T]) -> CudaResult<Self> {
+        let () = T::ASSERT_LAYOUT;
         let size = val.len() * std::mem::size_of::<T>();
-        let buffer = unsafe { DeviceBuffer::<u8>::uninitialized(size)? };
+        let buffer = TrackedBuffer::uninitialized(size)?;
 
         Ok(Self {
             host_mem: val,
@@ -125,8 +299,15 @@ impl<'a, T> DeviceParam<'a, T> {
         })
     }
 
-    pub fn to_device(&mut self, stream: &Stream) -> CudaResult<()> {
-        use rustacuda::memory::AsyncCopyDestination;
+    /// Zeroes the device memory before it is released. The drop zeroes it on
+    /// a stream of its own and waits for that stream, so synchronize the
+    /// streams the copies were queued on before dropping it.
+    pub fn sensitive(mut self) -> Self {
+        self.device_mem.set_sensitive();
+        self
+    }
+
+    pub fn to_device(&mut self, stream: &CudaStream) -> CudaResult<()> {
         let size = self.host_mem.len() * std::mem::size_of::<T>();
 
         let bytes = unsafe {
@@ -135,13 +316,16 @@ impl<'a, T> DeviceParam<'a, T> {
                 size,
             )
         };
-        unsafe { self.device_mem.async_copy_from(bytes, stream)? };
+        unsafe {
+            stream
+                .driver()
+                .copy_to_device(&mut self.device_mem, 0, bytes)?
+        };
         stream.synchronize()?;
         Ok(())
     }
 
-    pub fn to_host(&mut self, stream: &Stream) -> CudaResult<()> {
-        use rustacuda::memory::AsyncCopyDestination;
+    pub fn to_host(&mut self, stream: &CudaStream) -> CudaResult<()> {
         let size = self.host_mem.len() * std::mem::size_of::<T>();
 
         let bytes = unsafe {
@@ -150,7 +334,7 @@ impl<'a, T> DeviceParam<'a, T> {
                 size,
             )
         };
-        unsafe { self.device_mem.async_copy_to(bytes, stream)? };
+        unsafe { stream.driver().copy_to_host(bytes, &self.device_mem)? };
         stream.synchronize()?;
         Ok(())
     }
@@ -162,59 +346,118 @@ impl<'a, T> DeviceParam<'a, T> {
     }
 }
 
-impl<'a, 'b, T> ParamIO for &'b DeviceParam<'a, T> {
-    fn param_pointer(&self) -> *mut c_void {
-        (&self.device_mem) as *const _ as *mut c_void
-    }
+impl<'a, 'b, T: KernelArg> ParamIO for &'b DeviceParam<'a, T> {
+    fn param_pointer(&self) -> *mut c_void { self.device_mem.param_pointer() }
+
+    fn after_call(&mut self, _stream: &CudaStream) -> CudaResult<()> { Ok(()) }
+}
 
-    fn after_call(&mut self, _stream: &Stream) -> CudaResult<()> { Ok(()) }
+/// The type of the elements a [`DeviceData`] was uploaded from.
+#[derive(Clone, Copy, Debug)]
+struct Element {
+    id: TypeId,
+    name: &'static str,
+}
+
+impl Element {
+    fn of<T: KernelArg>() -> Self {
+        let () = T::ASSERT_LAYOUT;
+        Self {
+            id: TypeId::of::<T>(),
+            name: std::any::type_name::<T>(),
+        }
+    }
 }
 
 pub struct DeviceData {
     size: usize,
-    device_mem: DeviceBuffer<u8>,
+    element: Option<Element>,
+    device_mem: TrackedBuffer,
 }
 
 impl DeviceData {
     pub fn uninitialized(size: usize) -> CudaResult<Self> {
         Ok(Self {
             size,
-            device_mem: unsafe { DeviceBuffer::<u8>::uninitialized(size)? },
+            element: None,
+            device_mem: TrackedBuffer::uninitialized(size)?,
         })
     }
 
-    pub fn upload<T>(val: &[T], stream: &Stream) -> CudaResult<Self> {
-        use rustacuda::memory::AsyncCopyDestination;
-
+    pub fn upload<T: KernelArg>(
+        val: &[T], stream: &CudaStream,
+    ) -> CudaResult<Self> {
         let size = val.len() * std::mem::size_of::<T>();
-        let mut buffer = unsafe { DeviceBuffer::<u8>::uninitialized(size)? };
+        let mut buffer = TrackedBuffer::alloc(stream, size)?;
 
         let bytes = unsafe {
             std::slice::from_raw_parts(val.as_ptr() as *const u8, size)
         };
-        unsafe { buffer.async_copy_from(bytes, stream)? };
+        unsafe { stream.driver().copy_to_device(&mut buffer, 0, bytes)? };
 
         Ok(Self {
             size,
+            element: Some(Element::of::<T>()),
             device_mem: buffer,
         })
     }
 
+    /// Zeroes the device memory before it is released, for data derived from
+    /// secrets. The drop zeroes it on a stream of its own and waits for that
+    /// stream only, the tasks using the data are completed by then.
+    pub fn sensitive(mut self) -> Self {
+        self.device_mem.set_sensitive();
+        self
+    }
+
     pub fn swap_device_pointer(me: &mut Self, another: &mut Self) {
         assert_eq!(me.size, another.size);
 
+        std::mem::swap(&mut me.element, &mut another.element);
         std::mem::swap(&mut me.device_mem, &mut another.device_mem);
     }
 
     pub fn size(&self) -> usize { self.size }
+
+    /// Checks that the data can be passed as elements of `T`: it must have
+    /// been uploaded from elements of `T`, or be uninitialized with a size
+    /// that is a multiple of the size of `T`.
+    fn check_element<T: KernelArg>(&self) -> CudaResult<()> {
+        let expected = Element::of::<T>();
+        let remainder = self.size % std::mem::size_of::<T>().max(1);
+        match self.element {
+            Some(element) if element.id != expected.id => {
+                log::error!(
+                    "Device data of `{}` passed as `{}`",
+                    element.name,
+                    expected.name
+                );
+                Err(CudaError::InvalidValue)
+            }
+            _ if remainder > 0 => {
+                log::error!(
+                    "Device data of {} bytes passed as `{}` of {} bytes",
+                    self.size,
+                    expected.name,
+                    std::mem::size_of::<T>()
+                );
+                Err(CudaError::InvalidValue)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn device_mem(&self) -> &DeviceMemory { &self.device_mem }
+
+    pub(crate) fn device_mem_mut(&mut self) -> &mut DeviceMemory {
+        &mut self.device_mem
+    }
 }
 
 impl<'b> ParamIO for &'b DeviceData {
-    fn param_pointer(&self) -> *mut c_void {
-        (&self.device_mem) as *const _ as *mut c_void
-    }
+    fn param_pointer(&self) -> *mut c_void { self.device_mem.param_pointer() }
 
-    fn after_call(&mut self, _stream: &Stream) -> CudaResult<()> { Ok(()) }
+    fn after_call(&mut self, _stream: &CudaStream) -> CudaResult<()> { Ok(()) }
 }
 
 pub(crate) struct NullPointer;
@@ -225,5 +468,189 @@ impl<'a> ParamIO for NullPointer {
         (&NULL) as *const _ as *mut c_void
     }
 
-    fn after_call(&mut self, _stream: &Stream) -> CudaResult<()> { Ok(()) }
+    fn after_call(&mut self, _stream: &CudaStream) -> CudaResult<()> { Ok(()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fake::{FakeStream, Op},
+        memory::DeviceMemory,
+    };
+    use std::rc::Rc;
+
+    /// Runs a call of `param` on a fake stream, during which the kernel
+    /// writes `written` to the device memory of the parameter.
+    fn call<T: KernelArg>(
+        fake: &Rc<FakeStream>, param: Param<'_, T>, written: &[u8],
+    ) -> Vec<Op> {
+        let stream = fake.stream();
+        let buffer = param.before_call(&stream).unwrap();
+        let mut param = (param, buffer);
+        if param.1.is_some() {
+            let memory = unsafe { fake.memory(param.param_pointer()) };
+            memory.borrow_mut().copy_from_slice(written);
+        }
+        param.after_call(&stream).unwrap();
+        fake.take_ops()
+    }
+
+    #[test]
+    fn test_copies_by_variant() {
+        let fake = FakeStream::new();
+        let written = 7u32.to_ne_bytes();
+        let size = 4;
+
+        assert!(call(&fake, Param::InVal(1u32), &[]).is_empty());
+
+        let ops = call(&fake, Param::InRef(&1u32), &written);
+        assert!(matches!(ops[..], [Op::Alloc { .. }, Op::ToDevice { .. }]));
+
+        let mut out = 1u32;
+        let ops = call(&fake, Param::Out(&mut out), &written);
+        assert!(matches!(ops[..], [Op::Alloc { .. }, Op::ToHost { .. }]));
+        assert_eq!(out, 7);
+
+        let mut inout = [1u32, 2];
+        let written = [3u32.to_ne_bytes(), 4u32.to_ne_bytes()].concat();
+        let ops = call(&fake, Param::InMutSlice(&mut inout), &written);
+        assert!(matches!(
+            ops[..],
+            [
+                Op::Alloc { .. },
+                Op::ToDevice { size: 8, .. },
+                Op::ToHost { size: 8, .. }
+            ]
+        ));
+        assert_eq!(inout, [3, 4]);
+
+        let mut out = [0u32; 2];
+        let ops = call(&fake, Param::OutSlice(&mut out), &written);
+        assert_eq!(ops.len(), 2);
+        assert_eq!(out, [3, 4]);
+
+        let input = [1u32];
+        let param = (Param::InRefSlice(&input[..]), None);
+        assert_eq!(param.transfer_size(), (0, 0));
+        let stream = fake.stream();
+        let buffer = Param::InRefSlice(&input[..]).before_call(&stream);
+        let param = (Param::InRefSlice(&input[..]), buffer.unwrap());
+        assert_eq!(param.transfer_size(), (size, 0));
+    }
+
+    #[test]
+    fn test_sensitive_param_is_wiped() {
+        let fake = FakeStream::new();
+        let stream = fake.stream();
+        let secret = [0xabu8; 16];
+
+        let param = Param::InRefSlice(&secret[..]);
+        let buffer = param.before_call(&stream).unwrap();
+        let mut param = (param, buffer);
+        param.set_sensitive();
+        let memory = unsafe { fake.memory(param.param_pointer()) };
+        assert_eq!(*memory.borrow(), secret);
+
+        param.after_call(&stream).unwrap();
+        assert!(memory.borrow().iter().all(|&x| x == 0));
+        assert!(matches!(
+            fake.take_ops().last(),
+            Some(Op::Memset { value: 0, .. })
+        ));
+
+        // Already zeroed on the stream of the call.
+        drop(param);
+        assert!(fake.take_ops().is_empty());
+    }
+
+    #[test]
+    fn test_sensitive_device_data_drop() {
+        let fake = FakeStream::new();
+        let secret = [0xabu8; 16];
+        let data = DeviceData::upload(&secret, &fake.stream())
+            .unwrap()
+            .sensitive();
+        let address = match &*data.device_mem {
+            DeviceMemory::Fake(memory) => memory.address(),
+            DeviceMemory::Cuda(..) => unreachable!(),
+        };
+        let memory = unsafe { fake.memory((&data).param_pointer()) };
+        fake.take_ops();
+
+        // Zeroed on a stream of its own, which is the only one waited for,
+        // even though no workspace is active.
+        drop(data);
+        assert!(memory.borrow().iter().all(|&x| x == 0));
+        assert_eq!(
+            fake.take_ops(),
+            [Op::Memset { address, value: 0 }, Op::Synchronize]
+        );
+
+        let data = DeviceData::upload(&secret, &fake.stream()).unwrap();
+        let memory = unsafe { fake.memory((&data).param_pointer()) };
+        drop(data);
+        assert_eq!(*memory.borrow(), secret);
+    }
+
+    #[test]
+    fn test_swap_device_pointer() {
+        let fake = FakeStream::new();
+        let stream = fake.stream();
+        let mut a = DeviceData::upload(&[1u32, 2], &stream).unwrap();
+        let mut b = DeviceData::upload(&[3u32, 4], &stream).unwrap();
+        let a_pointer = (&a).param_pointer();
+
+        DeviceData::swap_device_pointer(&mut a, &mut b);
+        let bytes = |pointer| unsafe { fake.memory(pointer).borrow().clone() };
+        let words =
+            |x: u32, y: u32| [x.to_ne_bytes(), y.to_ne_bytes()].concat();
+        assert_eq!(bytes((&a).param_pointer()), words(3, 4));
+        assert_eq!(bytes((&b).param_pointer()), words(1, 2));
+        // The argument points into the `DeviceData`, so it sees the swap.
+        assert_eq!(bytes(a_pointer), words(3, 4));
+    }
+
+    #[test]
+    fn test_device_data_element_type() {
+        let fake = FakeStream::new();
+        let stream = fake.stream();
+        fn is_valid<T>(arg: PointerArg<'_, T>, stream: &CudaStream) -> bool {
+            match arg.into_param(stream) {
+                Ok(_) => true,
+                Err(error) => {
+                    assert_eq!(error, CudaError::InvalidValue);
+                    false
+                }
+            }
+        }
+
+        let uploaded = DeviceData::upload(&[1u32, 2, 3], &stream).unwrap();
+        assert!(is_valid(PointerArg::<u32>::from(&uploaded), &stream));
+        assert!(!is_valid(PointerArg::<i32>::from(&uploaded), &stream));
+        assert!(!is_valid(PointerArg::<[u32; 3]>::from(&uploaded), &stream));
+        assert!(is_valid(PointerArg::<Untyped>::from(&uploaded), &stream));
+
+        // Data without an element type only needs a fitting size.
+        let untyped = DeviceData {
+            size: 12,
+            element: None,
+            device_mem: TrackedBuffer::alloc(&stream, 12).unwrap(),
+        };
+        assert!(is_valid(PointerArg::<u32>::from(&untyped), &stream));
+        assert!(is_valid(PointerArg::<[u32; 3]>::from(&untyped), &stream));
+        assert!(!is_valid(PointerArg::<u64>::from(&untyped), &stream));
+    }
+
+    #[test]
+    fn test_null_pointer() {
+        let fake = FakeStream::new();
+        let mut param = NullPointer;
+        let pointer = param.param_pointer();
+        assert!(!pointer.is_null());
+        assert_eq!(pointer, NullPointer.param_pointer());
+        param.after_call(&fake.stream()).unwrap();
+        assert_eq!(param.transfer_size(), (0, 0));
+        assert!(fake.take_ops().is_empty());
+    }
 }
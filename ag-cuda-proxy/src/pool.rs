@@ -0,0 +1,356 @@
+use crate::module::{CudaWorkspace, LoadResult};
+
+use std::{
+    marker::PhantomData,
+    ops::Deref,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, ThreadId},
+};
+
+/// The number of workspaces a pool constructs at most, unless configured
+/// with [`WorkspacePool::set_size`].
+pub const DEFAULT_POOL_SIZE: usize = 4;
+
+/// A bounded pool of workspaces shared by all threads.
+///
+/// Workspaces are constructed on demand up to the pool size, without blocking
+/// the threads checking out the others. A thread checks out a workspace for
+/// the duration of a call, threads waiting for a free one are served in
+/// arrival order. A thread that already holds a workspace gets the same one
+/// again, so nested calls neither deadlock nor activate a second context.
+pub struct WorkspacePool<W = CudaWorkspace> {
+    init: fn() -> LoadResult<W>,
+    state: Mutex<PoolState<W>>,
+    returned: Condvar,
+}
+
+struct PoolState<W> {
+    size: usize,
+    slots: Vec<Slot<W>>,
+    /// The number of workspaces being constructed, which count towards the
+    /// size.
+    constructing: usize,
+    /// The ticket of the next arriving thread.
+    next_ticket: u64,
+    /// The ticket of the thread allowed to take the next free workspace.
+    serving: u64,
+}
+
+struct Slot<W> {
+    workspace: Arc<W>,
+    holder: Option<ThreadId>,
+    depth: usize,
+}
+
+impl<W> PoolState<W> {
+    fn position(&self, workspace: &Arc<W>) -> usize {
+        self.slots
+            .iter()
+            .position(|x| Arc::ptr_eq(&x.workspace, workspace))
+            .expect("The workspace is not in the pool")
+    }
+
+    /// Removes free workspaces while there are more than the size, returns
+    /// them to be dropped once the lock is released.
+    fn shrink(&mut self) -> Vec<Slot<W>> {
+        let mut removed = Vec::new();
+        while self.slots.len() + self.constructing > self.size {
+            match self.slots.iter().position(|x| x.holder.is_none()) {
+                Some(index) => removed.push(self.slots.swap_remove(index)),
+                None => break,
+            }
+        }
+        removed
+    }
+}
+
+impl<W> WorkspacePool<W> {
+    pub const fn new(init: fn() -> LoadResult<W>) -> Self {
+        Self {
+            init,
+            state: Mutex::new(PoolState {
+                size: DEFAULT_POOL_SIZE,
+                slots: Vec::new(),
+                constructing: 0,
+                next_ticket: 0,
+                serving: 0,
+            }),
+            returned: Condvar::new(),
+        }
+    }
+
+    /// Sets the number of workspaces the pool holds at most. Free workspaces
+    /// exceeding `size` are dropped, checked out ones once they are returned.
+    pub fn set_size(&self, size: usize) {
+        assert!(size > 0, "The pool size must be positive");
+        let mut state = self.state.lock().unwrap();
+        state.size = size;
+        let removed = state.shrink();
+        drop(state);
+        drop(removed);
+        self.returned.notify_all();
+    }
+
+    /// The number of workspaces constructed so far.
+    pub fn len(&self) -> usize { self.state.lock().unwrap().slots.len() }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Checks out a workspace, waiting until one is free. Fails if a new
+    /// workspace is needed and cannot be constructed, the next checkout tries
+    /// again.
+    pub fn checkout(&self) -> LoadResult<PooledWorkspace<'_, W>> {
+        let current = thread::current().id();
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(index) =
+            state.slots.iter().position(|x| x.holder == Some(current))
+        {
+            return Ok(self.take(&mut state, index, current));
+        }
+
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        loop {
+            if state.serving == ticket {
+                if let Some(index) =
+                    state.slots.iter().position(|x| x.holder.is_none())
+                {
+                    return Ok(self.take(&mut state, index, current));
+                }
+                if state.slots.len() + state.constructing < state.size {
+                    return self.construct(state, current);
+                }
+            }
+            state = self.returned.wait(state).unwrap();
+        }
+    }
+
+    /// Constructs a workspace without holding the lock, in the meantime the
+    /// next waiting thread may take a free workspace.
+    fn construct(
+        &self, mut state: MutexGuard<'_, PoolState<W>>, current: ThreadId,
+    ) -> LoadResult<PooledWorkspace<'_, W>> {
+        state.constructing += 1;
+        self.advance(&mut state);
+        drop(state);
+
+        let result = (self.init)();
+
+        let mut state = self.state.lock().unwrap();
+        state.constructing -= 1;
+        match result {
+            Ok(workspace) => {
+                let workspace = Arc::new(workspace);
+                state.slots.push(Slot {
+                    workspace: workspace.clone(),
+                    holder: Some(current),
+                    depth: 1,
+                });
+                Ok(PooledWorkspace::new(self, workspace))
+            }
+            Err(e) => {
+                // The reserved place is free again.
+                self.returned.notify_all();
+                Err(e)
+            }
+        }
+    }
+
+    fn take(
+        &self, state: &mut PoolState<W>, index: usize, current: ThreadId,
+    ) -> PooledWorkspace<'_, W> {
+        let slot = &mut state.slots[index];
+        if slot.holder.is_none() {
+            slot.holder = Some(current);
+            self.advance(state);
+        }
+
+        let slot = &mut state.slots[index];
+        slot.depth += 1;
+        PooledWorkspace::new(self, slot.workspace.clone())
+    }
+
+    /// Lets the next waiting thread in.
+    fn advance(&self, state: &mut PoolState<W>) {
+        state.serving += 1;
+        self.returned.notify_all();
+    }
+
+    fn give_back(&self, workspace: &Arc<W>) {
+        let mut state = self.state.lock().unwrap();
+        let index = state.position(workspace);
+        let slot = &mut state.slots[index];
+        slot.depth -= 1;
+        if slot.depth == 0 {
+            slot.holder = None;
+            let removed = state.shrink();
+            drop(state);
+            drop(removed);
+            self.returned.notify_all();
+        }
+    }
+}
+
+/// A workspace checked out of a [`WorkspacePool`], returned on drop.
+///
+/// It is not `Send`: a nested checkout on the same thread gets the same
+/// workspace, which is only right while the holder stays on the thread. An
+/// async task must not hold it across an `.await`, the `_async` functions of
+/// `auto_workspace` check out on a thread of their own instead.
+pub struct PooledWorkspace<'p, W = CudaWorkspace> {
+    pool: &'p WorkspacePool<W>,
+    workspace: Arc<W>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'p, W> PooledWorkspace<'p, W> {
+    fn new(pool: &'p WorkspacePool<W>, workspace: Arc<W>) -> Self {
+        Self {
+            pool,
+            workspace,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<'p, W> Deref for PooledWorkspace<'p, W> {
+    type Target = W;
+
+    fn deref(&self) -> &W { &self.workspace }
+}
+
+impl<'p, W> Drop for PooledWorkspace<'p, W> {
+    fn drop(&mut self) { self.pool.give_back(&self.workspace); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::LoadError;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    /// Stands in for a workspace, numbered in the order of construction.
+    #[derive(Debug, PartialEq, Eq)]
+    struct FakeWorkspace(usize);
+
+    /// Waits until `count` threads arrived at the pool.
+    fn wait_for_tickets<W>(pool: &WorkspacePool<W>, count: u64) {
+        while pool.state.lock().unwrap().next_ticket < count {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_failed_construction_releases_queue() {
+        static POOL: WorkspacePool =
+            WorkspacePool::new(|| Err(LoadError::Stub("no nvcc".to_owned())));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| thread::spawn(|| POOL.checkout().err()))
+            .collect();
+        for handle in threads {
+            let error = handle.join().unwrap();
+            assert_eq!(error, Some(LoadError::Stub("no nvcc".to_owned())));
+        }
+        assert!(POOL.is_empty());
+    }
+
+    #[test]
+    fn test_fifo_order() {
+        static POOL: WorkspacePool<FakeWorkspace> =
+            WorkspacePool::new(|| Ok(FakeWorkspace(0)));
+        POOL.set_size(1);
+        let order = Mutex::new(Vec::new());
+
+        let held = POOL.checkout().unwrap();
+        thread::scope(|scope| {
+            for i in 0..4 {
+                let order = &order;
+                scope.spawn(move || {
+                    let _workspace = POOL.checkout().unwrap();
+                    order.lock().unwrap().push(i);
+                });
+                wait_for_tickets(&POOL, i + 2);
+            }
+            drop(held);
+        });
+        assert_eq!(*order.lock().unwrap(), [0, 1, 2, 3]);
+        assert_eq!(POOL.len(), 1);
+    }
+
+    #[test]
+    fn test_reentrant_checkout() {
+        static CONSTRUCTED: AtomicUsize = AtomicUsize::new(0);
+        static POOL: WorkspacePool<FakeWorkspace> = WorkspacePool::new(|| {
+            // The pool is not locked while a workspace is constructed.
+            assert!(POOL.state.try_lock().is_ok());
+            Ok(FakeWorkspace(CONSTRUCTED.fetch_add(1, Ordering::SeqCst)))
+        });
+
+        let outer = POOL.checkout().unwrap();
+        let inner = POOL.checkout().unwrap();
+        assert!(Arc::ptr_eq(&outer.workspace, &inner.workspace));
+        drop(outer);
+
+        // Still held by the inner checkout, another thread gets a new one.
+        let other = thread::spawn(|| POOL.checkout().unwrap().0);
+        assert_eq!(other.join().unwrap(), 1);
+        drop(inner);
+        assert_eq!(POOL.checkout().unwrap().0, 0);
+        assert_eq!(CONSTRUCTED.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        static CONSTRUCTED: AtomicUsize = AtomicUsize::new(0);
+        static POOL: WorkspacePool<FakeWorkspace> = WorkspacePool::new(|| {
+            Ok(FakeWorkspace(CONSTRUCTED.fetch_add(1, Ordering::SeqCst)))
+        });
+        POOL.set_size(2);
+
+        // Each thread keeps its workspace until all are checked out.
+        let barrier = std::sync::Barrier::new(4);
+        thread::scope(|scope| {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            for _ in 0..3 {
+                let (sender, barrier) = (sender.clone(), &barrier);
+                scope.spawn(move || {
+                    let _workspace = POOL.checkout().unwrap();
+                    sender.send(()).unwrap();
+                    barrier.wait();
+                });
+            }
+            receiver.recv().unwrap();
+            receiver.recv().unwrap();
+            wait_for_tickets(&POOL, 3);
+            assert_eq!(POOL.len(), 2);
+            assert!(receiver.try_recv().is_err());
+
+            // The third thread waits until the pool may grow.
+            POOL.set_size(3);
+            receiver.recv().unwrap();
+            assert_eq!(POOL.len(), 3);
+            barrier.wait();
+        });
+        assert_eq!(CONSTRUCTED.load(Ordering::SeqCst), 3);
+
+        // Shrinking drops the free workspaces.
+        POOL.set_size(1);
+        assert_eq!(POOL.len(), 1);
+
+        // A workspace returned while the pool is too large is dropped.
+        let held = POOL.checkout().unwrap();
+        POOL.set_size(2);
+        let other = thread::spawn(|| POOL.checkout().unwrap().0);
+        assert_eq!(other.join().unwrap(), 3);
+        assert_eq!(POOL.len(), 2);
+        POOL.set_size(1);
+        assert_eq!(POOL.len(), 1);
+        drop(held);
+        assert_eq!(POOL.len(), 1);
+    }
+}
@@ -0,0 +1,250 @@
+//! Per-launch profiling records.
+//!
+//! Profiling is off unless a sink is installed with [`set_profile_sink`] or
+//! debug logging is enabled for the `ag_cuda_proxy::profile` target. Every
+//! launch then yields a [`LaunchRecord`], which is logged and passed to the
+//! sink once the launch is completed.
+
+use crate::{
+    kernel::KernelConfig,
+    stream::{CudaEvent, CudaStream},
+};
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+const LOG_TARGET: &str = "ag_cuda_proxy::profile";
+
+static SINK: RwLock<Option<Arc<dyn ProfileSink>>> = RwLock::new(None);
+
+/// The profile of one kernel launch, including copying its parameters.
+#[derive(Clone, Debug)]
+pub struct LaunchRecord {
+    pub kernel: String,
+    pub config: KernelConfig,
+    /// Bytes copied from the host to the device for the parameters.
+    pub bytes_to_device: usize,
+    /// Bytes copied from the device back to the host for the parameters.
+    pub bytes_to_host: usize,
+    /// Wall-clock time from creating the task to observing its completion.
+    pub host_time: Duration,
+    /// Device time from the first parameter copy to the last copy back,
+    /// `None` if the events could not be recorded.
+    pub device_time: Option<Duration>,
+}
+
+impl fmt::Display for LaunchRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} grid {} block {} shared {} B, {} B to device, {} B to host, \
+             host {:?}, device {:?}",
+            self.kernel,
+            self.config.global_work_size,
+            self.config.local_work_size,
+            self.config.shared_mem,
+            self.bytes_to_device,
+            self.bytes_to_host,
+            self.host_time,
+            self.device_time,
+        )
+    }
+}
+
+/// Receives the launch records.
+pub trait ProfileSink: Send + Sync {
+    fn record(&self, record: &LaunchRecord);
+}
+
+/// Installs the sink receiving the launch records of all threads, or removes
+/// it with `None`.
+pub fn set_profile_sink(sink: Option<Arc<dyn ProfileSink>>) {
+    *SINK.write().unwrap() = sink;
+}
+
+/// A sink collecting the launch records in memory.
+#[derive(Default)]
+pub struct Profiler(Mutex<Vec<LaunchRecord>>);
+
+impl Profiler {
+    pub fn records(&self) -> Vec<LaunchRecord> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) { self.0.lock().unwrap().clear() }
+
+    /// Aggregates the records by kernel name.
+    pub fn report(&self) -> ProfileReport {
+        let mut kernels = BTreeMap::<String, KernelSummary>::new();
+        for record in self.0.lock().unwrap().iter() {
+            let summary = kernels.entry(record.kernel.clone()).or_default();
+            summary.launches += 1;
+            summary.bytes_to_device += record.bytes_to_device;
+            summary.bytes_to_host += record.bytes_to_host;
+            summary.host_time += record.host_time;
+            summary.device_time += record.device_time.unwrap_or_default();
+        }
+        ProfileReport(kernels)
+    }
+}
+
+impl ProfileSink for Profiler {
+    fn record(&self, record: &LaunchRecord) {
+        self.0.lock().unwrap().push(record.clone());
+    }
+}
+
+/// The totals of the launches of one kernel.
+#[derive(Clone, Debug, Default)]
+pub struct KernelSummary {
+    pub launches: usize,
+    pub bytes_to_device: usize,
+    pub bytes_to_host: usize,
+    pub host_time: Duration,
+    pub device_time: Duration,
+}
+
+/// The launch totals by kernel name.
+#[derive(Clone, Debug, Default)]
+pub struct ProfileReport(pub BTreeMap<String, KernelSummary>);
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (kernel, summary) in &self.0 {
+            writeln!(
+                f,
+                "{}: {} launches, {} B to device, {} B to host, host {:?}, \
+                 device {:?}",
+                kernel,
+                summary.launches,
+                summary.bytes_to_device,
+                summary.bytes_to_host,
+                summary.host_time,
+                summary.device_time,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn enabled() -> bool {
+    SINK.read().unwrap().is_some()
+        || log::log_enabled!(target: LOG_TARGET, log::Level::Debug)
+}
+
+/// The profile of a launch in progress.
+pub(crate) struct TaskProfile {
+    started: Instant,
+    start: Option<CudaEvent>,
+    config: Option<KernelConfig>,
+    bytes_to_device: usize,
+}
+
+impl TaskProfile {
+    /// Starts profiling a task on `stream`, `None` if profiling is off.
+    pub(crate) fn begin(stream: &CudaStream) -> Option<Self> {
+        if !enabled() {
+            return None;
+        }
+        Some(Self {
+            started: Instant::now(),
+            start: stream.record().ok(),
+            config: None,
+            bytes_to_device: 0,
+        })
+    }
+
+    pub(crate) fn launched(
+        &mut self, config: KernelConfig, bytes_to_device: usize,
+    ) {
+        self.config = Some(config);
+        self.bytes_to_device = bytes_to_device;
+    }
+
+    /// Marks the end of the work of the task on `stream`, `None` if the
+    /// task was not launched.
+    pub(crate) fn end(
+        self, kernel: &str, bytes_to_host: usize, stream: &CudaStream,
+    ) -> Option<FinishedProfile> {
+        Some(FinishedProfile {
+            record: LaunchRecord {
+                kernel: kernel.to_string(),
+                config: self.config?,
+                bytes_to_device: self.bytes_to_device,
+                bytes_to_host,
+                host_time: Duration::ZERO,
+                device_time: None,
+            },
+            started: self.started,
+            start: self.start,
+            stop: stream.record().ok(),
+        })
+    }
+}
+
+/// The profile of a launch whose work is queued completely.
+pub(crate) struct FinishedProfile {
+    record: LaunchRecord,
+    started: Instant,
+    start: Option<CudaEvent>,
+    stop: Option<CudaEvent>,
+}
+
+impl FinishedProfile {
+    /// Waits for the work of the launch and emits its record.
+    pub(crate) fn emit(self) {
+        let mut record = self.record;
+        if let (Some(start), Some(stop)) = (&self.start, &self.stop) {
+            record.device_time = stop
+                .synchronize()
+                .and_then(|()| stop.elapsed_since(start))
+                .ok();
+        }
+        record.host_time = self.started.elapsed();
+
+        log::debug!(target: LOG_TARGET, "{}", record);
+        if let Some(sink) = SINK.read().unwrap().as_ref() {
+            sink.record(&record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(kernel: &str, bytes: usize) -> LaunchRecord {
+        LaunchRecord {
+            kernel: kernel.into(),
+            config: KernelConfig {
+                global_work_size: 4,
+                local_work_size: 32,
+                shared_mem: 0,
+            },
+            bytes_to_device: bytes,
+            bytes_to_host: 0,
+            host_time: Duration::from_millis(2),
+            device_time: Some(Duration::from_millis(1)),
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let profiler = Profiler::default();
+        profiler.record(&record("G1_multiexp", 10));
+        profiler.record(&record("G1_multiexp", 20));
+        profiler.record(&record("G1_radix_fft", 5));
+
+        let report = profiler.report();
+        let multiexp = &report.0["G1_multiexp"];
+        assert_eq!(multiexp.launches, 2);
+        assert_eq!(multiexp.bytes_to_device, 30);
+        assert_eq!(multiexp.device_time, Duration::from_millis(2));
+        assert_eq!(report.0["G1_radix_fft"].launches, 1);
+        assert!(report.to_string().starts_with("G1_multiexp: 2 launches"));
+    }
+}
@@ -0,0 +1,257 @@
+use std::{ffi::c_void, rc::Rc, time::Duration};
+
+use rustacuda::error::{CudaError, CudaResult};
+
+use crate::{
+    driver::{Event, Function, Stream},
+    kernel::KernelConfig,
+    memory::DeviceMemory,
+};
+
+/// The driver calls queued on a stream. Implemented by the driver stream
+/// and, in tests, by an in-memory fake, so that the marshaling of the
+/// parameters can be tested without a GPU.
+pub(crate) trait StreamDriver {
+    fn alloc(&self, size: usize) -> CudaResult<DeviceMemory>;
+
+    /// Copies `src` to `dst` starting at `offset`. `src` must stay alive
+    /// until the copy is done.
+    unsafe fn copy_to_device(
+        &self, dst: &mut DeviceMemory, offset: usize, src: &[u8],
+    ) -> CudaResult<()>;
+
+    /// Copies `src` to `dst`, which must stay alive until the copy is done.
+    unsafe fn copy_to_host(
+        &self, dst: &mut [u8], src: &DeviceMemory,
+    ) -> CudaResult<()>;
+
+    /// Sets every byte of `dst` to `value`. `dst` must stay alive until the
+    /// memset is done.
+    unsafe fn memset(
+        &self, dst: &mut DeviceMemory, value: u8,
+    ) -> CudaResult<()>;
+
+    /// Launches `function`, `args` point to the values of the parameters.
+    unsafe fn launch(
+        &self, function: &Function, config: &KernelConfig, args: &[*mut c_void],
+    ) -> CudaResult<()>;
+
+    /// Records a new event capturing the work queued so far.
+    fn record(&self) -> CudaResult<CudaEvent>;
+
+    fn wait(&self, event: &CudaEvent) -> CudaResult<()>;
+
+    /// Calls `callback` with the status of the device once the work queued
+    /// so far is completed. The callback runs on a driver thread and must
+    /// not call the driver.
+    fn add_callback(&self, callback: StreamCallback) -> CudaResult<()>;
+
+    fn synchronize(&self) -> CudaResult<()>;
+}
+
+pub(crate) type StreamCallback = Box<dyn FnOnce(CudaResult<()>) + Send>;
+
+impl StreamDriver for Stream {
+    fn alloc(&self, size: usize) -> CudaResult<DeviceMemory> {
+        DeviceMemory::uninitialized(size)
+    }
+
+    unsafe fn copy_to_device(
+        &self, dst: &mut DeviceMemory, offset: usize, src: &[u8],
+    ) -> CudaResult<()> {
+        match dst {
+            DeviceMemory::Cuda(buffer, _) => {
+                if offset + src.len() > buffer.len() {
+                    return Err(CudaError::InvalidValue);
+                }
+                Stream::copy_to_device(
+                    self,
+                    buffer.address() + offset as u64,
+                    src,
+                )
+            }
+            #[cfg(test)]
+            DeviceMemory::Fake(_) => Err(CudaError::InvalidValue),
+        }
+    }
+
+    unsafe fn copy_to_host(
+        &self, dst: &mut [u8], src: &DeviceMemory,
+    ) -> CudaResult<()> {
+        match src {
+            DeviceMemory::Cuda(buffer, _) => {
+                if dst.len() != buffer.len() {
+                    return Err(CudaError::InvalidValue);
+                }
+                Stream::copy_to_host(self, dst, buffer.address())
+            }
+            #[cfg(test)]
+            DeviceMemory::Fake(_) => Err(CudaError::InvalidValue),
+        }
+    }
+
+    unsafe fn memset(
+        &self, dst: &mut DeviceMemory, value: u8,
+    ) -> CudaResult<()> {
+        match dst {
+            DeviceMemory::Cuda(buffer, _) => {
+                Stream::memset(self, buffer.address(), value, buffer.len())
+            }
+            #[cfg(test)]
+            DeviceMemory::Fake(_) => Err(CudaError::InvalidValue),
+        }
+    }
+
+    unsafe fn launch(
+        &self, function: &Function, config: &KernelConfig, args: &[*mut c_void],
+    ) -> CudaResult<()> {
+        Stream::launch(self, function, config, args)
+    }
+
+    fn record(&self) -> CudaResult<CudaEvent> {
+        Ok(CudaEvent(DeviceEvent::Cuda(Event::record(self)?)))
+    }
+
+    fn wait(&self, event: &CudaEvent) -> CudaResult<()> {
+        match &event.0 {
+            DeviceEvent::Cuda(event) => Stream::wait(self, event),
+            #[cfg(test)]
+            DeviceEvent::Fake(_) => Err(CudaError::InvalidValue),
+        }
+    }
+
+    fn add_callback(&self, callback: StreamCallback) -> CudaResult<()> {
+        Stream::add_callback(self, callback)
+    }
+
+    fn synchronize(&self) -> CudaResult<()> { Stream::synchronize(self) }
+}
+
+/// A shared handle of a CUDA stream. Cloning the handle does not create a new
+/// stream, so several kernels can be queued on the same stream and work on
+/// different streams can overlap.
+///
+/// The handle is reference counted without atomics, so it is not `Send`, and
+/// neither are the kernels and tasks using it: the stream belongs to the
+/// context pushed on the thread that created it.
+#[derive(Clone)]
+pub struct CudaStream(Rc<dyn StreamDriver>);
+
+impl CudaStream {
+    pub(crate) fn new() -> CudaResult<Self> {
+        Ok(Self(Rc::new(Stream::new()?)))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_driver(driver: Rc<dyn StreamDriver>) -> Self {
+        Self(driver)
+    }
+
+    /// Records an event capturing all the work queued on the stream so far.
+    pub fn record(&self) -> CudaResult<CudaEvent> { self.0.record() }
+
+    /// Makes all the work queued on the stream from now on wait until the
+    /// work captured by `event` is completed. Does not block the host.
+    pub fn wait(&self, event: &CudaEvent) -> CudaResult<()> {
+        self.0.wait(event)
+    }
+
+    /// Blocks until all the work queued on the stream is completed.
+    pub fn synchronize(&self) -> CudaResult<()> { self.0.synchronize() }
+
+    pub(crate) fn driver(&self) -> &dyn StreamDriver { &*self.0 }
+}
+
+/// A point in a stream, recorded by [`CudaStream::record`]. Any number of
+/// streams can wait for the same event.
+pub struct CudaEvent(pub(crate) DeviceEvent);
+
+/// A driver event, faked in tests.
+pub(crate) enum DeviceEvent {
+    Cuda(Event),
+    #[cfg(test)]
+    Fake(crate::fake::FakeEvent),
+}
+
+impl CudaEvent {
+    /// Whether the work captured by the event is completed.
+    pub fn is_complete(&self) -> CudaResult<bool> {
+        match &self.0 {
+            DeviceEvent::Cuda(event) => event.is_complete(),
+            #[cfg(test)]
+            DeviceEvent::Fake(event) => Ok(event.is_complete()),
+        }
+    }
+
+    /// Blocks until the work captured by the event is completed.
+    pub fn synchronize(&self) -> CudaResult<()> {
+        match &self.0 {
+            DeviceEvent::Cuda(event) => event.synchronize(),
+            #[cfg(test)]
+            DeviceEvent::Fake(event) => event.synchronize(),
+        }
+    }
+
+    /// The device time between `start` and this event, both of which must be
+    /// completed.
+    pub fn elapsed_since(&self, start: &CudaEvent) -> CudaResult<Duration> {
+        match (&self.0, &start.0) {
+            (DeviceEvent::Cuda(event), DeviceEvent::Cuda(start)) => {
+                let millis = event.elapsed_since(start)?;
+                Ok(Duration::from_secs_f32(millis / 1000.0))
+            }
+            #[cfg(test)]
+            (DeviceEvent::Fake(event), DeviceEvent::Fake(start)) => {
+                event.elapsed_since(start)
+            }
+            #[cfg(test)]
+            _ => Err(CudaError::InvalidValue),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{FakeStream, Op};
+
+    #[test]
+    fn test_events() {
+        let fake = FakeStream::new();
+        let stream = fake.stream();
+
+        let start = stream.record().unwrap();
+        let stop = stream.record().unwrap();
+        assert!(!stop.is_complete().unwrap());
+        assert_eq!(stop.elapsed_since(&start), Err(CudaError::NotReady));
+
+        stop.synchronize().unwrap();
+        assert!(start.is_complete().unwrap());
+        assert!(stop.is_complete().unwrap());
+        assert_eq!(stop.elapsed_since(&start), Ok(Duration::from_millis(1)));
+        assert_eq!(
+            fake.take_ops(),
+            [
+                Op::Record { event: 1 },
+                Op::Record { event: 2 },
+                Op::Synchronize,
+            ]
+        );
+
+        // Events recorded after a synchronization are pending again.
+        assert!(!stream.record().unwrap().is_complete().unwrap());
+    }
+
+    #[test]
+    fn test_wait_on_other_stream() {
+        let (first, second, third) =
+            (FakeStream::new(), FakeStream::new(), FakeStream::new());
+
+        let event = first.stream().record().unwrap();
+        second.stream().wait(&event).unwrap();
+        third.stream().wait(&event).unwrap();
+        assert_eq!(first.take_ops(), [Op::Record { event: 1 }]);
+        assert_eq!(second.take_ops(), [Op::Wait { event: 1 }]);
+        assert_eq!(third.take_ops(), [Op::Wait { event: 1 }]);
+    }
+}
@@ -1,13 +1,101 @@
 extern crate proc_macro;
 
-use proc_macro::TokenStream;
+use proc_macro::{Span, TokenStream};
 use quote::quote;
-use syn::{parse_macro_input, ExprClosure, FnArg, Ident, ItemFn, PatType};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, parse_quote, Data, DeriveInput, ExprClosure, FnArg,
+    GenericParam, Ident, ItemFn, Pat, PatIdent, PatType, Path, ReturnType,
+    Token, Visibility,
+};
 
+/// The arguments of `auto_workspace`: `async`, `global = <path>` and
+/// `pool = <path>`, separated by commas.
+struct AutoWorkspaceAttr {
+    asynchronous: bool,
+    global: Path,
+    pool: Path,
+}
+
+impl Parse for AutoWorkspaceAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut attr = AutoWorkspaceAttr {
+            asynchronous: false,
+            global: parse_quote!(GLOBAL),
+            pool: parse_quote!(POOL),
+        };
+        while !input.is_empty() {
+            if input.parse::<Option<Token![async]>>()?.is_some() {
+                attr.asynchronous = true;
+            } else {
+                let key: Ident = input.parse()?;
+                input.parse::<Token![=]>()?;
+                let value: Path = input.parse()?;
+                match key.to_string().as_str() {
+                    "global" => attr.global = value,
+                    "pool" => attr.pool = value,
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            key,
+                            "Expected `async`, `global = ..` or `pool = ..`",
+                        ))
+                    }
+                }
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(attr)
+    }
+}
+
+/// Generates wrappers of a function taking an `&ActiveWorkspace` as its first
+/// parameter: `<name>_st` activates the global workspace, `<name>_mt` one
+/// checked out of the workspace pool and `<name>_with` the `&CudaWorkspace`
+/// passed as its first parameter.
+///
+/// The function must return a `Result` whose error implements
+/// `From<CudaError>` and, for the workspace construction of the wrappers,
+/// `From<LoadError>`, e.g. `LaunchResult`. Its generic parameters and where
+/// clause are kept by the wrappers.
+///
+/// The statics are `GLOBAL` and `POOL` in the scope of the function, as
+/// declared by [`construct_workspace!`], unless named with
+/// `#[auto_workspace(global = path::TO_GLOBAL, pool = path::TO_POOL)]`.
+///
+/// The wrappers take the arguments by the names of simple parameter patterns,
+/// e.g. `x` for `mut x: u32`, and generate names for the other patterns.
+/// Their own variables do not clash with the names of the parameters.
+///
+/// With `#[auto_workspace(async)]` also the `async unsafe fn`s
+/// `<name>_st_async`, `<name>_mt_async` and `<name>_with_async` are
+/// generated. They run the blocking wrapper on a thread of a bounded pool
+/// with a [`BlockingTask`](../ag_cuda_proxy/struct.BlockingTask.html), so the
+/// workspace is activated and released on that thread and awaiting the call
+/// does not block the executor. Their futures are `Send` if the arguments
+/// and the result are. They are `unsafe` as the call borrows the arguments:
+/// their futures must not be leaked, only the drop of an unfinished future
+/// waits for the call.
 #[proc_macro_attribute]
-pub fn auto_workspace(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn auto_workspace(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let AutoWorkspaceAttr {
+        asynchronous,
+        global,
+        pool,
+    } = parse_macro_input!(attr as AutoWorkspaceAttr);
     let input_fn = parse_macro_input!(item as ItemFn);
 
+    if input_fn.sig.asyncness.is_some() {
+        return syn::Error::new_spanned(
+            &input_fn.sig,
+            "Function must not be async, use #[auto_workspace(async)] to \
+             generate async variants",
+        )
+        .to_compile_error()
+        .into();
+    }
+
     // 确保函数至少有一个参数
     if input_fn.sig.inputs.len() < 1 {
         return syn::Error::new_spanned(
@@ -18,61 +106,298 @@ pub fn auto_workspace(_attr: TokenStream, item: TokenStream) -> TokenStream {
         .into();
     }
 
+    if let ReturnType::Default = input_fn.sig.output {
+        return syn::Error::new_spanned(
+            &input_fn.sig,
+            "Function must return a Result whose error implements \
+             From<CudaError> and From<LoadError>",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let vis = &input_fn.vis;
     let fn_name = &input_fn.sig.ident;
-    let st_fn_name = Ident::new(&format!("{}_st", fn_name), fn_name.span());
-    let mt_fn_name = Ident::new(&format!("{}_mt", fn_name), fn_name.span());
+    let wrapper_name = |suffix: &str| {
+        Ident::new(&format!("{}_{}", fn_name, suffix), fn_name.span())
+    };
+    let st_fn_name = wrapper_name("st");
+    let mt_fn_name = wrapper_name("mt");
+    let with_fn_name = wrapper_name("with");
 
-    let fn_args: Vec<_> = input_fn.sig.inputs.iter().skip(1).collect(); // 跳过第一个参数
-    let fn_args_names: Vec<_> = input_fn
+    let (impl_generics, _, where_clause) =
+        input_fn.sig.generics.split_for_impl();
+    // Lifetimes are left to inference, they may be late bound.
+    let turbofish_args: Vec<_> = input_fn
+        .sig
+        .generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(x) => Some(x.ident.clone()),
+            GenericParam::Const(x) => Some(x.ident.clone()),
+            GenericParam::Lifetime(_) => None,
+        })
+        .collect();
+    let callee = if turbofish_args.is_empty() {
+        quote! { #fn_name }
+    } else {
+        quote! { #fn_name::<#(#turbofish_args),*> }
+    };
+
+    // The variables of the wrappers are hygienic, so that they cannot clash
+    // with the parameters.
+    let workspace = Ident::new("workspace", Span::mixed_site().into());
+    let pooled = Ident::new("pooled", Span::mixed_site().into());
+
+    // 跳过第一个参数
+    let (fn_args, fn_args_names): (Vec<_>, Vec<_>) = input_fn
         .sig
         .inputs
         .iter()
         .skip(1)
-        .map(|arg| match arg {
-            FnArg::Typed(PatType { pat, .. }) => quote! { #pat },
-            _ => quote! {},
+        .enumerate()
+        .filter_map(|(i, arg)| match arg {
+            FnArg::Typed(PatType { pat, ty, .. }) => {
+                let name = match &**pat {
+                    Pat::Ident(PatIdent {
+                        ident,
+                        subpat: None,
+                        ..
+                    }) => ident.clone(),
+                    _ => Ident::new(
+                        &format!("arg{}", i),
+                        Span::mixed_site().into(),
+                    ),
+                };
+                Some((quote! { #name: #ty }, name))
+            }
+            FnArg::Receiver(_) => None,
         })
-        .collect();
+        .unzip();
     let fn_return_type = &input_fn.sig.output;
 
+    let async_fns = if asynchronous {
+        let safety_doc = format!(
+            " Runs `{}` on a thread of the blocking pool.\n\n\
+             # Safety\n\n\
+             The future must be awaited or dropped, it must not be leaked, \
+             e.g. with `std::mem::forget` or in an `Rc` cycle: the call \
+             borrows the arguments, which only the drop of an unfinished \
+             future waits for.",
+            fn_name
+        );
+        let async_fn = |suffix: &str, wrapper: &Ident, with_workspace: bool| {
+            let async_fn_name = wrapper_name(suffix);
+            let callee = if turbofish_args.is_empty() {
+                quote! { #wrapper }
+            } else {
+                quote! { #wrapper::<#(#turbofish_args),*> }
+            };
+            let (workspace, workspace_arg) = if with_workspace {
+                (
+                    quote! { #workspace: &::ag_cuda_proxy::CudaWorkspace, },
+                    quote! { #workspace, },
+                )
+            } else {
+                (quote! {}, quote! {})
+            };
+            quote! {
+                #[doc = #safety_doc]
+                #vis async unsafe fn #async_fn_name #impl_generics(
+                    #workspace #(#fn_args),*
+                ) #fn_return_type
+                #where_clause
+                {
+                    // The task is awaited right away, the caller guarantees
+                    // that the future of this function is not leaked.
+                    unsafe {
+                        ::ag_cuda_proxy::BlockingTask::new(move || {
+                            #callee(#workspace_arg #(#fn_args_names),*)
+                        })
+                    }
+                    .await
+                }
+            }
+        };
+        let st_async = async_fn("st_async", &st_fn_name, false);
+        let mt_async = async_fn("mt_async", &mt_fn_name, false);
+        let with_async = async_fn("with_async", &with_fn_name, true);
+        quote! { #st_async #mt_async #with_async }
+    } else {
+        quote! {}
+    };
+
     let output_fn = quote! {
         #input_fn
 
-        pub fn #mt_fn_name(#(#fn_args),*) #fn_return_type {
-            LOCAL.with(|x| #fn_return_type {
-                let workspace = x.activate()?;
-                #fn_name(&workspace, #(#fn_args_names),*)
-            })
+        #vis fn #mt_fn_name #impl_generics(#(#fn_args),*) #fn_return_type
+        #where_clause
+        {
+            let #pooled = #pool.checkout()?;
+            let #workspace = #pooled.activate()?;
+            #callee(&#workspace, #(#fn_args_names),*)
+        }
+
+        #vis fn #st_fn_name #impl_generics(#(#fn_args),*) #fn_return_type
+        #where_clause
+        {
+            let #workspace = #global.activate()?;
+            #callee(&#workspace, #(#fn_args_names),*)
         }
 
-        pub fn #st_fn_name(#(#fn_args),*) #fn_return_type {
-            let workspace = GLOBAL.activate()?;
-            #fn_name(&workspace, #(#fn_args_names),*)
+        #vis fn #with_fn_name #impl_generics(
+            #workspace: &::ag_cuda_proxy::CudaWorkspace, #(#fn_args),*
+        ) #fn_return_type
+        #where_clause
+        {
+            let #workspace = #workspace.activate()?;
+            #callee(&#workspace, #(#fn_args_names),*)
         }
+
+        #async_fns
     };
 
     output_fn.into()
 }
 
+/// The arguments of `construct_workspace`: the visibility of the statics
+/// followed by the closure constructing a workspace.
+struct ConstructWorkspaceInput {
+    vis: Visibility,
+    closure: ExprClosure,
+}
+
+impl Parse for ConstructWorkspaceInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        Ok(ConstructWorkspaceInput {
+            vis: input.parse()?,
+            closure: input.parse()?,
+        })
+    }
+}
+
+/// Declares the `GLOBAL` workspace and the workspace `POOL` used by
+/// `auto_workspace`, constructed on first use by the given closure.
+///
+/// The closure returns a `CudaWorkspace`, a `CudaResult<CudaWorkspace>` or a
+/// `LoadResult<CudaWorkspace>`. A failed construction is reported as a
+/// `LoadError` by the wrappers and by `try_init_global_workspace` and
+/// `try_init_local_workspace`, and retried on the next call.
+///
+/// The statics are private unless the closure is preceded by a visibility,
+/// e.g. `construct_workspace!(pub(crate) || ...)` to use them in
+/// `auto_workspace` functions of other modules.
 #[proc_macro]
 pub fn construct_workspace(item: TokenStream) -> TokenStream {
-    let closure = parse_macro_input!(item as ExprClosure);
+    let ConstructWorkspaceInput { vis, closure } =
+        parse_macro_input!(item as ConstructWorkspaceInput);
 
     let output = quote! {
-        static GLOBAL: once_cell::sync::Lazy<CudaWorkspace> = once_cell::sync::Lazy::new(#closure);
+        #vis static GLOBAL: ::ag_cuda_proxy::LazyWorkspace = ::ag_cuda_proxy::LazyWorkspace::new(|| {
+            ::ag_cuda_proxy::IntoWorkspaceResult::into_workspace_result((#closure)())
+        });
 
-        std::thread_local! {
-            static LOCAL: once_cell::unsync::Lazy<CudaWorkspace> = once_cell::unsync::Lazy::new(#closure);
+        #vis static POOL: ::ag_cuda_proxy::WorkspacePool = ::ag_cuda_proxy::WorkspacePool::new(|| {
+            ::ag_cuda_proxy::IntoWorkspaceResult::into_workspace_result((#closure)())
+        });
+
+        pub fn try_init_global_workspace() -> ::ag_cuda_proxy::LoadResult<()> {
+            GLOBAL.get().map(|_| ())
+        }
+
+        /// Makes sure the workspace pool holds at least one workspace.
+        pub fn try_init_local_workspace() -> ::ag_cuda_proxy::LoadResult<()> {
+            POOL.checkout().map(|_| ())
         }
 
         pub fn init_global_workspace() {
-            let _x = &*GLOBAL;
+            try_init_global_workspace().expect("Cannot initialize the global workspace");
         }
 
         pub fn init_local_workspace() {
-            let _y = LOCAL.with(|x| { once_cell::unsync::Lazy::force(x); });
+            try_init_local_workspace().expect("Cannot initialize the local workspace");
+        }
+
+        /// Sets the number of workspaces shared by the `_mt` functions.
+        pub fn set_workspace_pool_size(size: usize) {
+            POOL.set_size(size);
         }
     };
 
     output.into()
 }
+
+/// Implements `KernelArg` for a plain old data struct.
+///
+/// The struct must be `#[repr(C)]` or `#[repr(transparent)]`, must not be
+/// generic, and every field must itself be a `KernelArg`. A compile-time
+/// assertion rejects structs whose size differs from the sum of their fields,
+/// as the padding bytes would be copied to the device uninitialized, and the
+/// layout assertions of the fields are evaluated along with it.
+///
+/// The generated code names the trait through its re-export in
+/// `ag_cuda_proxy`, which must be a dependency of the deriving crate.
+#[proc_macro_derive(KernelArg)]
+pub fn derive_kernel_arg(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    let name = &input.ident;
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return syn::Error::new_spanned(
+                &input,
+                "KernelArg can only be derived for structs",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    if !input.generics.params.is_empty() {
+        return syn::Error::new_spanned(
+            &input.generics,
+            "KernelArg cannot be derived for generic structs",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let mut has_stable_repr = false;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("repr")) {
+        let _ = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("C") || meta.path.is_ident("transparent") {
+                has_stable_repr = true;
+            }
+            Ok(())
+        });
+    }
+    if !has_stable_repr {
+        return syn::Error::new_spanned(
+            name,
+            "KernelArg requires #[repr(C)] or #[repr(transparent)]",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let field_types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+
+    let kernel_arg = quote!(::ag_cuda_proxy::KernelArg);
+    let output = quote! {
+        unsafe impl #kernel_arg for #name {}
+
+        const _: () = {
+            #(let () = <#field_types as #kernel_arg>::ASSERT_LAYOUT;)*
+        };
+
+        const _: () = assert!(
+            ::std::mem::size_of::<#name>()
+                == 0 #(+ ::std::mem::size_of::<#field_types>())*,
+            "KernelArg structs must not contain padding"
+        );
+    };
+
+    output.into()
+}
@@ -0,0 +1,15 @@
+#[path = "src/expressions.rs"]
+mod expressions;
+
+fn main() {
+    use ag_build::{generate_with_policy, BuildPolicy, SourceBuilder};
+
+    let source_builder = SourceBuilder::new()
+        .add_elementwise::<ark_bn254::Fr>(expressions::shift_scale())
+        .add_elementwise::<ark_bn254::Fr>(expressions::mul_sub());
+
+    // Builds without the CUDA toolkit, the tests then fail to load the
+    // workspace.
+    let policy = BuildPolicy::from_env().unwrap_or(BuildPolicy::Stub);
+    generate_with_policy(&source_builder, policy);
+}
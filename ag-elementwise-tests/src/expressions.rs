@@ -0,0 +1,20 @@
+//! The expressions of the kernels, shared by the build script generating
+//! them and the tests evaluating them on the CPU.
+
+use ag_build::{ElementwiseKernel, Expr};
+
+/// `(x + beta) * gamma`.
+pub fn shift_scale() -> ElementwiseKernel {
+    let (x, beta, gamma) = (
+        Expr::vector("x"),
+        Expr::scalar("beta"),
+        Expr::scalar("gamma"),
+    );
+    ElementwiseKernel::new("shift_scale", (x + beta) * gamma)
+}
+
+/// `a * b - 2 * c^2 + a`.
+pub fn mul_sub() -> ElementwiseKernel {
+    let (a, b, c) = (Expr::vector("a"), Expr::vector("b"), Expr::vector("c"));
+    ElementwiseKernel::new("mul_sub", a.clone() * b - c.square().double() - -a)
+}
@@ -0,0 +1,118 @@
+//! Runs the elementwise kernels generated by `ag-build` on the GPU and
+//! checks them against the CPU evaluation of their expressions. The kernels
+//! are test fixtures, so they live in this unpublished crate instead of the
+//! fatbin of a library.
+
+pub mod kernels {
+    //! Typed launchers for the kernels in the embedded fatbin, generated by
+    //! `ag-build` from the kernel signatures.
+    include!(env!("_EC_GPU_CUDA_KERNEL_LAUNCHERS"));
+}
+
+use crate::kernels::{FieldMulSubArgs, FieldShiftScaleArgs, KernelLaunchers};
+
+use ag_cuda_proxy::{
+    ActiveWorkspace, CudaError, CudaWorkspace, KernelConfig, LaunchResult,
+    PointerArg, ValueArg,
+};
+use ag_cuda_workspace_macro::{auto_workspace, construct_workspace};
+use ark_bn254::Fr;
+use ark_ff::Zero;
+
+const FATBIN: &[u8] = include_bytes!(env!("_EC_GPU_CUDA_KERNEL_FATBIN"));
+const MANIFEST: &str = include_str!(env!("_EC_GPU_CUDA_KERNEL_MANIFEST"));
+
+construct_workspace!(|| CudaWorkspace::from_bytes_with_manifest(
+    FATBIN, MANIFEST
+));
+
+const LOCAL_WORK_SIZE: usize = 256;
+
+/// One thread for each of the `n` elements.
+fn config(n: usize) -> KernelConfig {
+    KernelConfig {
+        global_work_size: n.div_ceil(LOCAL_WORK_SIZE),
+        local_work_size: LOCAL_WORK_SIZE,
+        shared_mem: 0,
+    }
+}
+
+/// Computes `(x + beta) * gamma` for every element `x`.
+#[auto_workspace]
+pub fn shift_scale(
+    workspace: &ActiveWorkspace, x: &[Fr], beta: Fr, gamma: Fr,
+) -> LaunchResult<Vec<Fr>> {
+    let mut result = vec![Fr::zero(); x.len()];
+    let args = FieldShiftScaleArgs {
+        result: PointerArg::out_slice(&mut result),
+        x: PointerArg::in_slice(x),
+        beta: ValueArg::new(beta),
+        gamma: ValueArg::new(gamma),
+        n: x.len() as u32,
+    };
+    workspace
+        .field_shift_scale::<Fr>(config(x.len()), args)?
+        .complete()?;
+    Ok(result)
+}
+
+/// Computes `a * b - 2 * c^2 + a` for every index of the vectors. Fails with
+/// `InvalidValue` if they differ in length.
+#[auto_workspace]
+pub fn mul_sub(
+    workspace: &ActiveWorkspace, a: &[Fr], b: &[Fr], c: &[Fr],
+) -> LaunchResult<Vec<Fr>> {
+    let n = a.len();
+    if b.len() != n || c.len() != n {
+        return Err(CudaError::InvalidValue.into());
+    }
+    let mut result = vec![Fr::zero(); n];
+    let args = FieldMulSubArgs {
+        result: PointerArg::out_slice(&mut result),
+        a: PointerArg::in_slice(a),
+        b: PointerArg::in_slice(b),
+        c: PointerArg::in_slice(c),
+        n: n as u32,
+    };
+    workspace.field_mul_sub::<Fr>(config(n), args)?.complete()?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod expressions;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ag_cuda_proxy::LaunchError;
+    use ark_std::{rand::thread_rng, UniformRand};
+
+    fn random(n: usize) -> Vec<Fr> {
+        let mut rng = thread_rng();
+        (0..n).map(|_| Fr::rand(&mut rng)).collect()
+    }
+
+    #[test]
+    fn test_elementwise() {
+        // Also lengths not filling the last block.
+        for n in [1, 255, 1 << 10, 1000] {
+            let (a, b, c) = (random(n), random(n), random(n));
+            let scalars = random(2);
+
+            let cpu_output =
+                expressions::shift_scale().evaluate(n, &[&a], &scalars);
+            let gpu_output = shift_scale_mt(&a, scalars[0], scalars[1]);
+            assert_eq!(gpu_output.unwrap(), cpu_output);
+
+            let cpu_output =
+                expressions::mul_sub().evaluate(n, &[&a, &b, &c], &[]);
+            assert_eq!(mul_sub_st(&a, &b, &c).unwrap(), cpu_output);
+        }
+
+        let (a, b) = (random(4), random(3));
+        assert_eq!(
+            mul_sub_st(&a, &b, &a),
+            Err(LaunchError::Cuda(CudaError::InvalidValue))
+        );
+    }
+}
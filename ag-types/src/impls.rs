@@ -1,8 +1,75 @@
 use super::*;
 
 use ark_ec::{
-    models::short_weierstrass::Affine, short_weierstrass::SWCurveConfig,
+    models::short_weierstrass::{Affine, Projective},
+    short_weierstrass::SWCurveConfig,
 };
+use ark_ff::{FpConfig, QuadExtConfig, QuadExtField};
+use std::mem::{align_of, size_of};
+
+macro_rules! impl_kernel_arg {
+    ($($t:ty),*) => {
+        $(unsafe impl KernelArg for $t {})*
+    };
+}
+
+// Not `bool`: a kernel may write any byte to it, pass flags as integers.
+impl_kernel_arg!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+unsafe impl<T: KernelArg, const N: usize> KernelArg for [T; N] {
+    const ASSERT_LAYOUT: () = T::ASSERT_LAYOUT;
+}
+
+/// Asserts that `T` is laid out like `n` elements of `E`, the GPU struct of
+/// an arkworks type being an array of its limbs or coordinates.
+const fn assert_layout_of<T, E>(n: usize, what: &str) {
+    if size_of::<T>() != n * size_of::<E>()
+        || align_of::<T>() != align_of::<E>()
+    {
+        panic!("{}", what);
+    }
+}
+
+// `BigInt<N>` is a newtype around `[u64; N]`, which is `FIELD_repr`.
+unsafe impl<const N: usize> KernelArg for BigInt<N> {
+    const ASSERT_LAYOUT: () =
+        assert_layout_of::<Self, u64>(N, "BigInt<N> is not FIELD_repr");
+}
+
+// `Fp` wraps a `BigInt<N>` in Montgomery form next to a zero-sized marker,
+// which is `FIELD`.
+unsafe impl<P: FpConfig<N>, const N: usize> KernelArg for ark_ff::Fp<P, N> {
+    const ASSERT_LAYOUT: () =
+        assert_layout_of::<Self, u64>(N, "Fp<P, N> is not FIELD");
+}
+
+// Two coefficients of the same type, which is `FIELD2`.
+unsafe impl<P> KernelArg for QuadExtField<P>
+where
+    P: QuadExtConfig,
+    P::BaseField: KernelArg,
+{
+    const ASSERT_LAYOUT: () = {
+        let () = P::BaseField::ASSERT_LAYOUT;
+        assert_layout_of::<Self, P::BaseField>(2, "QuadExtField is not FIELD2")
+    };
+}
+
+// Three coordinates of the same type, which is `POINT_jacobian`. The affine
+// type carries an `infinity` flag and is passed through `GpuRepr` instead.
+unsafe impl<P> KernelArg for Projective<P>
+where
+    P: SWCurveConfig,
+    P::BaseField: KernelArg,
+{
+    const ASSERT_LAYOUT: () = {
+        let () = P::BaseField::ASSERT_LAYOUT;
+        assert_layout_of::<Self, P::BaseField>(
+            3,
+            "Projective is not POINT_jacobian",
+        )
+    };
+}
 
 impl<T: MontConfig<N>, const N: usize> PrimeFieldRepr
     for ark_ff::Fp<MontBackend<T, N>, N>
@@ -45,7 +112,9 @@ where P::Fp: GpuField
     fn sub_field_name() -> Option<String> { Some(<P::Fp as GpuName>::name()) }
 }
 
-impl<P: SWCurveConfig> GpuRepr for Affine<P> {
+impl<P: SWCurveConfig> GpuRepr for Affine<P>
+where P::BaseField: KernelArg
+{
     type Repr = [P::BaseField; 2];
 
     fn to_gpu_repr(&self) -> Self::Repr {
@@ -60,7 +129,7 @@ impl<P: SWCurveConfig> GpuRepr for Affine<P> {
 impl<P: SWCurveConfig> GpuCurveAffine for Affine<P>
 where
     <Affine<P> as ark_ec::AffineRepr>::ScalarField: GpuField + PrimeFieldRepr,
-    <Affine<P> as ark_ec::AffineRepr>::BaseField: GpuField,
+    <Affine<P> as ark_ec::AffineRepr>::BaseField: GpuField + KernelArg,
 {
     type Base = <Affine<P> as ark_ec::AffineRepr>::BaseField;
     type Curve = <Affine<P> as ark_ec::AffineRepr>::Group;
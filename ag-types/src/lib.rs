@@ -60,17 +60,47 @@ pub trait GpuCurveAffine:
 }
 
 pub trait PrimeFieldRepr: ark_ff::PrimeField {
-    type Repr: ark_ff::BigInteger;
+    type Repr: ark_ff::BigInteger + KernelArg;
     fn to_bigint(&self) -> Self::Repr;
     fn from_bigint(repr: Self::Repr) -> Option<Self>;
 }
 
 pub trait GpuRepr {
-    type Repr;
+    type Repr: KernelArg;
 
     fn to_gpu_repr(&self) -> Self::Repr;
 }
 
+/// A type whose bytes can be copied verbatim into GPU kernel arguments and
+/// device buffers.
+///
+/// It is implemented for the primitive types, arrays of kernel arguments and
+/// the arkworks field, big integer and point types whose layout matches the
+/// generated `FIELD`, `FIELD_repr` and `POINT_jacobian` structs. For own types
+/// use `#[derive(KernelArg)]` from `ag-cuda-workspace-macro`, which requires
+/// `#[repr(C)]` (or `#[repr(transparent)]`) and checks that the struct has no
+/// padding. The derive implements the trait through its re-export
+/// `ag_cuda_proxy::KernelArg`, so the deriving crate must depend on
+/// `ag-cuda-proxy`.
+///
+/// # Safety
+///
+/// The type must be plain old data: it must not contain pointers, references
+/// or heap allocations, and its in-memory layout must be identical to the
+/// type expected by the kernel.
+pub unsafe trait KernelArg: Copy + 'static {
+    /// Fails to compile if the layout of the type differs from the GPU type
+    /// it stands for. `ag-cuda-proxy` evaluates it for every type it passes
+    /// to a kernel, the generic arkworks implementations check their size
+    /// and alignment with it.
+    const ASSERT_LAYOUT: () = ();
+}
+
+/// The first bytes of the stub `ag-build` embeds in place of a kernel that
+/// could not be compiled, followed by the build error. Loading the stub fails
+/// with that error at run time.
+pub const STUB_KERNEL_MAGIC: &[u8] = b"ag-build stub kernel\n";
+
 /// Macro to get a unique name of an item.
 ///
 /// The name is a string that consists of the module path and the type name. All
@@ -109,3 +139,24 @@ fn mr_demo() -> () {
     println!("G2 modulus: {:?}", Fq2::modulus());
     println!("G2 sub field name: {:?}", Fq2::sub_field_name());
 }
+
+#[test]
+fn kernel_arg_layout() {
+    use ark_bls12_381::{Fq, Fq2, Fr, G1Affine, G1Projective};
+    use std::mem::size_of;
+
+    assert_eq!(size_of::<Fr>(), size_of::<<Fr as PrimeFieldRepr>::Repr>());
+    assert_eq!(size_of::<Fq>(), 6 * size_of::<u64>());
+    assert_eq!(size_of::<Fq2>(), 2 * size_of::<Fq>());
+    assert_eq!(size_of::<G1Projective>(), 3 * size_of::<Fq>());
+    assert_eq!(
+        size_of::<<G1Affine as GpuRepr>::Repr>(),
+        2 * size_of::<Fq>()
+    );
+
+    // Evaluated at compile time.
+    let () = <Fr as PrimeFieldRepr>::Repr::ASSERT_LAYOUT;
+    let () = Fq2::ASSERT_LAYOUT;
+    let () = G1Projective::ASSERT_LAYOUT;
+    let () = <G1Affine as GpuRepr>::Repr::ASSERT_LAYOUT;
+}